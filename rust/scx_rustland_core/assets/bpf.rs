@@ -6,6 +6,11 @@
 use crate::bpf_intf;
 use crate::bpf_skel::*;
 
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
 use anyhow::Context;
 use anyhow::Result;
 
@@ -16,10 +21,13 @@ use libbpf_rs::skel::SkelBuilder as _;
 
 use libc::{sched_param, sched_setscheduler};
 
+use log::warn;
+
 use scx_utils::compat;
 use scx_utils::init_libbpf_logging;
 use scx_utils::scx_ops_attach;
 use scx_utils::scx_ops_load;
+use scx_utils::time;
 use scx_utils::uei_exited;
 use scx_utils::uei_report;
 
@@ -38,28 +46,33 @@ pub const RL_CPU_ANY: u64 = bpf_intf::RL_CPU_ANY as u64;
 #[allow(dead_code)]
 pub const RL_PREEMPT_CPU: u64 = bpf_intf::RL_PREEMPT_CPU as u64;
 
-/// High-level Rust abstraction to interact with a generic sched-ext BPF component.
-///
-/// Overview
-/// ========
-///
-/// The main BPF interface is provided by the BpfScheduler() struct. When this object is
-/// initialized it will take care of registering and initializing the BPF component.
+/// Scheduling policy implemented in user-space.
 ///
-/// The scheduler then can use BpfScheduler() instance to receive tasks (in the form of QueuedTask
-/// objects) and dispatch tasks (in the form of DispatchedTask objects), using respectively the
-/// methods dequeue_task() and dispatch_task().
-///
-/// The CPU ownership map can be accessed using the method get_cpu_pid(), this also allows to keep
-/// track of the idle and busy CPUs, with the corresponding PIDs associated to them.
-///
-/// BPF counters and statistics can be accessed using the methods nr_*_mut(), in particular
-/// nr_queued_mut() and nr_scheduled_mut() can be updated to notify the BPF component if the
-/// user-space scheduler has some pending work to do or not.
+/// BpfScheduler::run() repeatedly drains all the tasks queued by the BPF component, passing each
+/// of them to enqueue() (or task_exit() for exiting tasks), then calls dispatch() to let the
+/// policy send tasks back to the BPF component via BpfScheduler::dispatch_task().
 ///
-/// Finally the methods exited() and shutdown_and_report() can be used respectively to test
-/// whether the BPF component exited, and to shutdown and report the exit message.
-/// whether the BPF component exited, and to shutdown and report exit message.
+/// The BPF component is notified about pending work using nr_scheduled(), so the policy doesn't
+/// need to call BpfScheduler::update_tasks() on its own.
+pub trait Scheduler {
+    // A task has been queued by the BPF component and needs to be scheduled.
+    fn enqueue(&mut self, bpf: &mut BpfScheduler, task: QueuedTask);
+
+    // Dispatch the tasks that should run next using bpf.dispatch_task().
+    //
+    // Tasks that are not dispatched can be kept by the policy and dispatched in a later call.
+    fn dispatch(&mut self, bpf: &mut BpfScheduler);
+
+    // Amount of tasks received by the policy that are still waiting to be dispatched.
+    fn nr_scheduled(&self) -> u64;
+
+    // Task @pid exited, any per-task state kept by the policy can be released.
+    fn task_exit(&mut self, _bpf: &mut BpfScheduler, _pid: i32) {}
+
+    // Called about once per second (and one last time before exiting), e.g., to print
+    // statistics or to perform periodic maintenance.
+    fn tick(&mut self, _bpf: &mut BpfScheduler) {}
+}
 
 // Task queued for scheduling from the BPF component (see bpf_intf::queued_task_ctx).
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone)]
@@ -152,11 +165,38 @@ impl EnqueuedMessage {
     }
 }
 
+/// High-level Rust abstraction to interact with a generic sched-ext BPF component.
+///
+/// Overview
+/// ========
+///
+/// The main BPF interface is provided by the BpfScheduler() struct. When this object is
+/// initialized it will take care of registering and initializing the BPF component.
+///
+/// The scheduler then can use BpfScheduler() instance to receive tasks (in the form of QueuedTask
+/// objects) and dispatch tasks (in the form of DispatchedTask objects), using respectively the
+/// methods dequeue_task() and dispatch_task().
+///
+/// The CPU ownership map can be accessed using the method get_cpu_pid(), this also allows to keep
+/// track of the idle and busy CPUs, with the corresponding PIDs associated to them.
+///
+/// BPF counters and statistics can be accessed using the methods nr_*_mut(), in particular
+/// nr_queued_mut() and nr_scheduled_mut() can be updated to notify the BPF component if the
+/// user-space scheduler has some pending work to do or not.
+///
+/// Finally the methods exited() and shutdown_and_report() can be used respectively to test
+/// whether the BPF component exited, and to shutdown and report the exit message.
+///
+/// Alternatively, a scheduling policy can implement the Scheduler trait and let
+/// BpfScheduler::run() drive it: in this case the policy only needs to decide in which order (and
+/// optionally on which CPU and with which time slice) tasks are dispatched.
 pub struct BpfScheduler<'cb> {
     pub skel: BpfSkel<'cb>,                // Low-level BPF connector
     queued: libbpf_rs::RingBuffer<'cb>,    // Ring buffer of queued tasks
     dispatched: libbpf_rs::UserRingBuffer, // User Ring buffer of dispatched tasks
     struct_ops: Option<libbpf_rs::Link>,   // Low-level BPF methods
+    busy_poll_tasks: u64,                  // Min tasks per round to busy-poll (0 = disabled)
+    busy_poll_idle: Duration,              // Keep busy-polling for this long after activity
 }
 
 // Buffer to store a task read from the ring buffer.
@@ -240,7 +280,7 @@ impl<'cb> BpfScheduler<'cb> {
         skel.struct_ops.rustland_mut().exit_dump_len = exit_dump_len;
 
         skel.bss_mut().usersched_pid = std::process::id();
-        skel.rodata_mut().slice_ns = slice_us * 1000;
+        skel.rodata_mut().slice_ns = time::us_to_ns(slice_us);
         skel.rodata_mut().switch_partial = partial;
         skel.rodata_mut().debug = debug;
         skel.rodata_mut().full_user = full_user;
@@ -268,6 +308,8 @@ impl<'cb> BpfScheduler<'cb> {
                 queued,
                 dispatched,
                 struct_ops,
+                busy_poll_tasks: 0,
+                busy_poll_idle: Duration::ZERO,
             }),
             err => Err(anyhow::Error::msg(format!(
                 "sched_setscheduler error: {}",
@@ -296,7 +338,7 @@ impl<'cb> BpfScheduler<'cb> {
     // Override the default scheduler time slice (in us).
     #[allow(dead_code)]
     pub fn set_effective_slice_us(&mut self, slice_us: u64) {
-        self.skel.bss_mut().effective_slice_ns = slice_us * 1000;
+        self.skel.bss_mut().effective_slice_ns = time::us_to_ns(slice_us);
     }
 
     // Get current value of time slice (slice_ns).
@@ -311,6 +353,23 @@ impl<'cb> BpfScheduler<'cb> {
         }
     }
 
+    // Enable the adaptive busy-poll mode of run().
+    //
+    // By default, after each scheduling round, run() yields the CPU and relies on the BPF
+    // component to wake it up when new tasks are queued, which adds wakeup latency to every
+    // scheduling decision.
+    //
+    // With busy-polling enabled, when at least @min_tasks tasks are received in a single round,
+    // run() keeps polling the queued tasks without yielding, until no such round is seen for
+    // @idle_us microseconds. Then it falls back to yielding the CPU.
+    //
+    // Use @min_tasks = 0 to disable busy-polling.
+    #[allow(dead_code)]
+    pub fn set_busy_poll(&mut self, min_tasks: u64, idle_us: u64) {
+        self.busy_poll_tasks = min_tasks;
+        self.busy_poll_idle = Duration::from_micros(idle_us);
+    }
+
     // Counter of queued tasks.
     #[allow(dead_code)]
     pub fn nr_queued_mut(&mut self) -> &mut u64 {
@@ -432,6 +491,74 @@ impl<'cb> BpfScheduler<'cb> {
         Ok(())
     }
 
+    // Drain all the tasks from the queued list and pass them to the scheduling policy, returning
+    // the amount of tasks received.
+    fn drain_queued_tasks<S: Scheduler>(&mut self, sched: &mut S) -> u64 {
+        let mut nr_tasks = 0;
+
+        loop {
+            match self.dequeue_task() {
+                Ok(Some(task)) => {
+                    nr_tasks += 1;
+
+                    // task.cpu < 0 is used to to notify an exiting task.
+                    if task.cpu < 0 {
+                        sched.task_exit(self, task.pid);
+                    } else {
+                        sched.enqueue(self, task);
+                    }
+                }
+                Ok(None) => {
+                    // Reset nr_queued and update nr_scheduled, to notify the dispatcher that
+                    // queued tasks are drained, but there may be still some work left to do in
+                    // the scheduler.
+                    self.update_tasks(Some(0), Some(sched.nr_scheduled()));
+                    break;
+                }
+                Err(err) => {
+                    warn!("Error: {}", err);
+                    break;
+                }
+            }
+        }
+
+        nr_tasks
+    }
+
+    // Run the scheduling policy @sched until @shutdown is set or the BPF component exits, then
+    // shutdown and report the exit message.
+    pub fn run<S: Scheduler>(&mut self, sched: &mut S, shutdown: &AtomicBool) -> Result<()> {
+        let mut prev_tick = Instant::now();
+        let mut prev_busy: Option<Instant> = None;
+
+        while !shutdown.load(Ordering::Relaxed) && !self.exited() {
+            let nr_tasks = self.drain_queued_tasks(sched);
+            sched.dispatch(self);
+
+            // Notify the dispatcher about the tasks that are still waiting in the scheduler, so
+            // that it doesn't keep waking up the scheduler if there's nothing left to do.
+            self.update_tasks(None, Some(sched.nr_scheduled()));
+
+            if prev_tick.elapsed() >= Duration::from_secs(1) {
+                sched.tick(self);
+                prev_tick = Instant::now();
+            }
+
+            // Keep polling while the scheduler is busy (see set_busy_poll()), otherwise yield to
+            // avoid using too much CPU from the scheduler itself.
+            if self.busy_poll_tasks > 0 && nr_tasks >= self.busy_poll_tasks {
+                prev_busy = Some(Instant::now());
+            }
+            match prev_busy {
+                Some(ts) if ts.elapsed() < self.busy_poll_idle => std::hint::spin_loop(),
+                _ => std::thread::yield_now(),
+            }
+        }
+        sched.tick(self);
+
+        self.shutdown_and_report()
+    }
+
     // Read exit code from the BPF part.
     pub fn exited(&mut self) -> bool {
         uei_exited!(&self.skel, uei)
@@ -13,9 +13,11 @@
  * to be dispatched in the proper order.
  *
  * Messages between the BPF component and the user-space scheduler are passed
- * using two BPF_MAP_TYPE_QUEUE maps: @queued for the messages sent by the BPF
- * dispatcher to the user-space scheduler and @dispatched for the messages sent
- * by the user-space scheduler to the BPF dispatcher.
+ * using two ring buffers that are memory-mapped by the user-space scheduler:
+ * @queued (BPF_MAP_TYPE_RINGBUF) for the messages sent by the BPF dispatcher
+ * to the user-space scheduler and @dispatched (BPF_MAP_TYPE_USER_RINGBUF) for
+ * the messages sent by the user-space scheduler to the BPF dispatcher. This
+ * way tasks are exchanged without any per-task syscall.
  *
  * The BPF dispatcher is completely agnostic of the particular scheduling
  * policy implemented in user-space. For this reason developers that are
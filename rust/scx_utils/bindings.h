@@ -1 +1,5 @@
 #include "bpf_h/vmlinux/vmlinux.h"
+#include "bpf_h/scx/dsq_dump_intf.h"
+#include "bpf_h/scx/events_intf.h"
+#include "bpf_h/scx/nice_intf.h"
+#include "bpf_h/scx/user_exit_info.h"
@@ -0,0 +1,466 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Benchmark Mode
+//!
+//! `--bench SECS` runs the scheduler for a fixed duration, records its
+//! stats every interval along with system-wide perf counters and writes a
+//! single JSON file with the whole time-series and per-metric percentiles.
+//! Comparing two schedulers or two flag settings then comes down to running
+//! both with `--bench` and diffing the summaries.
+//!
+//! Flatten `BenchOpts` into the scheduler's options and feed the stats to
+//! the `Bench` from the main loop. Any `Serialize` stats struct works, its
+//! numeric fields become the metrics named by their JSON paths.
+//!
+//! ```ignore
+//! let mut bench = opts.bench.start("scx_foo")?;
+//! while !shutdown.load(Ordering::Relaxed) && !uei_exited!(&skel, uei) {
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     let stats = sched.read_stats()?;
+//!     if let Some(bench) = bench.as_mut() {
+//!         bench.record(&stats)?;
+//!         if bench.done() {
+//!             break;
+//!         }
+//!     }
+//! }
+//! if let Some(bench) = bench {
+//!     bench.finish()?;
+//! }
+//! ```
+//!
+//! Perf counters require CAP_PERFMON or a permissive
+//! kernel.perf_event_paranoid. Counters which can't be opened, e.g.
+//! hardware events in VMs, are left out.
+
+use anyhow::Context;
+use anyhow::Result;
+use clap::Args;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+#[derive(Args, Clone, Debug, Default, PartialEq)]
+pub struct BenchOpts {
+    /// Run in benchmark mode for the specified number of seconds and write
+    /// the stats time-series and summary to --bench-output.
+    #[clap(long, env = "SCX_BENCH")]
+    pub bench: Option<f64>,
+
+    /// Benchmark result file.
+    #[clap(long, env = "SCX_BENCH_OUTPUT", default_value = "scx-bench.json")]
+    pub bench_output: PathBuf,
+
+    /// Free-form label stored in the benchmark result, e.g. the flags
+    /// being compared.
+    #[clap(long, env = "SCX_BENCH_LABEL", default_value = "")]
+    pub bench_label: String,
+}
+
+impl BenchOpts {
+    /// Start benchmarking @sched if --bench was specified.
+    pub fn start(&self, sched: &str) -> Result<Option<Bench>> {
+        match self.bench {
+            Some(secs) => Ok(Some(Bench::new(
+                sched,
+                &self.bench_label,
+                Duration::from_secs_f64(secs.max(0.0)),
+                self.bench_output.clone(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+const PERF_TYPE_HARDWARE: u32 = 0;
+const PERF_TYPE_SOFTWARE: u32 = 1;
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+/// (name, type, config) of the collected perf events.
+const PERF_EVENTS: &[(&str, u32, u64)] = &[
+    ("cycles", PERF_TYPE_HARDWARE, 0),
+    ("instructions", PERF_TYPE_HARDWARE, 1),
+    ("cache_misses", PERF_TYPE_HARDWARE, 3),
+    ("context_switches", PERF_TYPE_SOFTWARE, 3),
+    ("cpu_migrations", PERF_TYPE_SOFTWARE, 4),
+];
+
+/// PERF_ATTR_SIZE_VER0 prefix of struct perf_event_attr. Zeroed fields
+/// select counting mode with the counter enabled on open.
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    ty: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+}
+
+/// System-wide perf counters, one fd per event and CPU.
+#[derive(Debug, Default)]
+pub struct PerfCounters {
+    events: Vec<(&'static str, Vec<OwnedFd>)>,
+}
+
+impl PerfCounters {
+    /// Open the counters on all CPUs. Events which can't be opened on any
+    /// CPU are skipped.
+    pub fn open() -> Self {
+        let nr_cpus = libbpf_rs::num_possible_cpus().unwrap_or(1);
+        let mut events = vec![];
+
+        for (name, ty, config) in PERF_EVENTS.iter() {
+            let attr = PerfEventAttr {
+                ty: *ty,
+                size: std::mem::size_of::<PerfEventAttr>() as u32,
+                config: *config,
+                ..Default::default()
+            };
+            let fds: Vec<OwnedFd> = (0..nr_cpus)
+                .filter_map(|cpu| {
+                    let fd = unsafe {
+                        libc::syscall(
+                            libc::SYS_perf_event_open,
+                            &attr as *const PerfEventAttr,
+                            -1,
+                            cpu as libc::c_int,
+                            -1,
+                            PERF_FLAG_FD_CLOEXEC,
+                        )
+                    };
+                    // Offline CPUs fail with ENODEV.
+                    match fd >= 0 {
+                        true => Some(unsafe { OwnedFd::from_raw_fd(fd as i32) }),
+                        false => None,
+                    }
+                })
+                .collect();
+            if !fds.is_empty() {
+                events.push((*name, fds));
+            }
+        }
+        Self { events }
+    }
+
+    /// The current counts summed over the CPUs.
+    pub fn read(&self) -> BTreeMap<String, u64> {
+        self.events
+            .iter()
+            .map(|(name, fds)| {
+                let sum = fds
+                    .iter()
+                    .map(|fd| {
+                        let mut val = 0u64;
+                        let ret = unsafe {
+                            libc::read(fd.as_raw_fd(), &mut val as *mut u64 as *mut _, 8)
+                        };
+                        if ret == 8 {
+                            val
+                        } else {
+                            0
+                        }
+                    })
+                    .sum();
+                (name.to_string(), sum)
+            })
+            .collect()
+    }
+}
+
+/// Flatten the numeric leaves of @val into @out keyed by their dotted
+/// paths. Arrays are indexed by position.
+fn flatten(prefix: &str, val: &serde_json::Value, out: &mut BTreeMap<String, f64>) {
+    let key = |k: &str| match prefix {
+        "" => k.to_string(),
+        _ => format!("{}.{}", prefix, k),
+    };
+    match val {
+        serde_json::Value::Number(n) => {
+            if let Some(v) = n.as_f64() {
+                out.insert(prefix.to_string(), v);
+            }
+        }
+        serde_json::Value::Bool(b) => {
+            out.insert(prefix.to_string(), *b as u64 as f64);
+        }
+        serde_json::Value::Object(map) => {
+            for (k, v) in map.iter() {
+                flatten(&key(k), v, out);
+            }
+        }
+        serde_json::Value::Array(vals) => {
+            for (i, v) in vals.iter().enumerate() {
+                flatten(&key(&i.to_string()), v, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchSample {
+    /// Seconds since the start of the benchmark.
+    pub t: f64,
+    pub metrics: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BenchSummary {
+    pub min: f64,
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl BenchSummary {
+    /// Nearest-rank percentiles of @vals.
+    pub fn from_vals(vals: &[f64]) -> Self {
+        if vals.is_empty() {
+            return Self::default();
+        }
+        let mut sorted = vals.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let pct = |p: f64| {
+            let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
+        Self {
+            min: sorted[0],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            p50: pct(50.0),
+            p90: pct(90.0),
+            p99: pct(99.0),
+            max: sorted[sorted.len() - 1],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchResult {
+    pub sched: String,
+    pub label: String,
+    pub args: Vec<String>,
+    pub kernel: String,
+    /// Seconds since the epoch when the benchmark started.
+    pub started_at: u64,
+    pub duration: f64,
+    /// Perf counter totals over the whole run.
+    pub perf: BTreeMap<String, u64>,
+    pub summary: BTreeMap<String, BenchSummary>,
+    pub samples: Vec<BenchSample>,
+}
+
+pub struct Bench {
+    result: BenchResult,
+    output: PathBuf,
+    duration: Duration,
+    started: Instant,
+    last: Instant,
+    perf: PerfCounters,
+    perf_start: BTreeMap<String, u64>,
+    perf_last: BTreeMap<String, u64>,
+}
+
+impl Bench {
+    /// Start benchmarking @sched for @duration. The result is written to
+    /// @output by finish(). @output is created right away so that an
+    /// unwritable path fails before the benchmark runs.
+    pub fn new(sched: &str, label: &str, duration: Duration, output: PathBuf) -> Result<Self> {
+        std::fs::File::create(&output)
+            .with_context(|| format!("Failed to create {}", output.display()))?;
+
+        let perf = PerfCounters::open();
+        let perf_start = perf.read();
+        let now = Instant::now();
+
+        Ok(Self {
+            result: BenchResult {
+                sched: sched.to_string(),
+                label: label.to_string(),
+                args: std::env::args().collect(),
+                kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                    .map(|s| s.trim().to_string())
+                    .unwrap_or_default(),
+                started_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+                duration: 0.0,
+                perf: BTreeMap::new(),
+                summary: BTreeMap::new(),
+                samples: vec![],
+            },
+            output,
+            duration,
+            started: now,
+            last: now,
+            perf_last: perf_start.clone(),
+            perf_start,
+            perf,
+        })
+    }
+
+    /// Whether the benchmark duration has elapsed.
+    pub fn done(&self) -> bool {
+        self.started.elapsed() >= self.duration
+    }
+
+    /// Record @stats and the perf counter rates since the last sample.
+    pub fn record<T: Serialize>(&mut self, stats: &T) -> Result<()> {
+        let val = serde_json::to_value(stats).context("Failed to serialize stats")?;
+        let mut metrics = BTreeMap::new();
+        flatten("", &val, &mut metrics);
+        self.record_metrics(metrics);
+        Ok(())
+    }
+
+    fn record_metrics(&mut self, mut metrics: BTreeMap<String, f64>) {
+        let now = Instant::now();
+        let dur = now.duration_since(self.last).as_secs_f64();
+        let perf = self.perf.read();
+
+        if dur > 0.0 {
+            for (name, val) in perf.iter() {
+                let delta = val.wrapping_sub(*self.perf_last.get(name).unwrap_or(&0));
+                metrics.insert(format!("perf.{}_per_sec", name), delta as f64 / dur);
+            }
+        }
+        if let (Some(insns), Some(cycles)) = (
+            metrics.get("perf.instructions_per_sec"),
+            metrics.get("perf.cycles_per_sec"),
+        ) {
+            if *cycles > 0.0 {
+                metrics.insert("perf.ipc".into(), insns / cycles);
+            }
+        }
+
+        self.result.samples.push(BenchSample {
+            t: now.duration_since(self.started).as_secs_f64(),
+            metrics,
+        });
+        self.last = now;
+        self.perf_last = perf;
+    }
+
+    /// Compute the summaries and write the result file.
+    pub fn finish(mut self) -> Result<BenchResult> {
+        self.result.duration = self.started.elapsed().as_secs_f64();
+        self.result.perf = self
+            .perf_last
+            .iter()
+            .map(|(name, val)| {
+                let start = self.perf_start.get(name).unwrap_or(&0);
+                (name.clone(), val.wrapping_sub(*start))
+            })
+            .collect();
+
+        let mut series: BTreeMap<&String, Vec<f64>> = BTreeMap::new();
+        for sample in self.result.samples.iter() {
+            for (name, val) in sample.metrics.iter() {
+                series.entry(name).or_default().push(*val);
+            }
+        }
+        let summary = series
+            .into_iter()
+            .map(|(name, vals)| (name.clone(), BenchSummary::from_vals(&vals)))
+            .collect();
+        self.result.summary = summary;
+
+        let json = serde_json::to_string_pretty(&self.result)?;
+        std::fs::write(&self.output, json)
+            .with_context(|| format!("Failed to write {}", self.output.display()))?;
+        Ok(self.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Stats {
+        busy: f64,
+        nr_layers: Vec<u32>,
+        enabled: bool,
+        name: String,
+    }
+
+    fn stats(busy: f64) -> Stats {
+        Stats {
+            busy,
+            nr_layers: vec![1, 2],
+            enabled: true,
+            name: "foo".into(),
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = BenchSummary::from_vals(&[5.0, 1.0, 4.0, 2.0, 3.0]);
+        assert_eq!((summary.min, summary.p50, summary.max), (1.0, 3.0, 5.0));
+        assert_eq!((summary.mean, summary.p90, summary.p99), (3.0, 5.0, 5.0));
+        assert_eq!(BenchSummary::from_vals(&[]), BenchSummary::default());
+    }
+
+    #[test]
+    fn test_flatten() {
+        let val = serde_json::to_value(stats(10.0)).unwrap();
+        let mut metrics = BTreeMap::new();
+        flatten("", &val, &mut metrics);
+        assert_eq!(metrics["busy"], 10.0);
+        assert_eq!(metrics["nr_layers.1"], 2.0);
+        assert_eq!(metrics["enabled"], 1.0);
+        assert!(!metrics.contains_key("name"));
+    }
+
+    #[test]
+    fn test_start_without_bench() {
+        assert!(BenchOpts::default().start("scx_test").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_unwritable_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("missing/bench.json");
+        assert!(Bench::new("scx_test", "", Duration::ZERO, output).is_err());
+    }
+
+    #[test]
+    fn test_finish() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("bench.json");
+        let mut bench = Bench::new("scx_test", "A", Duration::ZERO, output.clone()).unwrap();
+        assert!(bench.done());
+        for busy in [10.0, 20.0, 30.0] {
+            bench.record(&stats(busy)).unwrap();
+        }
+
+        let result = bench.finish().unwrap();
+        assert_eq!(result.label, "A");
+        assert_eq!(result.samples.len(), 3);
+        assert_eq!(result.summary["busy"].p50, 20.0);
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(written["sched"], "scx_test");
+        assert_eq!(written["samples"].as_array().unwrap().len(), 3);
+    }
+}
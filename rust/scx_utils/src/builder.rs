@@ -8,6 +8,8 @@ use std::fs::File;
 use std::path::PathBuf;
 
 const BPF_H: &str = "bpf_h";
+const WEIGHT_H: &str = "bpf_h/scx/weight.bpf.h";
+const WEIGHT_TABLE: &str = "scx_prio_to_weight[SCX_NICE_WIDTH] = {";
 
 pub struct Builder;
 
@@ -46,6 +48,13 @@ impl Builder {
             .header("bindings.h")
             .allowlist_type("scx_exit_kind")
             .allowlist_type("scx_consts")
+            .allowlist_type("scx_dsq_dump_consts")
+            .allowlist_type("scx_dsq_dump_args")
+            .allowlist_type("scx_dsq_dump_rec")
+            .allowlist_type("scx_event_kind")
+            .allowlist_type("scx_event")
+            .allowlist_type("scx_nice_consts")
+            .allowlist_type("uei_sizes")
             .parse_callbacks(Box::new(bindgen::CargoCallbacks))
             .generate()
             .expect("Unable to generate bindings");
@@ -55,8 +64,46 @@ impl Builder {
             .expect("Couldn't write bindings");
     }
 
+    // Generate the Rust copy of scx_prio_to_weight[] from the BPF header so
+    // that the two can't diverge. See weight.rs.
+    fn gen_weight_table(&self) {
+        let out_dir = env::var("OUT_DIR").unwrap();
+        let header = std::fs::read_to_string(WEIGHT_H).unwrap();
+
+        let start = header
+            .find(WEIGHT_TABLE)
+            .expect("scx_prio_to_weight[] not found");
+        let body = &header[start + WEIGHT_TABLE.len()..];
+        let body = &body[..body
+            .find("};")
+            .expect("scx_prio_to_weight[] not terminated")];
+
+        let mut vals = vec![];
+        for line in body.lines() {
+            let line = match (line.find("/*"), line.find("*/")) {
+                (Some(s), Some(e)) => format!("{}{}", &line[..s], &line[e + 2..]),
+                _ => line.to_string(),
+            };
+            for tok in line.split(',').map(|t| t.trim()).filter(|t| !t.is_empty()) {
+                vals.push(
+                    tok.parse::<u32>()
+                        .expect("invalid scx_prio_to_weight[] entry"),
+                );
+            }
+        }
+        assert_eq!(vals.len(), 40, "scx_prio_to_weight[] must have 40 entries");
+
+        let table = format!(
+            "pub const PRIO_TO_WEIGHT: [u32; NICE_WIDTH] = {:?};\n",
+            vals
+        );
+        std::fs::write(PathBuf::from(&out_dir).join("weight_table.rs"), table)
+            .expect("Couldn't write weight table");
+    }
+
     pub fn build(self) {
         self.gen_bpf_h();
         self.gen_bindings();
+        self.gen_weight_table();
     }
 }
@@ -0,0 +1,114 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Cgroup Utilities
+//!
+//! Helpers to locate cgroups on the cgroup2 (unified) hierarchy. Paths
+//! returned by these helpers are relative to the cgroup2 mount point and
+//! don't have leading or trailing '/', e.g. "system.slice/sshd.service",
+//! which matches how BPF schedulers usually format cgroup paths from
+//! `cgroup->ancestors[]`.
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::path::PathBuf;
+
+/// Find the mount point of the cgroup2 hierarchy.
+pub fn cgroup2_root() -> Result<PathBuf> {
+    let mounts =
+        std::fs::read_to_string("/proc/self/mounts").context("Failed to read /proc/self/mounts")?;
+
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        let (_dev, mnt, fstype) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(dev), Some(mnt), Some(fstype)) => (dev, mnt, fstype),
+            _ => continue,
+        };
+        if fstype == "cgroup2" {
+            return Ok(PathBuf::from(mnt));
+        }
+    }
+
+    Err(anyhow!("cgroup2 hierarchy is not mounted"))
+}
+
+/// Test whether @unit looks like a systemd unit which owns a cgroup.
+pub fn is_systemd_cgroup_unit(unit: &str) -> bool {
+    [".service", ".scope", ".slice"]
+        .iter()
+        .any(|suffix| unit.len() > suffix.len() && unit.ends_with(suffix))
+}
+
+/// Resolve the systemd @unit to the cgroup paths it currently occupies. If
+/// the unit isn't running, an empty vector is returned.
+///
+/// Only the system manager's tree is searched. Units of the system manager
+/// always live directly in a slice, so only slices are descended into. This
+/// skips the user managers under user@UID.service and other delegated
+/// subtrees such as containers which may have units of the same name.
+pub fn systemd_unit_cgroups(unit: &str) -> Result<Vec<String>> {
+    if !is_systemd_cgroup_unit(unit) {
+        bail!("{:?} is not a systemd service, scope or slice", unit);
+    }
+
+    let root = cgroup2_root()?;
+    let mut found = vec![];
+    let mut walker = walkdir::WalkDir::new(&root).min_depth(1).into_iter();
+
+    while let Some(ent) = walker.next() {
+        // Cgroups may come and go while we're walking. Ignore errors.
+        let ent = match ent {
+            Ok(ent) => ent,
+            Err(_) => continue,
+        };
+        if !ent.file_type().is_dir() {
+            continue;
+        }
+        let name = ent.file_name().to_str().unwrap_or("");
+        if name == unit {
+            if let Ok(rel) = ent.path().strip_prefix(&root) {
+                found.push(rel.to_string_lossy().into_owned());
+            }
+        }
+        if !name.ends_with(".slice") {
+            walker.skip_current_dir();
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// List the immediate child cgroups of @parent which is relative to the
+/// cgroup2 root. The returned paths are relative to the cgroup2 root and
+/// sorted. If @parent doesn't exist, an empty vector is returned.
+pub fn child_cgroups(parent: &str) -> Result<Vec<String>> {
+    let parent = parent.trim_matches('/');
+    let dir = cgroup2_root()?.join(parent);
+
+    let ents = match std::fs::read_dir(&dir) {
+        Ok(ents) => ents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", &dir)),
+    };
+
+    let mut children = vec![];
+    for ent in ents.filter_map(|ent| ent.ok()) {
+        if !ent.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
+            continue;
+        }
+        let name = ent.file_name().to_string_lossy().into_owned();
+        if parent.is_empty() {
+            children.push(name);
+        } else {
+            children.push(format!("{}/{}", parent, name));
+        }
+    }
+
+    children.sort();
+    Ok(children)
+}
@@ -0,0 +1,129 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Common Scheduler Options
+//!
+//! The options every scheduler has in some form, with the same names, help
+//! texts and environment variable overrides everywhere, so that users and
+//! tools such as scx_loader can rely on them. Flatten `CommonOpts` into the
+//! scheduler's clap options:
+//!
+//! ```ignore
+//! #[derive(Debug, Parser)]
+//! struct Opts {
+//!     #[clap(flatten)]
+//!     common: CommonOpts,
+//!
+//!     /// Scheduler specific option.
+//!     #[clap(long)]
+//!     partial: bool,
+//! }
+//!
+//! let opts = Opts::parse();
+//! if opts.common.print_version("scx_foo", env!("CARGO_PKG_VERSION")) {
+//!     return Ok(());
+//! }
+//! let slice_ns = opts.common.slice_us_or(20000) * 1000;
+//! ```
+//!
+//! Each option can also be set through the environment variable listed in
+//! its help, e.g. `SCX_SLICE_US=5000`. An option given on the command line
+//! takes precedence over the environment.
+
+use clap::ArgAction;
+use clap::Args;
+use std::time::Duration;
+
+#[derive(Args, Clone, Debug, Default, PartialEq)]
+pub struct CommonOpts {
+    /// Scheduling slice duration in microseconds. The default depends on
+    /// the scheduler.
+    #[clap(short = 's', long, env = "SCX_SLICE_US")]
+    pub slice_us: Option<u64>,
+
+    /// Enable verbose output including libbpf details. Specify multiple
+    /// times to increase verbosity.
+    #[clap(short = 'v', long, env = "SCX_VERBOSE", action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Enable stats monitoring with the specified interval in seconds.
+    #[clap(long, env = "SCX_STATS")]
+    pub stats: Option<f64>,
+
+    /// Run in stats monitoring mode with the specified interval in seconds.
+    /// The scheduler is not launched.
+    #[clap(long, env = "SCX_MONITOR")]
+    pub monitor: Option<f64>,
+
+    /// Exit debug dump buffer length. 0 indicates default.
+    #[clap(long, env = "SCX_EXIT_DUMP_LEN", default_value = "0")]
+    pub exit_dump_len: u32,
+
+    /// Print scheduler version and exit.
+    #[clap(short = 'V', long, action = ArgAction::SetTrue)]
+    pub version: bool,
+}
+
+impl CommonOpts {
+    /// --slice-us or @dfl if not specified.
+    pub fn slice_us_or(&self, dfl: u64) -> u64 {
+        self.slice_us.unwrap_or(dfl)
+    }
+
+    /// The --stats interval or the --monitor interval in monitoring mode.
+    /// None if stats aren't enabled.
+    pub fn stats_interval(&self) -> Option<Duration> {
+        self.monitor
+            .or(self.stats)
+            .map(|secs| Duration::from_secs_f64(secs.max(0.0)))
+    }
+
+    /// Whether only to monitor the stats of an already running scheduler.
+    pub fn monitor_only(&self) -> bool {
+        self.monitor.is_some()
+    }
+
+    /// If --version was specified, print "@name @version" and return true.
+    pub fn print_version(&self, name: &str, version: &str) -> bool {
+        if self.version {
+            println!("{} {}", name, version);
+        }
+        self.version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Opts {
+        #[clap(flatten)]
+        common: CommonOpts,
+
+        #[clap(long)]
+        partial: bool,
+    }
+
+    #[test]
+    fn test_common_opts() {
+        let opts = Opts::parse_from(["scx_test", "-vv", "--stats", "0.5", "--partial"]);
+        assert_eq!(opts.common.verbose, 2);
+        assert_eq!(opts.common.slice_us_or(20000), 20000);
+        assert_eq!(
+            opts.common.stats_interval(),
+            Some(Duration::from_millis(500))
+        );
+        assert!(!opts.common.monitor_only());
+        assert!(opts.partial);
+
+        let opts = Opts::parse_from(["scx_test", "-s", "5000", "--monitor", "2", "-V"]);
+        assert_eq!(opts.common.slice_us_or(20000), 5000);
+        assert_eq!(opts.common.stats_interval(), Some(Duration::from_secs(2)));
+        assert!(opts.common.monitor_only());
+        assert!(opts.common.version);
+    }
+}
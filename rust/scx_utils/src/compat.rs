@@ -135,6 +135,16 @@ pub fn kfunc_exists(kfunc: &str) -> Result<bool> {
     Ok(tid >= 0)
 }
 
+/// Test whether the tracepoint @tp can be attached with tp_btf, i.e. whether
+/// vmlinux BTF has its btf_trace_@tp typedef.
+pub fn tracepoint_exists(tp: &str) -> Result<bool> {
+    let btf: &btf = *VMLINUX_BTF;
+
+    let typedef_name = CString::new(format!("btf_trace_{}", tp)).unwrap();
+    let tid = unsafe { btf__find_by_name_kind(btf, typedef_name.as_ptr(), BTF_KIND_TYPEDEF) };
+    Ok(tid >= 0)
+}
+
 pub fn is_sched_ext_enabled() -> io::Result<bool> {
     let content = std::fs::read_to_string("/sys/kernel/sched_ext/state")?;
 
@@ -211,4 +221,10 @@ mod tests {
         assert!(super::kfunc_exists("scx_bpf_consume").unwrap());
         assert!(!super::kfunc_exists("NO_SUCH_KFUNC").unwrap());
     }
+
+    #[test]
+    fn test_tracepoint_exists() {
+        assert!(super::tracepoint_exists("sched_switch").unwrap());
+        assert!(!super::tracepoint_exists("NO_SUCH_TRACEPOINT").unwrap());
+    }
 }
@@ -0,0 +1,238 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Config Files
+//!
+//! File based configuration for schedulers which layers a JSON or TOML
+//! file over the scheduler's command line options. The option struct is
+//! usually the scheduler's clap `Opts` which must also be serde
+//! (de)serializable. The file is a flat table keyed by the option names,
+//! e.g.
+//!
+//! ```json
+//! { "slice_us_underutil": 10000, "fifo_sched": true }
+//! ```
+//!
+//! Options are resolved in the following order with later ones winning:
+//!
+//! 1. The default values.
+//! 2. The values in the config file.
+//! 3. The options explicitly specified on the command line, i.e. the ones
+//!    whose `ArgMatches::value_source()` is `ValueSource::CommandLine`.
+//!
+//! The option names in the file are matched against the clap argument IDs,
+//! so the serde and clap names of the fields must agree, which they do
+//! unless either is renamed.
+//!
+//! `ConfigFile::reload()` re-reads the file if it has been modified so
+//! that the scheduler can poll it from its main loop and restart or retune
+//! itself with the new options.
+//!
+//! ```ignore
+//! let matches = Opts::command().get_matches();
+//! let opts = Opts::from_arg_matches(&matches)?;
+//! let mut config = ConfigFile::new(path, opts, &matches)?;
+//! loop {
+//!     if config.reload()? {
+//!         apply(config.get());
+//!     }
+//!     ...
+//! }
+//! ```
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Read the config file at @path into a JSON value. Files ending with
+/// `.toml` are parsed as TOML, everything else as JSON.
+pub fn read_file(path: &Path) -> Result<Value> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {:?}", path))?;
+
+    let val: Value = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&text).with_context(|| format!("Failed to parse TOML {:?}", path))?
+    } else {
+        serde_json::from_str(&text).with_context(|| format!("Failed to parse JSON {:?}", path))?
+    };
+
+    if !val.is_object() {
+        bail!("Config file {:?} is not a table", path);
+    }
+    Ok(val)
+}
+
+/// Layer @file over @cli, the options parsed from @matches, except for the
+/// options which were specified on the command line. See the module
+/// documentation for details.
+pub fn layer<T: Serialize + DeserializeOwned>(
+    file: &Value,
+    cli: &T,
+    matches: &ArgMatches,
+) -> Result<T> {
+    let mut merged = serde_json::to_value(cli)?;
+    let merged_map = merged
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Options don't serialize into a table"))?;
+    let file_map = file
+        .as_object()
+        .ok_or_else(|| anyhow!("Config is not a table"))?;
+
+    for (key, val) in file_map.iter() {
+        let cur = match merged_map.get_mut(key) {
+            Some(cur) => cur,
+            None => bail!("Unknown option {:?}", key),
+        };
+        let on_cli = matches.ids().any(|id| {
+            id.as_str() == key && matches.value_source(key) == Some(ValueSource::CommandLine)
+        });
+        if !on_cli {
+            *cur = val.clone();
+        }
+    }
+
+    serde_json::from_value(merged).context("Invalid config")
+}
+
+/// A config file layered over the command line options.
+pub struct ConfigFile<T> {
+    path: PathBuf,
+    cli: T,
+    matches: ArgMatches,
+    cur: T,
+    mtime: Option<SystemTime>,
+    generation: u64,
+}
+
+impl<T: Serialize + DeserializeOwned> ConfigFile<T> {
+    /// Load @path layered under @cli which was parsed from @matches.
+    pub fn new<P: AsRef<Path>>(path: P, cli: T, matches: &ArgMatches) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mtime = Self::read_mtime(&path);
+        let cur = layer(&read_file(&path)?, &cli, matches)
+            .with_context(|| format!("Failed to load config file {:?}", &path))?;
+
+        Ok(Self {
+            path,
+            cli,
+            matches: matches.clone(),
+            cur,
+            mtime,
+            generation: 0,
+        })
+    }
+
+    fn read_mtime(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|md| md.modified()).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The current options.
+    pub fn get(&self) -> &T {
+        &self.cur
+    }
+
+    /// Incremented each time the options are updated by reload().
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Re-read the config file if it has been modified since the last
+    /// load. Returns whether the options were updated. If the new file is
+    /// invalid, the current options are kept and the error is returned
+    /// once. It's not retried until the file is modified again.
+    pub fn reload(&mut self) -> Result<bool> {
+        let mtime = Self::read_mtime(&self.path);
+        if mtime == self.mtime {
+            return Ok(false);
+        }
+        self.mtime = mtime;
+
+        self.cur = read_file(&self.path)
+            .and_then(|file| layer(&file, &self.cli, &self.matches))
+            .with_context(|| format!("Failed to reload config file {:?}", &self.path))?;
+        self.generation += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use clap::FromArgMatches;
+    use clap::Parser;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Parser, Serialize, Deserialize)]
+    struct Opts {
+        #[clap(long, default_value = "20000")]
+        slice_us: u64,
+        #[clap(long)]
+        fifo: bool,
+        #[clap(long)]
+        cpumasks: Vec<String>,
+    }
+
+    fn parse(args: &[&str]) -> (Opts, ArgMatches) {
+        let matches = Opts::command()
+            .try_get_matches_from(std::iter::once("test").chain(args.iter().copied()))
+            .unwrap();
+        (Opts::from_arg_matches(&matches).unwrap(), matches)
+    }
+
+    #[test]
+    fn test_layer_file_overrides_defaults() {
+        let (cli, matches) = parse(&[]);
+        let file = serde_json::json!({ "slice_us": 10000, "fifo": true, "cpumasks": ["0xf"] });
+        let opts = layer(&file, &cli, &matches).unwrap();
+        assert_eq!(opts.slice_us, 10000);
+        assert!(opts.fifo);
+        assert_eq!(opts.cpumasks, vec!["0xf".to_string()]);
+    }
+
+    #[test]
+    fn test_layer_cli_overrides_file() {
+        let (cli, matches) = parse(&["--slice-us", "5000"]);
+        let file = serde_json::json!({ "slice_us": 10000, "fifo": true });
+        let opts = layer(&file, &cli, &matches).unwrap();
+        assert_eq!(opts.slice_us, 5000);
+        assert!(opts.fifo);
+    }
+
+    #[test]
+    fn test_layer_cli_equal_to_default() {
+        // Explicitly specifying the default value still wins over the file.
+        let (cli, matches) = parse(&["--slice-us", "20000"]);
+        let file = serde_json::json!({ "slice_us": 10000 });
+        assert_eq!(layer(&file, &cli, &matches).unwrap().slice_us, 20000);
+    }
+
+    #[test]
+    fn test_layer_toml() {
+        let (cli, matches) = parse(&[]);
+        let file = toml::from_str::<Value>("fifo = true").unwrap();
+        assert!(layer(&file, &cli, &matches).unwrap().fifo);
+    }
+
+    #[test]
+    fn test_layer_invalid() {
+        let (cli, matches) = parse(&[]);
+        assert!(layer(&serde_json::json!({ "nope": 1 }), &cli, &matches).is_err());
+        assert!(layer(&serde_json::json!({ "fifo": 1 }), &cli, &matches).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # CPU Frequency Residency and Boost Statistics
+//!
+//! Power-aware placement is only as good as the frequencies the CPUs
+//! actually end up running at. `CpuFreqSnapshot` reads the per-CPU cpufreq
+//! state from sysfs. The delta between two snapshots tells how long each CPU
+//! spent at each frequency and how much of the time it was boosting above
+//! its base frequency.
+//!
+//! ```ignore
+//! let mut prev = CpuFreqSnapshot::read()?;
+//! loop {
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     let cur = CpuFreqSnapshot::read()?;
+//!     info!("{}", cur.delta(&prev));
+//!     prev = cur;
+//! }
+//! ```
+//!
+//! Residency comes from cpufreq/stats/time_in_state when the kernel has
+//! CONFIG_CPU_FREQ_STAT and the driver supports it. amd_pstate and
+//! intel_pstate in active mode don't maintain the table, in which case each
+//! interval is attributed to the frequency sampled from scaling_cur_freq at
+//! the end of the interval and the residency is only as accurate as the
+//! sampling rate.
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::time::Instant;
+
+const CPU_ROOT: &str = "/sys/devices/system/cpu";
+
+/// The cpufreq state of a CPU. Frequencies are in kHz.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuFreq {
+    pub cpu: usize,
+    pub cur_freq: usize,
+    pub max_freq: usize,
+    /// The highest frequency sustainable without boosting. None if the
+    /// driver doesn't report it.
+    pub base_freq: Option<usize>,
+    /// Time in msecs spent at each frequency. Cumulative in snapshots and
+    /// per-interval in deltas. Empty if not available.
+    pub time_in_state: BTreeMap<usize, u64>,
+    pub total_trans: u64,
+}
+
+fn read_num<T: std::str::FromStr>(path: &Path) -> Option<T> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+impl CpuFreq {
+    fn read(cpu: usize, cpu_path: &Path) -> Option<Self> {
+        let freq_path = cpu_path.join("cpufreq");
+        let cur_freq = read_num(&freq_path.join("scaling_cur_freq"))?;
+        let max_freq = read_num(&freq_path.join("cpuinfo_max_freq")).unwrap_or(0);
+
+        // intel_pstate reports base_frequency directly. Otherwise, use the
+        // ACPI CPPC nominal frequency which is in MHz.
+        let base_freq = read_num(&freq_path.join("base_frequency")).or_else(|| {
+            read_num::<usize>(&cpu_path.join("acpi_cppc/nominal_freq")).map(|mhz| mhz * 1000)
+        });
+
+        // time_in_state is in USER_HZ units of 10ms.
+        let time_in_state = std::fs::read_to_string(freq_path.join("stats/time_in_state"))
+            .map(|s| {
+                s.lines()
+                    .filter_map(|line| {
+                        let mut fields = line.split_whitespace();
+                        let freq = fields.next()?.parse().ok()?;
+                        let time = fields.next()?.parse::<u64>().ok()?;
+                        Some((freq, time * 10))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let total_trans = read_num(&freq_path.join("stats/total_trans")).unwrap_or(0);
+
+        Some(Self {
+            cpu,
+            cur_freq,
+            max_freq,
+            base_freq,
+            time_in_state,
+            total_trans,
+        })
+    }
+
+    fn total_ms(&self) -> u64 {
+        self.time_in_state.values().sum()
+    }
+
+    /// Fraction of time spent at each frequency.
+    pub fn residency(&self) -> BTreeMap<usize, f64> {
+        let total = self.total_ms().max(1) as f64;
+        self.time_in_state
+            .iter()
+            .map(|(freq, ms)| (*freq, *ms as f64 / total))
+            .collect()
+    }
+
+    /// Fraction of time spent above the base frequency. None if the base
+    /// frequency isn't known.
+    pub fn boost_residency(&self) -> Option<f64> {
+        let base = self.base_freq?;
+        let boosted: u64 = self.time_in_state.range(base + 1..).map(|(_, ms)| ms).sum();
+        Some(boosted as f64 / self.total_ms().max(1) as f64)
+    }
+
+    /// Time-weighted average frequency. Falls back to the current
+    /// frequency if there is no residency information.
+    pub fn avg_freq(&self) -> usize {
+        match self.total_ms() {
+            0 => self.cur_freq,
+            total => {
+                let sum: u64 = self
+                    .time_in_state
+                    .iter()
+                    .map(|(freq, ms)| *freq as u64 * ms)
+                    .sum();
+                (sum / total) as usize
+            }
+        }
+    }
+}
+
+/// cpufreq state of all CPUs at a point in time.
+#[derive(Clone, Debug)]
+pub struct CpuFreqSnapshot {
+    pub at: Instant,
+    pub cpus: BTreeMap<usize, CpuFreq>,
+    /// Whether boosting is enabled globally. None if unknown.
+    pub boost: Option<bool>,
+    /// Whether the per-CPU residency tables were available.
+    pub has_stats: bool,
+}
+
+impl CpuFreqSnapshot {
+    pub fn read() -> Result<Self> {
+        Self::read_from(Path::new(CPU_ROOT))
+    }
+
+    /// Read from @root which has the layout of /sys/devices/system/cpu.
+    /// CPUs without cpufreq are skipped.
+    pub fn read_from(root: &Path) -> Result<Self> {
+        let mut cpus = BTreeMap::new();
+        for entry in std::fs::read_dir(root)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let cpu = match name.to_str().and_then(|n| n.strip_prefix("cpu")) {
+                Some(id) => match id.parse::<usize>() {
+                    Ok(cpu) => cpu,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+            if let Some(freq) = CpuFreq::read(cpu, &entry.path()) {
+                cpus.insert(cpu, freq);
+            }
+        }
+
+        // acpi-cpufreq and amd_pstate expose cpufreq/boost while
+        // intel_pstate has the inverted intel_pstate/no_turbo.
+        let boost = read_num::<u32>(&root.join("cpufreq/boost"))
+            .map(|v| v != 0)
+            .or_else(|| read_num::<u32>(&root.join("intel_pstate/no_turbo")).map(|v| v == 0));
+
+        let has_stats = !cpus.is_empty() && cpus.values().all(|c| !c.time_in_state.is_empty());
+
+        Ok(Self {
+            at: Instant::now(),
+            cpus,
+            boost,
+            has_stats,
+        })
+    }
+
+    /// The residency and transitions between @prev and @self. Without the
+    /// residency tables, the whole interval is attributed to each CPU's
+    /// current frequency.
+    pub fn delta(&self, prev: &Self) -> Self {
+        let dur_ms = self.at.duration_since(prev.at).as_millis() as u64;
+        let has_stats = self.has_stats && prev.has_stats;
+
+        let cpus = self
+            .cpus
+            .iter()
+            .map(|(cpu, cur)| {
+                let mut delta = cur.clone();
+                match prev.cpus.get(cpu).filter(|_| has_stats) {
+                    Some(prev) => {
+                        for (freq, ms) in delta.time_in_state.iter_mut() {
+                            *ms -= prev.time_in_state.get(freq).copied().unwrap_or(0).min(*ms);
+                        }
+                        delta.total_trans -= prev.total_trans.min(delta.total_trans);
+                    }
+                    None => {
+                        delta.time_in_state = [(cur.cur_freq, dur_ms)].into();
+                        delta.total_trans = 0;
+                    }
+                }
+                (*cpu, delta)
+            })
+            .collect();
+
+        Self {
+            at: self.at,
+            cpus,
+            boost: self.boost,
+            has_stats,
+        }
+    }
+}
+
+impl fmt::Display for CpuFreqSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let boost = match self.boost {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "unknown",
+        };
+        let source = match self.has_stats {
+            true => "time_in_state",
+            false => "sampled",
+        };
+        writeln!(f, "cpufreq: boost={} residency={}", boost, source)?;
+
+        for freq in self.cpus.values() {
+            write!(
+                f,
+                "  cpu{:<4} cur={:>5}MHz avg={:>5}MHz max={:>5}MHz trans={:<6}",
+                freq.cpu,
+                freq.cur_freq / 1000,
+                freq.avg_freq() / 1000,
+                freq.max_freq / 1000,
+                freq.total_trans
+            )?;
+            if let Some(boosted) = freq.boost_residency() {
+                write!(f, " boosted={:5.1}%", boosted * 100.0)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write(path: PathBuf, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    /// A sysfs cpu directory with cpu0 at 3GHz, boosting above 2GHz, and
+    /// cpu1 without cpufreq.
+    fn sysfs() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let freq = root.path().join("cpu0/cpufreq");
+        write(freq.join("scaling_cur_freq"), "3000000\n");
+        write(freq.join("cpuinfo_max_freq"), "4000000\n");
+        write(freq.join("base_frequency"), "2000000\n");
+        write(
+            freq.join("stats/time_in_state"),
+            "1000000 10\n2000000 10\n3000000 0\n",
+        );
+        write(freq.join("stats/total_trans"), "5\n");
+        std::fs::create_dir_all(root.path().join("cpu1")).unwrap();
+        write(root.path().join("cpufreq/boost"), "1\n");
+        root
+    }
+
+    #[test]
+    fn test_read() {
+        let root = sysfs();
+        let snap = CpuFreqSnapshot::read_from(root.path()).unwrap();
+        assert!(snap.has_stats);
+        assert_eq!(snap.boost, Some(true));
+        assert_eq!(snap.cpus.keys().copied().collect::<Vec<_>>(), vec![0]);
+
+        let cpu0 = &snap.cpus[&0];
+        assert_eq!((cpu0.cur_freq, cpu0.max_freq), (3000000, 4000000));
+        assert_eq!(cpu0.base_freq, Some(2000000));
+        // time_in_state is in 10ms units
+        assert_eq!(cpu0.time_in_state[&1000000], 100);
+        assert_eq!(cpu0.total_trans, 5);
+    }
+
+    #[test]
+    fn test_read_cppc_and_no_turbo() {
+        let root = sysfs();
+        std::fs::remove_file(root.path().join("cpu0/cpufreq/base_frequency")).unwrap();
+        write(root.path().join("cpu0/acpi_cppc/nominal_freq"), "2500\n");
+        std::fs::remove_dir_all(root.path().join("cpufreq")).unwrap();
+        write(root.path().join("intel_pstate/no_turbo"), "1\n");
+
+        let snap = CpuFreqSnapshot::read_from(root.path()).unwrap();
+        assert_eq!(snap.cpus[&0].base_freq, Some(2500000));
+        assert_eq!(snap.boost, Some(false));
+    }
+
+    #[test]
+    fn test_delta_residency() {
+        let root = sysfs();
+        let freq = root.path().join("cpu0/cpufreq");
+        let prev = CpuFreqSnapshot::read_from(root.path()).unwrap();
+
+        write(
+            freq.join("stats/time_in_state"),
+            "1000000 10\n2000000 20\n3000000 30\n",
+        );
+        write(freq.join("stats/total_trans"), "9\n");
+        let cur = CpuFreqSnapshot::read_from(root.path()).unwrap();
+
+        let delta = cur.delta(&prev);
+        let cpu0 = &delta.cpus[&0];
+        assert_eq!(cpu0.total_trans, 4);
+        assert_eq!(cpu0.residency()[&1000000], 0.0);
+        assert_eq!(cpu0.boost_residency(), Some(0.75));
+        assert_eq!(cpu0.avg_freq(), 2750000);
+    }
+
+    #[test]
+    fn test_delta_sampled() {
+        let root = sysfs();
+        std::fs::remove_dir_all(root.path().join("cpu0/cpufreq/stats")).unwrap();
+        let prev = CpuFreqSnapshot::read_from(root.path()).unwrap();
+        assert!(!prev.has_stats);
+
+        let mut cur = CpuFreqSnapshot::read_from(root.path()).unwrap();
+        cur.at = prev.at + std::time::Duration::from_millis(500);
+
+        // Without time_in_state, the interval is attributed to the current
+        // frequency.
+        let cpu0 = &cur.delta(&prev).cpus[&0];
+        assert_eq!(cpu0.time_in_state, [(3000000, 500)].into());
+        assert_eq!(cpu0.avg_freq(), 3000000);
+        assert_eq!(cpu0.boost_residency(), Some(1.0));
+    }
+}
@@ -203,6 +203,14 @@ impl Cpumask {
         new
     }
 
+    /// Create a Cpumask with the CPUs of the current Cpumask which aren't in
+    /// another.
+    pub fn and_not(&self, other: &Cpumask) -> Cpumask {
+        let mut new = self.clone();
+        new.mask &= !other.mask.clone();
+        new
+    }
+
     /// Create a Cpumask that is the XOR of the current Cpumask and another.
     pub fn xor(&self, other: &Cpumask) -> Cpumask {
         let mut new = self.clone();
@@ -0,0 +1,152 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Crash Report Bundles
+//!
+//! `CrashReport` collects everything needed to triage a scheduler failure
+//! into a single gzipped tarball: the exit info and debug dump, the most
+//! recent stats snapshots, the CPU topology, the kernel version and the
+//! scheduler command line.
+//!
+//! ```ignore
+//! let mut crash = CrashReport::new("scx_foo", "/var/tmp");
+//! while !shutdown.load(Ordering::Relaxed) && !uei_exited!(&skel, uei) {
+//!     crash.add_stats(format!("{:?}", stats));
+//!     ...
+//! }
+//! let uei = uei_read!(&skel, uei);
+//! if let Some(path) = crash.write_on_error(&uei)? {
+//!     eprintln!("Crash report written to {}", path.display());
+//! }
+//! uei.report()
+//! ```
+
+use crate::Topology;
+use crate::UserExitInfo;
+use anyhow::Context;
+use anyhow::Result;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Number of stats snapshots to keep by default.
+const DFL_NR_STATS: usize = 16;
+
+pub struct CrashReport {
+    sched: String,
+    dir: PathBuf,
+    nr_stats: usize,
+    stats: VecDeque<(u64, String)>,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl CrashReport {
+    /// Create a crash report collector for @sched. Reports are written
+    /// into the directory @dir.
+    pub fn new(sched: &str, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            sched: sched.into(),
+            dir: dir.into(),
+            nr_stats: DFL_NR_STATS,
+            stats: VecDeque::new(),
+        }
+    }
+
+    /// Set the number of most recent stats snapshots to keep.
+    pub fn set_nr_stats(&mut self, nr_stats: usize) {
+        self.nr_stats = nr_stats;
+        while self.stats.len() > nr_stats {
+            self.stats.pop_front();
+        }
+    }
+
+    /// Record a stats snapshot. Only the most recent ones are kept.
+    pub fn add_stats(&mut self, snapshot: String) {
+        if self.nr_stats == 0 {
+            return;
+        }
+        if self.stats.len() >= self.nr_stats {
+            self.stats.pop_front();
+        }
+        self.stats.push_back((unix_secs(), snapshot));
+    }
+
+    fn files(&self, uei: &UserExitInfo) -> Vec<(&'static str, String)> {
+        let stats = self
+            .stats
+            .iter()
+            .map(|(at, snapshot)| format!("[{}]\n{}\n", at, snapshot))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let topology = match Topology::new() {
+            Ok(topo) => format!("{:#?}\n", topo),
+            Err(e) => format!("Failed to read topology: {:?}\n", e),
+        };
+
+        let kernel = std::fs::read_to_string("/proc/version")
+            .unwrap_or_else(|e| format!("Failed to read /proc/version: {}\n", e));
+
+        let cmdline = std::env::args().collect::<Vec<String>>().join(" ") + "\n";
+
+        vec![
+            ("exit.txt", uei.to_text()),
+            ("stats.txt", stats),
+            ("topology.txt", topology),
+            ("kernel.txt", kernel),
+            ("cmdline.txt", cmdline),
+        ]
+    }
+
+    /// Write the crash report tarball unconditionally and return its path.
+    pub fn write(&self, uei: &UserExitInfo) -> Result<PathBuf> {
+        let at = unix_secs();
+        let name = format!("{}-crash-{}", self.sched, at);
+        let path = self.dir.join(format!("{}.tar.gz", name));
+
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", &self.dir))?;
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed to create {:?}", &path))?;
+
+        let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        for (file_name, content) in self.files(uei).iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(at);
+            header.set_cksum();
+            tar.append_data(
+                &mut header,
+                format!("{}/{}", name, file_name),
+                content.as_bytes(),
+            )
+            .with_context(|| format!("Failed to add {} to {:?}", file_name, &path))?;
+        }
+        tar.into_inner()?
+            .finish()
+            .with_context(|| format!("Failed to write {:?}", &path))?;
+
+        Ok(path)
+    }
+
+    /// Write the crash report if @uei indicates an error exit. Returns the
+    /// path of the tarball if one was written.
+    pub fn write_on_error(&self, uei: &UserExitInfo) -> Result<Option<PathBuf>> {
+        if !uei.is_error() {
+            return Ok(None);
+        }
+        self.write(uei).map(Some)
+    }
+}
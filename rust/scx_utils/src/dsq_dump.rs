@@ -0,0 +1,248 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # DSQ State Dumper
+//!
+//! Rust userland counterpart of
+//! [dsq_dump.bpf.h](https://github.com/sched-ext/scx/blob/main/scheds/include/scx/dsq_dump.bpf.h).
+//! `DsqDump::read()` runs the `scx_dsq_dump` BPF program and collects the
+//! depths and task lists of the requested DSQs and the depths of the per-CPU
+//! local DSQs. The result implements `Display` so that it can be printed
+//! on demand, e.g. from a SIGUSR1 handler, to triage stalls without
+//! triggering a full kernel dump.
+//!
+//! ```ignore
+//! let dump = DsqDump::read(
+//!     skel.progs_mut().scx_dsq_dump(),
+//!     skel.maps().scx_dsq_dump_rb(),
+//!     &[SHARED_DSQ],
+//!     *NR_POSSIBLE_CPUS,
+//! )?;
+//! info!("{}", dump);
+//! ```
+
+use crate::bindings;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::fmt;
+
+const MAX_DSQS: usize = bindings::scx_dsq_dump_consts_SCX_DSQ_DUMP_MAX_DSQS as usize;
+const MAX_CPUS: usize = bindings::scx_dsq_dump_consts_SCX_DSQ_DUMP_MAX_CPUS as usize;
+
+const REC_DSQ: u32 = bindings::scx_dsq_dump_consts_SCX_DSQ_DUMP_REC_DSQ;
+const REC_TASK: u32 = bindings::scx_dsq_dump_consts_SCX_DSQ_DUMP_REC_TASK;
+const REC_CPU: u32 = bindings::scx_dsq_dump_consts_SCX_DSQ_DUMP_REC_CPU;
+
+/// A task queued on a DSQ.
+#[derive(Clone, Debug)]
+pub struct DsqTask {
+    pub pid: i32,
+    pub comm: String,
+    /// How long the task has been runnable in milliseconds.
+    pub runnable_ms: u64,
+}
+
+/// State of a DSQ. @tasks is in dispatch order and may be truncated.
+#[derive(Clone, Debug)]
+pub struct DsqState {
+    pub id: u64,
+    pub nr_queued: i32,
+    pub tasks: Vec<DsqTask>,
+}
+
+impl DsqState {
+    /// How long the longest waiting listed task has been runnable.
+    pub fn oldest_runnable_ms(&self) -> Option<u64> {
+        self.tasks.iter().map(|t| t.runnable_ms).max()
+    }
+}
+
+/// Snapshot of the DSQs of the attached scheduler.
+#[derive(Clone, Debug, Default)]
+pub struct DsqDump {
+    pub dsqs: Vec<DsqState>,
+    /// Depth of the local DSQ of each CPU.
+    pub cpu_nr_queued: Vec<i32>,
+}
+
+impl DsqDump {
+    /// Dump the DSQs @dsq_ids and the local DSQs of the first @nr_cpus
+    /// CPUs. @prog and @rb are the scx_dsq_dump program and the
+    /// scx_dsq_dump_rb ring buffer of the loaded skeleton.
+    pub fn read(
+        prog: &mut libbpf_rs::Program,
+        rb: &libbpf_rs::Map,
+        dsq_ids: &[u64],
+        nr_cpus: usize,
+    ) -> Result<Self> {
+        if dsq_ids.len() > MAX_DSQS {
+            bail!("Too many DSQs to dump ({} > {})", dsq_ids.len(), MAX_DSQS);
+        }
+
+        let mut args = bindings::scx_dsq_dump_args {
+            dsq_ids: [0; MAX_DSQS],
+            nr_dsqs: dsq_ids.len() as u32,
+            nr_cpus: nr_cpus.min(MAX_CPUS) as u32,
+        };
+        args.dsq_ids[..dsq_ids.len()].copy_from_slice(dsq_ids);
+
+        let args_bytes = unsafe {
+            std::slice::from_raw_parts(
+                &args as *const bindings::scx_dsq_dump_args as *const u8,
+                std::mem::size_of::<bindings::scx_dsq_dump_args>(),
+            )
+        };
+
+        let mut recs = vec![];
+        let mut builder = libbpf_rs::RingBufferBuilder::new();
+        builder.add(rb, |data: &[u8]| {
+            if data.len() >= std::mem::size_of::<bindings::scx_dsq_dump_rec>() {
+                // Ring buffer records are only 8-byte aligned.
+                let rec = unsafe {
+                    std::ptr::read_unaligned(data.as_ptr() as *const bindings::scx_dsq_dump_rec)
+                };
+                recs.push(rec);
+            }
+            0
+        })?;
+        let ringbuf = builder.build()?;
+
+        let input = libbpf_rs::ProgramInput {
+            context_in: Some(args_bytes),
+            ..Default::default()
+        };
+        prog.test_run(input).context("Failed to run scx_dsq_dump")?;
+
+        ringbuf.consume()?;
+        drop(ringbuf);
+
+        Ok(Self::from_recs(&recs))
+    }
+
+    fn from_recs(recs: &[bindings::scx_dsq_dump_rec]) -> Self {
+        let mut dump = Self::default();
+
+        for rec in recs.iter() {
+            match rec.kind {
+                REC_DSQ => dump.dsqs.push(DsqState {
+                    id: rec.id,
+                    nr_queued: rec.nr_queued,
+                    tasks: vec![],
+                }),
+                REC_TASK => {
+                    if let Some(dsq) = dump.dsqs.last_mut() {
+                        let comm: Vec<u8> = rec
+                            .comm
+                            .iter()
+                            .take_while(|c| **c != 0)
+                            .map(|c| *c as u8)
+                            .collect();
+                        dsq.tasks.push(DsqTask {
+                            pid: rec.pid,
+                            comm: String::from_utf8_lossy(&comm).into_owned(),
+                            runnable_ms: rec.runnable_ms,
+                        });
+                    }
+                }
+                REC_CPU => dump.cpu_nr_queued.push(rec.nr_queued),
+                _ => (),
+            }
+        }
+
+        dump
+    }
+}
+
+impl fmt::Display for DsqDump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for dsq in self.dsqs.iter() {
+            write!(f, "DSQ 0x{:x}: nr_queued={}", dsq.id, dsq.nr_queued)?;
+            if let Some(oldest) = dsq.oldest_runnable_ms() {
+                write!(f, " oldest={}ms", oldest)?;
+            }
+            writeln!(f)?;
+            for task in dsq.tasks.iter() {
+                writeln!(
+                    f,
+                    "  {:>8} {:<16} runnable={}ms",
+                    task.pid, task.comm, task.runnable_ms
+                )?;
+            }
+            if dsq.nr_queued > 0 && (dsq.tasks.len() as i32) < dsq.nr_queued {
+                writeln!(f, "  ... {} more", dsq.nr_queued - dsq.tasks.len() as i32)?;
+            }
+        }
+
+        write!(f, "local DSQs:")?;
+        for (cpu, nr_queued) in self.cpu_nr_queued.iter().enumerate() {
+            if cpu % 16 == 0 {
+                write!(f, "\n  {:4}:", cpu)?;
+            }
+            write!(f, " {:3}", nr_queued)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(
+        kind: u32,
+        id: u64,
+        nr_queued: i32,
+        task: Option<(i32, &str, u64)>,
+    ) -> bindings::scx_dsq_dump_rec {
+        let (pid, name, runnable_ms) = task.unwrap_or((0, "", 0));
+        let mut comm = [0; 16];
+        for (c, b) in comm.iter_mut().zip(name.bytes()) {
+            *c = b as _;
+        }
+        bindings::scx_dsq_dump_rec {
+            kind,
+            nr_queued,
+            id,
+            pid,
+            pad: 0,
+            runnable_ms,
+            comm,
+        }
+    }
+
+    #[test]
+    fn test_from_recs() {
+        let dump = DsqDump::from_recs(&[
+            // A task record without a preceding DSQ is ignored.
+            rec(REC_TASK, 0, 0, Some((1, "orphan", 1))),
+            rec(REC_DSQ, 0x100, 3, None),
+            rec(REC_TASK, 0x100, 0, Some((42, "stress-ng", 120))),
+            rec(REC_TASK, 0x100, 0, Some((43, "kworker/0:1", 5))),
+            rec(REC_CPU, 0, 1, None),
+            rec(REC_CPU, 1, 0, None),
+            rec(0, 0, 0, None),
+        ]);
+
+        assert_eq!(dump.dsqs.len(), 1);
+        let dsq = &dump.dsqs[0];
+        assert_eq!((dsq.id, dsq.nr_queued, dsq.tasks.len()), (0x100, 3, 2));
+        assert_eq!(
+            (dsq.tasks[1].pid, dsq.tasks[1].comm.as_str()),
+            (43, "kworker/0:1")
+        );
+        assert_eq!(dsq.oldest_runnable_ms(), Some(120));
+        assert_eq!(dump.cpu_nr_queued, vec![1, 0]);
+
+        assert_eq!(
+            format!("{}", dump),
+            "DSQ 0x100: nr_queued=3 oldest=120ms\n\
+             \x20       42 stress-ng        runnable=120ms\n\
+             \x20       43 kworker/0:1      runnable=5ms\n\
+             \x20 ... 1 more\n\
+             local DSQs:\n     0:   1   0\n"
+        );
+    }
+}
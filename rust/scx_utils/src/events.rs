@@ -0,0 +1,209 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Scheduling Event Collection
+//!
+//! Rust userland counterpart of
+//! [events.bpf.h](https://github.com/sched-ext/scx/blob/main/scheds/include/scx/events.bpf.h).
+//! A BPF scheduler which includes the header streams scheduling events
+//! (wakeups, context switches, migrations and exits) into the `scx_events`
+//! ring buffer. This module selects the programs to load, enables the
+//! events and parses them into `SchedEvent`.
+//!
+//! ```ignore
+//! let mut skel = skel_builder.open()?;
+//! scx_utils::scx_events_select_progs!(skel);
+//! let mut skel = scx_ops_load!(skel, ops, uei)?;
+//! skel.bss_mut().scx_events_mask = events::event_mask(&[SchedEventKind::Wakeup]);
+//!
+//! let mut builder = libbpf_rs::RingBufferBuilder::new();
+//! builder.add(skel.maps().scx_events(), |data| {
+//!     if let Some(ev) = SchedEvent::from_bytes(data) {
+//!         println!("{:?}", ev);
+//!     }
+//!     0
+//! })?;
+//! ```
+
+use crate::bindings;
+
+/// Kinds of scheduling events. Matches C enum scx_event_kind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedEventKind {
+    Wakeup = bindings::scx_event_kind_SCX_EVENT_WAKEUP as isize,
+    Switch = bindings::scx_event_kind_SCX_EVENT_SWITCH as isize,
+    Migrate = bindings::scx_event_kind_SCX_EVENT_MIGRATE as isize,
+    Exit = bindings::scx_event_kind_SCX_EVENT_EXIT as isize,
+}
+
+/// A scheduling event. `ts` is in CLOCK_MONOTONIC nanoseconds and `cpu` is
+/// the CPU which generated the event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchedEvent {
+    /// @pid was woken up to run on @target_cpu if known.
+    Wakeup {
+        ts: u64,
+        cpu: u32,
+        pid: i32,
+        target_cpu: Option<u32>,
+    },
+    /// @cpu switched from @prev_pid to @next_pid.
+    Switch {
+        ts: u64,
+        cpu: u32,
+        prev_pid: i32,
+        next_pid: i32,
+    },
+    /// @pid is being migrated to @dest_cpu.
+    Migrate {
+        ts: u64,
+        cpu: u32,
+        pid: i32,
+        dest_cpu: u32,
+    },
+    /// @pid exited.
+    Exit { ts: u64, cpu: u32, pid: i32 },
+}
+
+impl SchedEvent {
+    /// Parse a record read from the `scx_events` ring buffer. None if the
+    /// record is truncated or of an unknown kind.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < std::mem::size_of::<bindings::scx_event>() {
+            return None;
+        }
+        // Ring buffer records are only 8-byte aligned.
+        let raw = unsafe { std::ptr::read_unaligned(data.as_ptr() as *const bindings::scx_event) };
+        let target_cpu = u32::try_from(raw.target_cpu).ok();
+
+        match raw.kind {
+            k if k == SchedEventKind::Wakeup as u32 => Some(Self::Wakeup {
+                ts: raw.ts,
+                cpu: raw.cpu,
+                pid: raw.pid,
+                target_cpu,
+            }),
+            k if k == SchedEventKind::Switch as u32 => Some(Self::Switch {
+                ts: raw.ts,
+                cpu: raw.cpu,
+                prev_pid: raw.prev_pid,
+                next_pid: raw.pid,
+            }),
+            k if k == SchedEventKind::Migrate as u32 => Some(Self::Migrate {
+                ts: raw.ts,
+                cpu: raw.cpu,
+                pid: raw.pid,
+                dest_cpu: target_cpu?,
+            }),
+            k if k == SchedEventKind::Exit as u32 => Some(Self::Exit {
+                ts: raw.ts,
+                cpu: raw.cpu,
+                pid: raw.pid,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> SchedEventKind {
+        match self {
+            Self::Wakeup { .. } => SchedEventKind::Wakeup,
+            Self::Switch { .. } => SchedEventKind::Switch,
+            Self::Migrate { .. } => SchedEventKind::Migrate,
+            Self::Exit { .. } => SchedEventKind::Exit,
+        }
+    }
+
+    pub fn ts(&self) -> u64 {
+        match self {
+            Self::Wakeup { ts, .. }
+            | Self::Switch { ts, .. }
+            | Self::Migrate { ts, .. }
+            | Self::Exit { ts, .. } => *ts,
+        }
+    }
+}
+
+/// Build the value for `scx_events_mask` which enables @kinds.
+pub fn event_mask(kinds: &[SchedEventKind]) -> u32 {
+    kinds
+        .iter()
+        .fold(0, |mask, kind| mask | (1 << *kind as u32))
+}
+
+/// Select between the tracepoint and kprobe programs of events.bpf.h
+/// depending on which tracepoints can be attached with BTF on the running
+/// kernel. Must be called on the open skeleton before loading.
+#[macro_export]
+macro_rules! scx_events_select_progs {
+    ($skel: expr) => {{
+        let has_tp = |tp| scx_utils::compat::tracepoint_exists(tp).unwrap_or(false);
+        let (wakeup, switch, migrate, exit) = (
+            has_tp("sched_wakeup"),
+            has_tp("sched_switch"),
+            has_tp("sched_migrate_task"),
+            has_tp("sched_process_exit"),
+        );
+
+        let mut progs = $skel.progs_mut();
+        progs.scx_ev_tp_wakeup().set_autoload(wakeup)?;
+        progs.scx_ev_kp_wakeup().set_autoload(!wakeup)?;
+        progs.scx_ev_tp_switch().set_autoload(switch)?;
+        progs.scx_ev_tp_migrate().set_autoload(migrate)?;
+        progs.scx_ev_kp_migrate().set_autoload(!migrate)?;
+        progs.scx_ev_tp_exit().set_autoload(exit)?;
+        progs.scx_ev_kp_exit().set_autoload(!exit)?;
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_bytes(raw: bindings::scx_event) -> Vec<u8> {
+        let ptr = &raw as *const bindings::scx_event as *const u8;
+        unsafe { std::slice::from_raw_parts(ptr, std::mem::size_of::<bindings::scx_event>()) }.to_vec()
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let raw = bindings::scx_event {
+            ts: 100,
+            kind: SchedEventKind::Switch as u32,
+            cpu: 3,
+            pid: 20,
+            prev_pid: 10,
+            target_cpu: -1,
+            pad: 0,
+        };
+        assert_eq!(
+            SchedEvent::from_bytes(&raw_bytes(raw)),
+            Some(SchedEvent::Switch {
+                ts: 100,
+                cpu: 3,
+                prev_pid: 10,
+                next_pid: 20
+            })
+        );
+
+        let raw = bindings::scx_event {
+            kind: SchedEventKind::Wakeup as u32,
+            target_cpu: -1,
+            ..raw
+        };
+        assert_eq!(
+            SchedEvent::from_bytes(&raw_bytes(raw)).unwrap(),
+            SchedEvent::Wakeup {
+                ts: 100,
+                cpu: 3,
+                pid: 20,
+                target_cpu: None
+            }
+        );
+
+        let raw = bindings::scx_event { kind: 0, ..raw };
+        assert_eq!(SchedEvent::from_bytes(&raw_bytes(raw)), None);
+        assert_eq!(SchedEvent::from_bytes(&[0; 8]), None);
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Exit Dump Parser
+//!
+//! When a BPF scheduler exits with an error, the kernel dumps its state into
+//! the debug dump area which `UserExitInfo` captures as text. `ExitDump`
+//! parses the text into the exit backtrace and per-CPU sections each listing
+//! the runnable tasks with their backtraces so that tools can compare dumps
+//! and pinpoint the stalled CPU.
+//!
+//! ```ignore
+//! let uei = uei_read!(skel, uei);
+//! if let Some(dump) = uei.exit_dump() {
+//!     if let Some((cpu, task)) = dump.longest_waiting() {
+//!         warn!("CPU {}: {}[{}] waited {}ms", cpu.cpu, task.comm, task.pid,
+//!               task.waiting_ms());
+//!     }
+//! }
+//! ```
+
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref CPU_RE: Regex = Regex::new(r"^CPU\s+(\d+)\s*:(.*)$").unwrap();
+    static ref CURR_RE: Regex = Regex::new(r"^\s+curr=(.*)\[(-?\d+)\]").unwrap();
+    static ref TASK_RE: Regex =
+        Regex::new(r"^ ([* ])(\S) (.*)\[(-?\d+)\] ([+-]?\d+)ms$").unwrap();
+}
+
+/// A task listed in a CPU section of the exit dump.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DumpTask {
+    /// Whether the task was running on the CPU at the time of the dump.
+    pub curr: bool,
+    /// Task state character as in /proc/PID/stat.
+    pub state: char,
+    pub comm: String,
+    pub pid: i32,
+    /// When the task became runnable in msecs relative to the dump.
+    /// Negative if the task had been runnable before the dump.
+    pub runnable_at_ms: i64,
+    /// Lines describing the task's sched_ext state and the output of
+    /// ops.dump_task(), stripped of indentation.
+    pub info: Vec<String>,
+    pub backtrace: Vec<String>,
+}
+
+impl DumpTask {
+    /// How long the task had been waiting at the time of the dump.
+    pub fn waiting_ms(&self) -> u64 {
+        (-self.runnable_at_ms).max(0) as u64
+    }
+}
+
+/// A CPU section of the exit dump.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DumpCpu {
+    pub cpu: u32,
+    pub nr_run: u32,
+    /// comm and pid of the task which was running on the CPU.
+    pub curr: Option<(String, i32)>,
+    /// The remaining lines describing the CPU including the output of
+    /// ops.dump_cpu(), stripped of indentation.
+    pub info: Vec<String>,
+    pub tasks: Vec<DumpTask>,
+}
+
+/// Parsed kernel exit dump. Lines which aren't recognized are kept in
+/// @misc so that nothing is lost when the dump format changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExitDump {
+    /// The first line, e.g. "kworker/0:1[12] triggered exit kind 1026:"
+    pub header: String,
+    /// The exit reason and message line following the header.
+    pub reason: String,
+    pub backtrace: Vec<String>,
+    pub cpus: Vec<DumpCpu>,
+    pub misc: Vec<String>,
+}
+
+#[derive(PartialEq)]
+enum Section {
+    Head,
+    Backtrace,
+    Cpus,
+    Misc,
+}
+
+impl ExitDump {
+    pub fn parse(text: &str) -> Self {
+        let mut dump = Self::default();
+        let mut section = Section::Head;
+        let mut after_blank = false;
+
+        let mut lines = text.lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = line.trim_end();
+
+            if line.is_empty() {
+                after_blank = true;
+                continue;
+            }
+            let blank = std::mem::replace(&mut after_blank, false);
+
+            // Top-level section headers
+            if line == "Backtrace:" {
+                section = Section::Backtrace;
+                continue;
+            }
+            if line == "CPU states" {
+                if lines.peek().is_some_and(|l| l.starts_with("---")) {
+                    lines.next();
+                }
+                section = Section::Cpus;
+                continue;
+            }
+
+            match section {
+                Section::Head => {
+                    if dump.header.is_empty() {
+                        dump.header = line.to_string();
+                    } else if dump.reason.is_empty() {
+                        dump.reason = line.trim().to_string();
+                    } else {
+                        dump.misc.push(line.to_string());
+                    }
+                }
+                Section::Backtrace => {
+                    if line.starts_with(' ') && !blank {
+                        dump.backtrace.push(line.trim().to_string());
+                    } else {
+                        section = Section::Misc;
+                        dump.misc.push(line.to_string());
+                    }
+                }
+                Section::Cpus => {
+                    if !line.starts_with(' ') {
+                        match CPU_RE.captures(line) {
+                            Some(caps) => dump.cpus.push(Self::parse_cpu(&caps[1], &caps[2])),
+                            None => {
+                                section = Section::Misc;
+                                dump.misc.push(line.to_string());
+                            }
+                        }
+                        continue;
+                    }
+
+                    let cpu = match dump.cpus.last_mut() {
+                        Some(cpu) => cpu,
+                        None => {
+                            dump.misc.push(line.to_string());
+                            continue;
+                        }
+                    };
+
+                    if let Some(caps) = TASK_RE.captures(line) {
+                        cpu.tasks.push(DumpTask {
+                            curr: &caps[1] == "*",
+                            state: caps[2].chars().next().unwrap_or('?'),
+                            comm: caps[3].to_string(),
+                            pid: caps[4].parse().unwrap_or(-1),
+                            runnable_at_ms: caps[5].parse().unwrap_or(0),
+                            ..Default::default()
+                        });
+                    } else if let Some(task) = cpu.tasks.last_mut() {
+                        // A blank line separates the task info from the
+                        // task's backtrace.
+                        if blank || !task.backtrace.is_empty() {
+                            task.backtrace.push(line.trim().to_string());
+                        } else {
+                            task.info.push(line.trim().to_string());
+                        }
+                    } else if let Some(caps) = CURR_RE.captures(line) {
+                        cpu.curr = Some((caps[1].to_string(), caps[2].parse().unwrap_or(-1)));
+                    } else {
+                        cpu.info.push(line.trim().to_string());
+                    }
+                }
+                Section::Misc => dump.misc.push(line.to_string()),
+            }
+        }
+
+        dump
+    }
+
+    fn parse_cpu(cpu: &str, rest: &str) -> DumpCpu {
+        let nr_run = rest
+            .split_whitespace()
+            .find_map(|kv| kv.strip_prefix("nr_run="))
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        DumpCpu {
+            cpu: cpu.parse().unwrap_or(0),
+            nr_run,
+            info: vec![rest.trim().to_string()],
+            ..Default::default()
+        }
+    }
+
+    /// The task which had been waiting the longest and its CPU. On stall
+    /// exits, this is usually the stalled task.
+    pub fn longest_waiting(&self) -> Option<(&DumpCpu, &DumpTask)> {
+        self.cpus
+            .iter()
+            .flat_map(|cpu| cpu.tasks.iter().map(move |task| (cpu, task)))
+            .filter(|(_, task)| task.waiting_ms() > 0)
+            .max_by_key(|(_, task)| task.waiting_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "\
+kworker/u8:1[42] triggered exit kind 1026:
+  runnable task stall (stress[1234] failed to run for 30.1s)
+
+Backtrace:
+  scx_watchdog_workfn+0x154/0x1e0
+  process_one_work+0x18e/0x380
+
+CPU states
+----------
+
+CPU 0   : nr_run=2 flags=0x1 cpu_rel=0 ops_qseq=10 pnt_seq=20
+          curr=swapper/0[0] class=idle_sched_class
+
+  R stress[1234] -30100ms
+      scx_state/flags=3/0x9 dsq_flags=0x0 ops_state/qseq=0/0
+      sticky/holding_cpu=-1/-1 dsq_id=0x0
+      cpus=0f
+
+    do_syscall_64+0x3f/0xc0
+
+CPU 1   : nr_run=1 flags=0x1 cpu_rel=0 ops_qseq=3 pnt_seq=7
+          curr=bash[99] class=ext_sched_class
+
+ *R bash[99] +0ms
+      scx_state/flags=3/0xd dsq_flags=0x0 ops_state/qseq=0/0
+";
+
+    #[test]
+    fn test_parse() {
+        let dump = ExitDump::parse(DUMP);
+
+        assert_eq!(dump.header, "kworker/u8:1[42] triggered exit kind 1026:");
+        assert!(dump.reason.starts_with("runnable task stall"));
+        assert_eq!(dump.backtrace.len(), 2);
+        assert_eq!(dump.cpus.len(), 2);
+        assert!(dump.misc.is_empty());
+
+        let cpu0 = &dump.cpus[0];
+        assert_eq!((cpu0.cpu, cpu0.nr_run), (0, 2));
+        assert_eq!(cpu0.curr, Some(("swapper/0".to_string(), 0)));
+        assert_eq!(cpu0.tasks.len(), 1);
+        assert_eq!(cpu0.tasks[0].info.len(), 3);
+        assert_eq!(cpu0.tasks[0].backtrace, vec!["do_syscall_64+0x3f/0xc0"]);
+
+        let (cpu, task) = dump.longest_waiting().unwrap();
+        assert_eq!((cpu.cpu, task.pid, task.waiting_ms()), (0, 1234, 30100));
+        assert!(dump.cpus[1].tasks[0].curr);
+    }
+}
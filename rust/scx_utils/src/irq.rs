@@ -0,0 +1,275 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # IRQ and Softirq Load
+//!
+//! CPUs hammered by device interrupts, e.g. NIC queues, are bad places for
+//! latency sensitive tasks. `IrqSnapshot` reads the per-CPU counters from
+//! /proc/interrupts and /proc/softirqs. The delta between two snapshots
+//! tells how many hard and soft interrupts each CPU handled in the
+//! interval. `irq_affinity()` reads where an IRQ is allowed and currently
+//! routed to.
+//!
+//! ```ignore
+//! let mut prev = IrqSnapshot::read()?;
+//! loop {
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     let cur = IrqSnapshot::read()?;
+//!     let delta = cur.delta(&prev);
+//!     for cpu in delta.busiest_cpus(4) {
+//!         info!("CPU {}: {} irqs {} softirqs", cpu, delta.cpu_irqs()[cpu],
+//!               delta.cpu_softirqs()[cpu]);
+//!     }
+//!     prev = cur;
+//! }
+//! ```
+
+use crate::Cpumask;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// Per-CPU counts of an interrupt source.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IrqCounts {
+    /// Indexed by the CPU. Sources which aren't per-CPU, e.g. ERR, have a
+    /// single count.
+    pub counts: Vec<u64>,
+    /// The chip, hwirq and device names following the counts.
+    pub desc: String,
+}
+
+/// The interrupt counters of all CPUs at a point in time. Keyed by the
+/// IRQ number or name, e.g. "24" or "LOC", and by the softirq name, e.g.
+/// "NET_RX".
+#[derive(Clone, Debug)]
+pub struct IrqSnapshot {
+    pub at: Instant,
+    pub nr_cpus: usize,
+    pub irqs: BTreeMap<String, IrqCounts>,
+    pub softirqs: BTreeMap<String, Vec<u64>>,
+}
+
+/// The CPU ids from the header line of /proc/interrupts or /proc/softirqs.
+/// Offline CPUs are skipped in the columns.
+fn parse_header(line: &str) -> Result<Vec<usize>> {
+    line.split_whitespace()
+        .map(|col| match col.strip_prefix("CPU").map(|id| id.parse()) {
+            Some(Ok(cpu)) => Ok(cpu),
+            _ => bail!("Invalid CPU column {:?}", col),
+        })
+        .collect()
+}
+
+fn parse_row<'a>(line: &'a str, cpus: &[usize]) -> Option<(String, Vec<u64>, &'a str)> {
+    let (name, rest) = line.split_once(':')?;
+    let nr_cpus = cpus.iter().max().map_or(0, |max| max + 1);
+
+    let mut rest = rest.trim_start();
+    let mut vals = vec![];
+    while vals.len() < cpus.len() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        match rest[..end].parse::<u64>() {
+            Ok(v) => vals.push(v),
+            Err(_) => break,
+        }
+        rest = rest[end..].trim_start();
+    }
+
+    // Per-CPU rows are indexed by CPU id. Global ones keep their count.
+    let counts = match vals.len() == cpus.len() {
+        true => {
+            let mut counts = vec![0; nr_cpus];
+            for (cpu, v) in cpus.iter().zip(vals) {
+                counts[*cpu] = v;
+            }
+            counts
+        }
+        false => vals,
+    };
+    Some((name.trim().to_string(), counts, rest.trim_end()))
+}
+
+impl IrqSnapshot {
+    pub fn read() -> Result<Self> {
+        let interrupts = std::fs::read_to_string("/proc/interrupts")
+            .context("Failed to read /proc/interrupts")?;
+        let softirqs =
+            std::fs::read_to_string("/proc/softirqs").context("Failed to read /proc/softirqs")?;
+        Self::parse(&interrupts, &softirqs)
+    }
+
+    /// Parse the contents of /proc/interrupts and /proc/softirqs.
+    pub fn parse(interrupts: &str, softirqs: &str) -> Result<Self> {
+        let mut lines = interrupts.lines();
+        let cpus = parse_header(lines.next().unwrap_or(""))?;
+        let nr_cpus = cpus.iter().max().map_or(0, |max| max + 1);
+
+        let irqs = lines
+            .filter_map(|line| parse_row(line, &cpus))
+            .map(|(name, counts, desc)| {
+                let desc = desc.to_string();
+                (name, IrqCounts { counts, desc })
+            })
+            .collect();
+
+        let mut lines = softirqs.lines();
+        let cpus = parse_header(lines.next().unwrap_or(""))?;
+        let softirqs = lines
+            .filter_map(|line| parse_row(line, &cpus))
+            .map(|(name, counts, _)| (name, counts))
+            .collect();
+
+        Ok(Self {
+            at: Instant::now(),
+            nr_cpus,
+            irqs,
+            softirqs,
+        })
+    }
+
+    /// The counts between @prev and @self.
+    pub fn delta(&self, prev: &Self) -> Self {
+        fn sub(cur: &[u64], prev: Option<&Vec<u64>>) -> Vec<u64> {
+            cur.iter()
+                .enumerate()
+                .map(|(i, v)| v - prev.and_then(|p| p.get(i)).copied().unwrap_or(0).min(*v))
+                .collect()
+        }
+
+        let irqs = self
+            .irqs
+            .iter()
+            .map(|(name, cur)| {
+                let prev = prev.irqs.get(name).map(|p| &p.counts);
+                let counts = sub(&cur.counts, prev);
+                let desc = cur.desc.clone();
+                (name.clone(), IrqCounts { counts, desc })
+            })
+            .collect();
+
+        let softirqs = self
+            .softirqs
+            .iter()
+            .map(|(name, cur)| (name.clone(), sub(cur, prev.softirqs.get(name))))
+            .collect();
+
+        Self {
+            at: self.at,
+            nr_cpus: self.nr_cpus,
+            irqs,
+            softirqs,
+        }
+    }
+
+    fn sum_per_cpu<'a>(&self, rows: impl Iterator<Item = &'a Vec<u64>>) -> Vec<u64> {
+        let mut sums = vec![0; self.nr_cpus];
+        for counts in rows.filter(|c| c.len() == self.nr_cpus) {
+            for (sum, v) in sums.iter_mut().zip(counts.iter()) {
+                *sum += v;
+            }
+        }
+        sums
+    }
+
+    /// Hard interrupts per CPU. Only device IRQs, the numbered rows, are
+    /// counted. Architectural ones such as the local timer are excluded
+    /// as they hit all CPUs alike.
+    pub fn cpu_irqs(&self) -> Vec<u64> {
+        self.sum_per_cpu(
+            self.irqs
+                .iter()
+                .filter(|(name, _)| name.parse::<u32>().is_ok())
+                .map(|(_, irq)| &irq.counts),
+        )
+    }
+
+    /// Softirqs per CPU. The timer and SCHED softirqs are excluded as they
+    /// hit all CPUs alike.
+    pub fn cpu_softirqs(&self) -> Vec<u64> {
+        self.sum_per_cpu(
+            self.softirqs
+                .iter()
+                .filter(|(name, _)| !matches!(name.as_str(), "TIMER" | "HRTIMER" | "SCHED"))
+                .map(|(_, counts)| counts),
+        )
+    }
+
+    /// Up to @nr CPUs with the highest device IRQ and softirq counts,
+    /// busiest first. CPUs without any are skipped.
+    pub fn busiest_cpus(&self, nr: usize) -> Vec<usize> {
+        let irqs = self.cpu_irqs();
+        let softirqs = self.cpu_softirqs();
+        let mut cpus: Vec<(usize, u64)> = (0..self.nr_cpus)
+            .map(|cpu| (cpu, irqs[cpu] + softirqs[cpu]))
+            .filter(|(_, load)| *load > 0)
+            .collect();
+        cpus.sort_by_key(|(cpu, load)| (std::cmp::Reverse(*load), *cpu));
+        cpus.into_iter().take(nr).map(|(cpu, _)| cpu).collect()
+    }
+}
+
+fn read_irq_mask(irq: u32, file: &str) -> Result<Cpumask> {
+    let path = format!("/proc/irq/{}/{}", irq, file);
+    let mask =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", &path))?;
+    Cpumask::from_str(&mask.trim().replace(',', ""))
+}
+
+/// The CPUs @irq is allowed to be routed to and the CPUs it's actually
+/// routed to. The latter is the same as the former if the kernel doesn't
+/// report the effective affinity.
+pub fn irq_affinity(irq: u32) -> Result<(Cpumask, Cpumask)> {
+    let allowed = read_irq_mask(irq, "smp_affinity")?;
+    let effective = read_irq_mask(irq, "effective_affinity").unwrap_or_else(|_| allowed.clone());
+    Ok((allowed, effective))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERRUPTS: &str = "\
+           CPU0       CPU2
+ 24:        100          5  IO-APIC   5-edge      ACPI:Ged
+ 40:         10       9000  PCI-MSIX-0000:00:04.0   1-edge      eth0-rx-0
+NMI:          1          1   Non-maskable interrupts
+LOC:       5000       5000   Local timer interrupts
+ERR:          0
+";
+
+    const SOFTIRQS: &str = "\
+                    CPU0       CPU2
+          HI:          0          0
+       TIMER:        500        500
+      NET_RX:         20       4000
+";
+
+    #[test]
+    fn test_parse_delta() {
+        let prev = IrqSnapshot::parse(INTERRUPTS, SOFTIRQS).unwrap();
+        assert_eq!(prev.nr_cpus, 3);
+        assert_eq!(prev.irqs["40"].counts, vec![10, 0, 9000]);
+        assert_eq!(
+            prev.irqs["40"].desc,
+            "PCI-MSIX-0000:00:04.0   1-edge      eth0-rx-0"
+        );
+        assert_eq!(prev.irqs["ERR"].counts, vec![0]);
+        assert_eq!(prev.cpu_irqs(), vec![110, 0, 9005]);
+        assert_eq!(prev.cpu_softirqs(), vec![20, 0, 4000]);
+        assert_eq!(prev.busiest_cpus(4), vec![2, 0]);
+
+        let cur = IrqSnapshot::parse(
+            &INTERRUPTS.replace("100          5", "300          5"),
+            SOFTIRQS,
+        )
+        .unwrap();
+        let delta = cur.delta(&prev);
+        assert_eq!(delta.cpu_irqs(), vec![200, 0, 0]);
+        assert_eq!(delta.busiest_cpus(4), vec![0]);
+    }
+}
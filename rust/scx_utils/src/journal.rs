@@ -0,0 +1,137 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # journald Logging
+//!
+//! Minimal client of the journald native protocol. `init_journal_logging()`
+//! installs a `log` backend which sends each record to journald with its
+//! priority and `SYSLOG_IDENTIFIER` so that `journalctl -t <sched>` shows the
+//! scheduler output. Once installed, `UserExitInfo::report()` also sends the
+//! exit info as a single structured record with the `SCX_EXIT_KIND`,
+//! `SCX_EXIT_CODE`, `SCX_REASON`, `SCX_MSG` and `SCX_DUMP` fields instead of
+//! printing it to stderr.
+//!
+//! ```ignore
+//! if opts.journald && journal::journal_available() {
+//!     journal::init_journal_logging("scx_foo", llv)?;
+//! } else {
+//!     simplelog::TermLogger::init(...)?;
+//! }
+//! ```
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+static IDENTIFIER: OnceLock<String> = OnceLock::new();
+
+/// syslog priorities as used by journald's PRIORITY field.
+pub const PRIO_ERR: u8 = 3;
+pub const PRIO_WARNING: u8 = 4;
+pub const PRIO_NOTICE: u8 = 5;
+pub const PRIO_INFO: u8 = 6;
+pub const PRIO_DEBUG: u8 = 7;
+
+/// Whether journald is running and accepting native protocol messages.
+pub fn journal_available() -> bool {
+    Path::new(JOURNAL_SOCKET).exists()
+}
+
+/// The identifier passed to init_journal_logging() if journald logging is
+/// enabled.
+pub fn journal_identifier() -> Option<&'static str> {
+    IDENTIFIER.get().map(|s| s.as_str())
+}
+
+fn append_field(buf: &mut Vec<u8>, key: &str, val: &[u8]) {
+    buf.extend_from_slice(key.as_bytes());
+    if val.contains(&b'\n') {
+        // Values containing newlines are sent length-prefixed.
+        buf.push(b'\n');
+        buf.extend_from_slice(&(val.len() as u64).to_le_bytes());
+    } else {
+        buf.push(b'=');
+    }
+    buf.extend_from_slice(val);
+    buf.push(b'\n');
+}
+
+/// Send a record with @fields to journald. Field names must be upper case
+/// and may contain digits and underscores, e.g. ("MESSAGE", "hello").
+pub fn journal_send(fields: &[(&str, &str)]) -> Result<()> {
+    let mut buf = vec![];
+    for (key, val) in fields.iter() {
+        append_field(&mut buf, key, val.as_bytes());
+    }
+
+    let sock = UnixDatagram::unbound().context("Failed to create journald socket")?;
+    sock.send_to(&buf, JOURNAL_SOCKET)
+        .with_context(|| format!("Failed to send {} bytes to journald", buf.len()))?;
+    Ok(())
+}
+
+fn level_to_prio(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => PRIO_ERR,
+        log::Level::Warn => PRIO_WARNING,
+        log::Level::Info => PRIO_INFO,
+        log::Level::Debug | log::Level::Trace => PRIO_DEBUG,
+    }
+}
+
+struct JournalLogger {
+    identifier: &'static str,
+    level: log::LevelFilter,
+}
+
+impl log::Log for JournalLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let msg = format!("{}", record.args());
+        let prio = level_to_prio(record.level()).to_string();
+        let res = journal_send(&[
+            ("MESSAGE", &msg),
+            ("PRIORITY", &prio),
+            ("SYSLOG_IDENTIFIER", self.identifier),
+            ("SCX_TARGET", record.target()),
+        ]);
+
+        // Don't lose messages if journald went away.
+        if res.is_err() {
+            eprintln!("{}", msg);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install a `log` backend which sends the records to journald tagged with
+/// @identifier. Records above @level are discarded.
+pub fn init_journal_logging(identifier: &str, level: log::LevelFilter) -> Result<()> {
+    IDENTIFIER
+        .set(identifier.to_string())
+        .map_err(|_| anyhow!("journald logging already initialized"))?;
+
+    let logger = Box::new(JournalLogger {
+        identifier: journal_identifier().unwrap(),
+        level,
+    });
+    log::set_logger(Box::leak(logger))
+        .map_err(|e| anyhow!("Failed to install journald logger ({})", e))?;
+    log::set_max_level(level);
+    Ok(())
+}
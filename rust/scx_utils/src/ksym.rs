@@ -0,0 +1,334 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Exit Dump Symbolization
+//!
+//! Backtraces in exit dumps point into BPF programs as
+//! `bpf_prog_<tag>_<name>+0x2c/0x50` and may contain raw kernel addresses,
+//! neither of which means much to whoever is filing the bug. `Symbolizer`
+//! annotates the dump so that raw addresses are followed by the kernel
+//! symbol from /proc/kallsyms and BPF program offsets by the source file,
+//! line and the source line itself from the loaded programs' BTF line info:
+//!
+//! ```text
+//!   bpf_prog_6c4c3f8e7a0b1d2e_foo_enqueue+0x2c/0x50 [foo.bpf.c:120: scx_bpf_error("bad dsq %llu", dsq_id);]
+//!   0xffffffff81234567 [scx_bpf_error_bstr+0x65]
+//! ```
+//!
+//! The line info is read from the kernel, so the BPF programs must still
+//! be loaded, i.e. symbolize before dropping the skeleton. Reading the
+//! addresses and line info requires root.
+//!
+//! ```ignore
+//! let mut uei = uei_read!(skel, uei);
+//! uei.symbolize_dump(&Symbolizer::new());
+//! uei.report()
+//! ```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Captures;
+use regex::Regex;
+use std::collections::HashMap;
+use std::ffi::CStr;
+
+lazy_static::lazy_static! {
+    static ref BPF_PROG_RE: Regex =
+        Regex::new(r"\b(bpf_prog_[0-9a-f]{16}_\w+)\+0x([0-9a-f]+)/0x[0-9a-f]+").unwrap();
+    static ref RAW_ADDR_RE: Regex = Regex::new(r"\b(?:0x)?(ffff[0-9a-f]{12})\b").unwrap();
+}
+
+/// A text symbol from /proc/kallsyms.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ksym {
+    pub addr: u64,
+    pub name: String,
+    /// The module the symbol belongs to, "bpf" for BPF programs.
+    pub module: Option<String>,
+}
+
+/// Text symbols of the running kernel sorted by address.
+#[derive(Clone, Debug, Default)]
+pub struct Kallsyms {
+    syms: Vec<Ksym>,
+    by_name: HashMap<String, usize>,
+}
+
+impl Kallsyms {
+    pub fn load() -> Result<Self> {
+        let text =
+            std::fs::read_to_string("/proc/kallsyms").context("Failed to read /proc/kallsyms")?;
+        let syms = Self::parse(&text);
+        if syms.syms.is_empty() {
+            bail!("No symbol addresses in /proc/kallsyms, kptr_restrict?");
+        }
+        Ok(syms)
+    }
+
+    /// Parse the contents of /proc/kallsyms. Only text symbols are kept.
+    /// Without sufficient privileges, all addresses read as 0 and the
+    /// result is empty.
+    pub fn parse(text: &str) -> Self {
+        let mut syms: Vec<Ksym> = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let ty = fields.next()?;
+                let name = fields.next()?;
+                let module = fields
+                    .next()
+                    .map(|m| m.trim_start_matches('[').trim_end_matches(']').to_string());
+                match (addr, ty) {
+                    (0, _) => None,
+                    (_, "t" | "T" | "w" | "W") => Some(Ksym {
+                        addr,
+                        name: name.to_string(),
+                        module,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect();
+        syms.sort_by_key(|sym| sym.addr);
+
+        let by_name = syms
+            .iter()
+            .enumerate()
+            .map(|(idx, sym)| (sym.name.clone(), idx))
+            .collect();
+        Self { syms, by_name }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.syms.is_empty()
+    }
+
+    /// The symbol containing @addr and the offset into it. Addresses past
+    /// the last symbol aren't resolved as there's no telling where it ends.
+    pub fn resolve(&self, addr: u64) -> Option<(&Ksym, u64)> {
+        let idx = self.syms.partition_point(|sym| sym.addr <= addr);
+        if idx == 0 || idx == self.syms.len() {
+            return None;
+        }
+        let sym = &self.syms[idx - 1];
+        Some((sym, addr - sym.addr))
+    }
+
+    /// The address of the symbol @name.
+    pub fn addr_of(&self, name: &str) -> Option<u64> {
+        self.by_name.get(name).map(|idx| self.syms[*idx].addr)
+    }
+}
+
+/// Source location of a JITed BPF instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BpfLine {
+    pub addr: u64,
+    pub file: String,
+    pub line: u32,
+    /// The source line as recorded in BTF.
+    pub src: String,
+}
+
+/// JITed address to source line mapping of the loaded BPF programs.
+#[derive(Clone, Debug, Default)]
+pub struct BpfLineTable {
+    /// Sorted by address.
+    lines: Vec<BpfLine>,
+    /// (start, end) of the JITed functions sorted by start.
+    funcs: Vec<(u64, u64)>,
+}
+
+/// Strings of the BTF object @btf_id.
+struct KernelBtf(*mut libbpf_rs::libbpf_sys::btf);
+
+impl KernelBtf {
+    fn load(btf_id: u32) -> Option<Self> {
+        let btf = unsafe { libbpf_rs::libbpf_sys::btf__load_from_kernel_by_id(btf_id) };
+        match btf.is_null() {
+            true => None,
+            false => Some(Self(btf)),
+        }
+    }
+
+    fn str_at(&self, off: u32) -> String {
+        let ptr = unsafe { libbpf_rs::libbpf_sys::btf__name_by_offset(self.0, off) };
+        if ptr.is_null() {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl Drop for KernelBtf {
+    fn drop(&mut self) {
+        unsafe { libbpf_rs::libbpf_sys::btf__free(self.0) };
+    }
+}
+
+impl BpfLineTable {
+    /// Read the line info of all loaded BPF programs.
+    pub fn load() -> Self {
+        let opts = libbpf_rs::query::ProgInfoQueryOptions::default()
+            .include_line_info(true)
+            .include_jited_line_info(true)
+            .include_jited_ksyms(true)
+            .include_jited_func_lens(true);
+
+        let mut table = Self::default();
+        for prog in libbpf_rs::query::ProgInfoIter::with_query_opts(opts) {
+            if prog.line_info.is_empty() || prog.line_info.len() != prog.jited_line_info.len() {
+                continue;
+            }
+            let btf = match KernelBtf::load(prog.btf_id) {
+                Some(btf) => btf,
+                None => continue,
+            };
+
+            for (li, addr) in prog.line_info.iter().zip(prog.jited_line_info.iter()) {
+                table.lines.push(BpfLine {
+                    addr: *addr as u64,
+                    file: btf.str_at(li.file_name_off),
+                    line: li.line_num,
+                    src: btf.str_at(li.line_off).trim().to_string(),
+                });
+            }
+            for (start, len) in prog.jited_ksyms.iter().zip(prog.jited_func_lens.iter()) {
+                table
+                    .funcs
+                    .push((*start as u64, *start as u64 + *len as u64));
+            }
+        }
+
+        table.lines.sort_by_key(|line| line.addr);
+        table.funcs.sort();
+        table
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// The source line of the JITed instruction at @addr.
+    pub fn lookup(&self, addr: u64) -> Option<&BpfLine> {
+        let fidx = self.funcs.partition_point(|(start, _)| *start <= addr);
+        let (start, end) = *self.funcs.get(fidx.checked_sub(1)?)?;
+        if addr >= end {
+            return None;
+        }
+
+        let idx = self.lines.partition_point(|line| line.addr <= addr);
+        let line = &self.lines[idx.checked_sub(1)?];
+        match line.addr >= start {
+            true => Some(line),
+            false => None,
+        }
+    }
+}
+
+/// Annotates exit dumps with kernel symbols and BPF source lines.
+#[derive(Clone, Debug, Default)]
+pub struct Symbolizer {
+    pub ksyms: Kallsyms,
+    pub bpf_lines: BpfLineTable,
+}
+
+impl Symbolizer {
+    /// Load whatever information is available. Symbolization degrades to
+    /// leaving the dump as-is if neither kallsyms nor the BPF line info
+    /// can be read.
+    pub fn new() -> Self {
+        Self {
+            ksyms: Kallsyms::load().unwrap_or_default(),
+            bpf_lines: BpfLineTable::load(),
+        }
+    }
+
+    /// Symbolize BPF program location @prog+@off.
+    fn bpf_prog_line(&self, prog: &str, off: u64) -> Option<&BpfLine> {
+        let addr = self.ksyms.addr_of(prog)?;
+        self.bpf_lines.lookup(addr + off)
+    }
+
+    /// Annotate @text, usually an exit dump. Each resolved location is
+    /// followed by the annotation in brackets.
+    pub fn annotate(&self, text: &str) -> String {
+        if self.ksyms.is_empty() {
+            return text.to_string();
+        }
+
+        let mut out = String::with_capacity(text.len());
+        for line in text.lines() {
+            let line = BPF_PROG_RE.replace_all(line, |caps: &Captures| {
+                let off = u64::from_str_radix(&caps[2], 16).unwrap_or(0);
+                match self.bpf_prog_line(&caps[1], off) {
+                    Some(bl) => format!("{} [{}:{}: {}]", &caps[0], bl.file, bl.line, bl.src),
+                    None => caps[0].to_string(),
+                }
+            });
+            let line = RAW_ADDR_RE.replace_all(&line, |caps: &Captures| {
+                let addr = u64::from_str_radix(&caps[1], 16).unwrap_or(0);
+                match self.ksyms.resolve(addr) {
+                    Some((sym, off)) => match &sym.module {
+                        Some(module) => {
+                            format!("{} [{}+{:#x} [{}]]", &caps[0], sym.name, off, module)
+                        }
+                        None => format!("{} [{}+{:#x}]", &caps[0], sym.name, off),
+                    },
+                    None => caps[0].to_string(),
+                }
+            });
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotate() {
+        let ksyms = Kallsyms::parse(
+            "0000000000000000 A fixed_percpu_data\n\
+             ffffffff81000000 T _stext\n\
+             ffffffff81000100 t foo\n\
+             ffffffff81000200 D some_data\n\
+             ffffffffc0000000 t bpf_prog_0123456789abcdef_enqueue\t[bpf]\n\
+             ffffffffc0000100 t bpf_prog_fedcba9876543210_dispatch\t[bpf]\n",
+        );
+        assert_eq!(ksyms.resolve(0xffffffff81000150).unwrap().1, 0x50);
+        assert_eq!(ksyms.resolve(0xffff888100000000), None);
+
+        let line = |addr, line| BpfLine {
+            addr,
+            file: "foo.bpf.c".into(),
+            line,
+            src: format!("line{};", line),
+        };
+        let sym = Symbolizer {
+            ksyms,
+            bpf_lines: BpfLineTable {
+                lines: vec![line(0xffffffffc0000000, 10), line(0xffffffffc0000020, 12)],
+                funcs: vec![(0xffffffffc0000000, 0xffffffffc0000040)],
+            },
+        };
+
+        assert_eq!(
+            sym.annotate(
+                "  bpf_prog_0123456789abcdef_enqueue+0x2c/0x40\n\
+                 \x20 ffffffff81000180 bpf_prog_fedcba9876543210_dispatch+0x8/0x10\n"
+            ),
+            "  bpf_prog_0123456789abcdef_enqueue+0x2c/0x40 [foo.bpf.c:12: line12;]\n\
+             \x20 ffffffff81000180 [foo+0x80] bpf_prog_fedcba9876543210_dispatch+0x8/0x10\n"
+        );
+    }
+}
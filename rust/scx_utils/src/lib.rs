@@ -33,6 +33,10 @@
 pub use paste::paste;
 pub use log::warn;
 
+// The exported macros refer to scx_utils:: paths. Make them resolve in the
+// crate itself.
+extern crate self as scx_utils;
+
 mod bindings;
 
 mod bpf_builder;
@@ -46,19 +50,88 @@ pub use user_exit_info::ScxExitKind;
 pub use user_exit_info::ScxConsts;
 pub use user_exit_info::UeiDumpPtr;
 pub use user_exit_info::UserExitInfo;
-pub use user_exit_info::UEI_DUMP_PTR_MUTEX;
+pub use user_exit_info::UEI_DUMP_PTRS;
+pub use user_exit_info::UEI_MSG_LEN;
+pub use user_exit_info::UEI_REASON_LEN;
+
+mod exit_dump;
+pub use exit_dump::DumpCpu;
+pub use exit_dump::DumpTask;
+pub use exit_dump::ExitDump;
+
+mod crashreport;
+pub use crashreport::CrashReport;
+
+pub mod bench;
+
+pub mod cli;
 
 pub mod compat;
 
+pub mod config;
+
+pub mod cpufreq;
+
+pub mod cgroup;
+
+pub mod events;
+
+pub mod irq;
+
+mod dsq_dump;
+pub use dsq_dump::DsqDump;
+pub use dsq_dump::DsqState;
+pub use dsq_dump::DsqTask;
+
+pub mod journal;
+
+pub mod ksym;
+
 mod libbpf_logger;
 pub use libbpf_logger::init_libbpf_logging;
 
+mod map_snapshot;
+pub use map_snapshot::Counters;
+pub use map_snapshot::MapSnapshot;
+pub use map_snapshot::PerCpuArraySnapshot;
+
+pub mod map_update;
+
+pub mod nice;
+
+pub mod preflight;
+
 pub mod ravg;
 
+pub mod sandbox;
+
+pub mod sched_info;
+
+pub mod schedstat;
+
+mod stall;
+pub use stall::StallAnalysis;
+pub use stall::StallFingerprint;
+pub use stall::StallHistory;
+
+mod state;
+pub use state::StateStore;
+
+pub mod systemd;
+
+pub mod testing;
+
+pub mod time;
+
+pub mod vm;
+
+pub mod weight;
+
 mod topology;
 pub use topology::Cache;
 pub use topology::Core;
 pub use topology::Cpu;
+pub use topology::Gpu;
 pub use topology::Node;
 pub use topology::Topology;
 pub use topology::TopologyMap;
@@ -0,0 +1,467 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # BPF Map Snapshots
+//!
+//! `MapSnapshot` reads all entries of an array or hash map, per-CPU or not,
+//! into typed values, usually the bindgen generated structs from
+//! `bpf_intf`. Snapshots of maps holding counters can be subtracted to
+//! compute interval deltas and summed over CPUs, which is what most
+//! scheduler stats loops do by hand.
+//!
+//! `PerCpuArraySnapshot` is the specialized version for per-CPU arrays,
+//! the usual home of scheduler stats counters. The whole array is read with
+//! a single batched lookup and the values are stored contiguously, CPU
+//! values of each index next to each other, so that summing them is a
+//! linear scan.
+//!
+//! The counter fields of a value type are declared with `impl_counters!()`.
+//! Fields which aren't listed are left alone by the arithmetic.
+//!
+//! ```ignore
+//! scx_utils::impl_counters!(bpf_intf::cpu_ctx, nr_sched, nr_migrations);
+//!
+//! let cur = MapSnapshot::<u32, bpf_intf::cpu_ctx>::read(skel.maps().cpu_ctxs())?;
+//! let delta = cur.delta(&prev);
+//! let total = delta.sum(&0).unwrap();
+//! prev = cur;
+//! ```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::time::Instant;
+
+/// Values consisting of monotonically increasing counters. Arithmetic
+/// wraps so that counter wraparounds produce correct deltas.
+pub trait Counters: Copy {
+    fn add(&mut self, rhs: &Self);
+    fn sub(&self, rhs: &Self) -> Self;
+}
+
+macro_rules! impl_counters_int {
+    ($($ty:ty),+) => {
+        $(impl Counters for $ty {
+            fn add(&mut self, rhs: &Self) {
+                *self = self.wrapping_add(*rhs);
+            }
+
+            fn sub(&self, rhs: &Self) -> Self {
+                self.wrapping_sub(*rhs)
+            }
+        })+
+    };
+}
+
+impl_counters_int!(u8, u16, u32, u64, i8, i16, i32, i64, usize, isize);
+
+impl<T: Counters, const N: usize> Counters for [T; N] {
+    fn add(&mut self, rhs: &Self) {
+        for (v, r) in self.iter_mut().zip(rhs.iter()) {
+            v.add(r);
+        }
+    }
+
+    fn sub(&self, rhs: &Self) -> Self {
+        let mut res = *self;
+        for (v, r) in res.iter_mut().zip(rhs.iter()) {
+            *v = v.sub(r);
+        }
+        res
+    }
+}
+
+/// Implement `Counters` for the struct @ty. Only the listed fields, which
+/// must implement `Counters` themselves, are added and subtracted. The
+/// other fields keep the values of the left-hand side.
+#[macro_export]
+macro_rules! impl_counters {
+    ($ty: ty, $($field:ident),+ $(,)?) => {
+        impl scx_utils::Counters for $ty {
+            fn add(&mut self, rhs: &Self) {
+                $(scx_utils::Counters::add(&mut self.$field, &rhs.$field);)+
+            }
+
+            fn sub(&self, rhs: &Self) -> Self {
+                let mut res = *self;
+                $(res.$field = scx_utils::Counters::sub(&self.$field, &rhs.$field);)+
+                res
+            }
+        }
+    };
+}
+
+pub(crate) fn value_from_bytes<T: Copy>(bytes: &[u8]) -> Result<T> {
+    if bytes.len() < std::mem::size_of::<T>() {
+        bail!(
+            "Map value too short ({} < {})",
+            bytes.len(),
+            std::mem::size_of::<T>()
+        );
+    }
+    // Map values are only guaranteed to be 8-byte aligned.
+    Ok(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// Snapshot of the entries of a BPF map. Each key maps to one value per
+/// CPU for per-CPU maps and to a single value otherwise.
+#[derive(Clone, Debug)]
+pub struct MapSnapshot<K, V> {
+    entries: BTreeMap<K, Vec<V>>,
+    at: Instant,
+}
+
+impl<K: Copy + Ord, V: Copy> MapSnapshot<K, V> {
+    /// Read all entries of @map. The key and value sizes must match @K and
+    /// @V.
+    pub fn read(map: &libbpf_rs::Map) -> Result<Self> {
+        if map.key_size() as usize != std::mem::size_of::<K>() {
+            bail!(
+                "Key size mismatch for map {} ({} != {})",
+                map.name(),
+                map.key_size(),
+                std::mem::size_of::<K>()
+            );
+        }
+
+        let percpu = map.map_type().is_percpu();
+        let mut entries = BTreeMap::new();
+
+        for key in map.keys() {
+            let vals = if percpu {
+                map.lookup_percpu(&key, libbpf_rs::MapFlags::ANY)
+            } else {
+                map.lookup(&key, libbpf_rs::MapFlags::ANY)
+                    .map(|val| val.map(|v| vec![v]))
+            }
+            .with_context(|| format!("Failed to lookup map {}", map.name()))?;
+
+            // The entry may have been deleted while iterating.
+            if let Some(vals) = vals {
+                let vals = vals
+                    .iter()
+                    .map(|v| value_from_bytes(v))
+                    .collect::<Result<Vec<V>>>()?;
+                entries.insert(value_from_bytes(&key)?, vals);
+            }
+        }
+
+        Ok(Self {
+            entries,
+            at: Instant::now(),
+        })
+    }
+
+    pub fn from_entries(entries: BTreeMap<K, Vec<V>>) -> Self {
+        Self {
+            entries,
+            at: Instant::now(),
+        }
+    }
+
+    pub fn entries(&self) -> &BTreeMap<K, Vec<V>> {
+        &self.entries
+    }
+
+    /// Values of @key. One per CPU for per-CPU maps.
+    pub fn get(&self, key: &K) -> Option<&[V]> {
+        self.entries.get(key).map(|v| v.as_slice())
+    }
+
+    /// When the snapshot was taken.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+impl<K: Copy + Ord, V: Counters> MapSnapshot<K, V> {
+    /// Subtract @prev from self entry by entry and CPU by CPU. Entries
+    /// which don't exist in @prev are kept as-is.
+    pub fn delta(&self, prev: &Self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .map(|(key, vals)| {
+                let vals = match prev.entries.get(key) {
+                    Some(prev_vals) => vals
+                        .iter()
+                        .zip(prev_vals.iter())
+                        .map(|(v, p)| v.sub(p))
+                        .collect(),
+                    None => vals.clone(),
+                };
+                (*key, vals)
+            })
+            .collect();
+
+        Self {
+            entries,
+            at: self.at,
+        }
+    }
+
+    /// Sum of the values of @key over all CPUs.
+    pub fn sum(&self, key: &K) -> Option<V> {
+        let vals = self.entries.get(key)?;
+        let (first, rest) = vals.split_first()?;
+        let mut sum = *first;
+        for v in rest.iter() {
+            sum.add(v);
+        }
+        Some(sum)
+    }
+
+    /// Sums over all CPUs for all keys.
+    pub fn sums(&self) -> BTreeMap<K, V> {
+        self.entries
+            .keys()
+            .filter_map(|key| Some((*key, self.sum(key)?)))
+            .collect()
+    }
+}
+
+/// Snapshot of a per-CPU array map. Values are stored in a flat vector
+/// indexed by `idx * nr_cpus + cpu`.
+#[derive(Clone, Debug)]
+pub struct PerCpuArraySnapshot<V> {
+    nr_cpus: usize,
+    vals: Vec<V>,
+    at: Instant,
+}
+
+impl<V: Copy> PerCpuArraySnapshot<V> {
+    /// Read all entries of the per-CPU array @map with one batched lookup.
+    /// Falls back to per-index lookups if the kernel doesn't support
+    /// batched operations on the map.
+    pub fn read(map: &libbpf_rs::Map) -> Result<Self> {
+        if map.map_type() != libbpf_rs::MapType::PercpuArray {
+            bail!("Map {} is not a per-CPU array", map.name());
+        }
+        if (map.value_size() as usize) < std::mem::size_of::<V>() {
+            bail!(
+                "Value size mismatch for map {} ({} < {})",
+                map.name(),
+                map.value_size(),
+                std::mem::size_of::<V>()
+            );
+        }
+
+        let nr_cpus = libbpf_rs::num_possible_cpus()?;
+        let nr_entries = map
+            .info()
+            .with_context(|| format!("Failed to read info of map {}", map.name()))?
+            .info
+            .max_entries as usize;
+        // Per-CPU values are 8-byte aligned in the batch buffer.
+        let stride = (map.value_size() as usize + 7) & !7;
+
+        let mut keys = vec![0u32; nr_entries];
+        let mut buf = vec![0u8; nr_entries * nr_cpus * stride];
+        let mut out_batch = 0u32;
+        let mut count = nr_entries as u32;
+        let opts = libbpf_rs::libbpf_sys::bpf_map_batch_opts {
+            sz: std::mem::size_of::<libbpf_rs::libbpf_sys::bpf_map_batch_opts>() as _,
+            ..Default::default()
+        };
+
+        let ret = unsafe {
+            libbpf_rs::libbpf_sys::bpf_map_lookup_batch(
+                map.as_fd().as_raw_fd(),
+                std::ptr::null_mut(),
+                &mut out_batch as *mut u32 as *mut _,
+                keys.as_mut_ptr() as *mut _,
+                buf.as_mut_ptr() as *mut _,
+                &mut count,
+                &opts,
+            )
+        };
+
+        // -ENOENT indicates that the end of the map was reached.
+        if ret < 0 && std::io::Error::from_raw_os_error(-ret).kind() != std::io::ErrorKind::NotFound
+        {
+            return Self::read_by_index(map, nr_entries, nr_cpus);
+        }
+
+        let mut vals = Vec::with_capacity(nr_entries * nr_cpus);
+        for (i, key) in keys.iter().take(count as usize).enumerate() {
+            if *key as usize != i {
+                bail!("Unexpected key {} at {} in map {}", key, i, map.name());
+            }
+            for cpu in 0..nr_cpus {
+                let off = (i * nr_cpus + cpu) * stride;
+                vals.push(value_from_bytes(&buf[off..off + stride])?);
+            }
+        }
+
+        Ok(Self {
+            nr_cpus,
+            vals,
+            at: Instant::now(),
+        })
+    }
+
+    fn read_by_index(map: &libbpf_rs::Map, nr_entries: usize, nr_cpus: usize) -> Result<Self> {
+        let mut vals = Vec::with_capacity(nr_entries * nr_cpus);
+
+        for idx in 0..nr_entries as u32 {
+            let cpu_vals = map
+                .lookup_percpu(&idx.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+                .with_context(|| format!("Failed to lookup {} in map {}", idx, map.name()))?
+                .with_context(|| format!("Index {} missing in map {}", idx, map.name()))?;
+            for val in cpu_vals.iter() {
+                vals.push(value_from_bytes(val)?);
+            }
+        }
+
+        Ok(Self {
+            nr_cpus,
+            vals,
+            at: Instant::now(),
+        })
+    }
+
+    pub fn nr_cpus(&self) -> usize {
+        self.nr_cpus
+    }
+
+    pub fn nr_entries(&self) -> usize {
+        self.vals.len() / self.nr_cpus.max(1)
+    }
+
+    /// Per-CPU values of @idx.
+    pub fn get(&self, idx: usize) -> &[V] {
+        &self.vals[idx * self.nr_cpus..(idx + 1) * self.nr_cpus]
+    }
+
+    /// When the snapshot was taken.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+}
+
+impl<V: Counters> PerCpuArraySnapshot<V> {
+    /// Subtract @prev from self value by value. @prev must be a snapshot
+    /// of the same map.
+    pub fn delta(&self, prev: &Self) -> Self {
+        let vals = self
+            .vals
+            .iter()
+            .zip(prev.vals.iter())
+            .map(|(v, p)| v.sub(p))
+            .collect();
+
+        Self {
+            nr_cpus: self.nr_cpus,
+            vals,
+            at: self.at,
+        }
+    }
+
+    /// Sum of the values of @idx over all CPUs.
+    pub fn sum(&self, idx: usize) -> V {
+        let vals = self.get(idx);
+        let mut sum = vals[0];
+        for v in vals[1..].iter() {
+            sum.add(v);
+        }
+        sum
+    }
+
+    /// Sums over all CPUs for all indices.
+    pub fn sums(&self) -> Vec<V> {
+        (0..self.nr_entries()).map(|idx| self.sum(idx)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    struct Ctx {
+        nr_sched: u64,
+        bkts: [u32; 2],
+        state: u32,
+    }
+    crate::impl_counters!(Ctx, nr_sched, bkts);
+
+    fn ctx(nr_sched: u64, bkt: u32, state: u32) -> Ctx {
+        Ctx {
+            nr_sched,
+            bkts: [bkt, bkt],
+            state,
+        }
+    }
+
+    fn snapshot(entries: Vec<(u32, Vec<Ctx>)>) -> MapSnapshot<u32, Ctx> {
+        MapSnapshot::from_entries(entries.into_iter().collect())
+    }
+
+    #[test]
+    fn test_delta_subtracts_counters() {
+        let prev = snapshot(vec![(0, vec![ctx(1, 1, 1)])]);
+        let cur = snapshot(vec![(0, vec![ctx(5, 3, 3)])]);
+        assert_eq!(cur.delta(&prev).get(&0).unwrap(), &[ctx(4, 2, 3)]);
+    }
+
+    #[test]
+    fn test_delta_wraps_around() {
+        let prev = snapshot(vec![(0, vec![ctx(u64::MAX, u32::MAX, 0)])]);
+        let cur = snapshot(vec![(0, vec![ctx(1, 0, 0)])]);
+        assert_eq!(cur.delta(&prev).get(&0).unwrap(), &[ctx(2, 1, 0)]);
+    }
+
+    #[test]
+    fn test_delta_new_key() {
+        let prev = snapshot(vec![(0, vec![ctx(1, 1, 1)])]);
+        let cur = snapshot(vec![(0, vec![ctx(1, 1, 1)]), (1, vec![ctx(7, 7, 7)])]);
+        assert_eq!(cur.delta(&prev).get(&1).unwrap(), &[ctx(7, 7, 7)]);
+    }
+
+    #[test]
+    fn test_sum_over_cpus() {
+        let snap = snapshot(vec![
+            (0, vec![ctx(4, 2, 3), ctx(2, 0, 4)]),
+            (1, vec![ctx(7, 7, 7)]),
+        ]);
+        // Non-counter fields keep the first CPU's value.
+        assert_eq!(snap.sum(&0), Some(ctx(6, 2, 3)));
+        assert_eq!(snap.sum(&2), None);
+        assert_eq!(snap.sums()[&1], ctx(7, 7, 7));
+    }
+
+    // Two CPUs, values of each index next to each other.
+    fn percpu(vals: Vec<Ctx>) -> PerCpuArraySnapshot<Ctx> {
+        PerCpuArraySnapshot {
+            nr_cpus: 2,
+            vals,
+            at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn test_percpu_layout() {
+        let snap = percpu(vec![ctx(1, 0, 0), ctx(2, 0, 0), ctx(3, 0, 0), ctx(4, 0, 0)]);
+        assert_eq!(snap.nr_entries(), 2);
+        assert_eq!(snap.get(1), &[ctx(3, 0, 0), ctx(4, 0, 0)]);
+    }
+
+    #[test]
+    fn test_percpu_delta() {
+        let prev = percpu(vec![ctx(1, 1, 0), ctx(u64::MAX, 1, 0)]);
+        let cur = percpu(vec![ctx(3, 2, 5), ctx(0, 1, 6)]);
+        assert_eq!(cur.delta(&prev).get(0), &[ctx(2, 1, 5), ctx(1, 0, 6)]);
+    }
+
+    #[test]
+    fn test_percpu_sums() {
+        let snap = percpu(vec![ctx(1, 1, 9), ctx(2, 2, 8), ctx(3, 3, 7), ctx(4, 4, 6)]);
+        assert_eq!(snap.sum(0), ctx(3, 3, 9));
+        assert_eq!(snap.sums(), vec![ctx(3, 3, 9), ctx(7, 7, 7)]);
+    }
+}
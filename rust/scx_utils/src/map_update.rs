@@ -0,0 +1,117 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Batched BPF Map Updates
+//!
+//! Helpers to push large tables, e.g. CPU to domain maps, capacity tables
+//! or preferred core orders, into BPF maps with BPF_MAP_UPDATE_BATCH
+//! instead of one syscall per entry. On kernels which don't support
+//! batched updates for the map type, the entries are updated one by one.
+//! The updated entries can optionally be read back and verified.
+//!
+//! Keys and values are passed as typed slices whose element sizes must
+//! match the map's key and value sizes. As they are copied and compared
+//! byte by byte, the types shouldn't contain implicit padding.
+//!
+//! ```ignore
+//! let caps: Vec<u32> = topo.cpus().values().map(|cpu| cpu.capacity()).collect();
+//! map_update::update_array(skel.maps().cpu_capacity(), &caps, true)?;
+//! ```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+fn as_bytes<T: Copy>(vals: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(vals.as_ptr() as *const u8, std::mem::size_of_val(vals)) }
+}
+
+fn check_sizes<K, V>(map: &libbpf_rs::Map) -> Result<()> {
+    if map.map_type().is_percpu() {
+        bail!("Per-CPU map {} is not supported", map.name());
+    }
+    if map.key_size() as usize != std::mem::size_of::<K>()
+        || map.value_size() as usize != std::mem::size_of::<V>()
+    {
+        bail!(
+            "Key/value size mismatch for map {} ({}/{} != {}/{})",
+            map.name(),
+            map.key_size(),
+            map.value_size(),
+            std::mem::size_of::<K>(),
+            std::mem::size_of::<V>()
+        );
+    }
+    Ok(())
+}
+
+/// Update @map with @keys and @vals using a single batched update. Falls
+/// back to per-entry updates if the batched update fails.
+pub fn update_batch<K: Copy, V: Copy>(map: &libbpf_rs::Map, keys: &[K], vals: &[V]) -> Result<()> {
+    check_sizes::<K, V>(map)?;
+    if keys.len() != vals.len() {
+        bail!("{} keys but {} values", keys.len(), vals.len());
+    }
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let (key_bytes, val_bytes) = (as_bytes(keys), as_bytes(vals));
+    if map
+        .update_batch(
+            key_bytes,
+            val_bytes,
+            keys.len() as u32,
+            libbpf_rs::MapFlags::ANY,
+            libbpf_rs::MapFlags::ANY,
+        )
+        .is_ok()
+    {
+        return Ok(());
+    }
+
+    let (ksz, vsz) = (std::mem::size_of::<K>(), std::mem::size_of::<V>());
+    for (k, v) in key_bytes.chunks_exact(ksz).zip(val_bytes.chunks_exact(vsz)) {
+        map.update(k, v, libbpf_rs::MapFlags::ANY)
+            .with_context(|| format!("Failed to update map {}", map.name()))?;
+    }
+    Ok(())
+}
+
+/// Read back @keys from @map and verify that they hold @vals.
+pub fn verify<K: Copy, V: Copy>(map: &libbpf_rs::Map, keys: &[K], vals: &[V]) -> Result<()> {
+    check_sizes::<K, V>(map)?;
+
+    let (ksz, vsz) = (std::mem::size_of::<K>(), std::mem::size_of::<V>());
+    let (key_bytes, val_bytes) = (as_bytes(keys), as_bytes(vals));
+
+    for (i, (k, v)) in key_bytes
+        .chunks_exact(ksz)
+        .zip(val_bytes.chunks_exact(vsz))
+        .enumerate()
+    {
+        let read = map
+            .lookup(k, libbpf_rs::MapFlags::ANY)
+            .with_context(|| format!("Failed to lookup map {}", map.name()))?;
+        match read {
+            Some(read) if read.as_slice() == v => (),
+            Some(_) => bail!("Entry {} of map {} doesn't match", i, map.name()),
+            None => bail!("Entry {} of map {} is missing", i, map.name()),
+        }
+    }
+    Ok(())
+}
+
+/// Fill the array @map with @vals starting from index 0 and verify the
+/// result if @verify.
+pub fn update_array<V: Copy>(map: &libbpf_rs::Map, vals: &[V], verify: bool) -> Result<()> {
+    let keys: Vec<u32> = (0..vals.len() as u32).collect();
+
+    update_batch(map, &keys, vals)?;
+    if verify {
+        self::verify(map, &keys, vals)?;
+    }
+    Ok(())
+}
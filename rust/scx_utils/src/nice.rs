@@ -0,0 +1,164 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Nice and Latency Nice
+//!
+//! Rust mirror of scx/nice.bpf.h. Nice maps to the weight through
+//! `weight::nice_to_weight()`. Latency nice scales the deadline offset of a
+//! task by the same ~1.25x factor per level, bounded by `LAT_SCALE_MAX` in
+//! either direction, so that the same nice values mean the same thing
+//! across schedulers. The ranges and bounds are shared with BPF through the
+//! generated bindings.
+//!
+//! `task_nice()` and `task_latency_nice()` read the values of a running
+//! task so that userspace load calculations and simulations can use the
+//! same inputs as the BPF side.
+
+use crate::bindings;
+use crate::weight;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+pub const NICE_MIN: i32 = bindings::scx_nice_consts_SCX_NICE_MIN;
+pub const NICE_MAX: i32 = bindings::scx_nice_consts_SCX_NICE_MAX;
+pub const LAT_NICE_MIN: i32 = bindings::scx_nice_consts_SCX_LAT_NICE_MIN;
+pub const LAT_NICE_MAX: i32 = bindings::scx_nice_consts_SCX_LAT_NICE_MAX;
+pub const DFL_PRIO: i32 = bindings::scx_nice_consts_SCX_DFL_PRIO;
+pub const DFL_LATENCY_PRIO: i32 = bindings::scx_nice_consts_SCX_DFL_LATENCY_PRIO;
+pub const LAT_SCALE_MAX: u64 = bindings::scx_nice_consts_SCX_LAT_SCALE_MAX as u64;
+
+/// struct sched_attr including sched_latency_nice from the latency nice
+/// patchset. Kernels without it report a smaller size.
+#[repr(C)]
+#[derive(Default)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+    sched_util_min: u32,
+    sched_util_max: u32,
+    sched_latency_nice: i32,
+}
+
+/// Size of struct sched_attr up to and including sched_latency_nice.
+const SCHED_ATTR_SIZE_LAT: u32 = 60;
+
+/// scx_task_nice(): nice of a task with p->static_prio @prio.
+pub fn prio_to_nice(prio: i32) -> i32 {
+    prio - DFL_PRIO
+}
+
+/// scx_task_latency_nice(): latency nice of a task with p->latency_prio
+/// @latency_prio.
+pub fn latency_prio_to_nice(latency_prio: i32) -> i32 {
+    latency_prio - DFL_LATENCY_PRIO
+}
+
+/// Nice of task @pid.
+pub fn task_nice(pid: i32) -> Result<i32> {
+    let path = format!("/proc/{}/stat", pid);
+    let stat =
+        std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", &path))?;
+
+    // comm may contain spaces, start after its closing parenthesis. nice
+    // is the 19th field and the first field after comm is the 3rd.
+    let rest = match stat.rfind(')') {
+        Some(pos) => &stat[pos + 1..],
+        None => bail!("Failed to parse {}", &path),
+    };
+    match rest.split_whitespace().nth(16).map(|v| v.parse::<i32>()) {
+        Some(Ok(nice)) => Ok(nice),
+        _ => bail!("Failed to parse nice from {}", &path),
+    }
+}
+
+/// Latency nice of task @pid. 0 if the kernel doesn't support latency
+/// nice.
+pub fn task_latency_nice(pid: i32) -> Result<i32> {
+    let mut attr = SchedAttr::default();
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_sched_getattr,
+            pid,
+            &mut attr as *mut SchedAttr,
+            SCHED_ATTR_SIZE_LAT,
+            0,
+        )
+    };
+    if ret < 0 {
+        bail!(
+            "sched_getattr({}) failed ({})",
+            pid,
+            std::io::Error::last_os_error()
+        );
+    }
+
+    if attr.size < SCHED_ATTR_SIZE_LAT {
+        return Ok(0);
+    }
+    Ok(attr.sched_latency_nice)
+}
+
+/// scx_lat_nice_scale(): scale @val, e.g. a deadline offset, by @lat_nice.
+pub fn lat_nice_scale(val: u64, lat_nice: i32) -> u64 {
+    let load = weight::nice_to_load(lat_nice) as u64;
+    let scaled = match val.checked_mul(weight::NICE_0_LOAD) {
+        Some(v) => v / load,
+        None => (val / load).saturating_mul(weight::NICE_0_LOAD),
+    };
+    scaled.clamp(val / LAT_SCALE_MAX, val.saturating_mul(LAT_SCALE_MAX))
+}
+
+/// scx_lat_nice_deadline(): scx_vtime_deadline() adjusted by @lat_nice.
+pub fn lat_nice_deadline(vtime: u64, slice_ns: u64, weight: u32, lat_nice: i32) -> u64 {
+    vtime.wrapping_add(lat_nice_scale(
+        weight::vtime_delta(slice_ns, weight),
+        lat_nice,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lat_nice() {
+        let pid = std::process::id() as i32;
+        assert!((NICE_MIN..=NICE_MAX).contains(&task_nice(pid).unwrap()));
+        assert!((LAT_NICE_MIN..=LAT_NICE_MAX).contains(&task_latency_nice(pid).unwrap()));
+
+        assert_eq!(prio_to_nice(100), NICE_MIN);
+        assert_eq!(prio_to_nice(139), NICE_MAX);
+        assert_eq!(latency_prio_to_nice(0), LAT_NICE_MIN);
+        assert_eq!(latency_prio_to_nice(DFL_LATENCY_PRIO), 0);
+        assert_eq!(latency_prio_to_nice(39), LAT_NICE_MAX);
+
+        assert_eq!(lat_nice_scale(1000, 0), 1000);
+        assert_eq!(lat_nice_scale(1000, -1), 1000 * 1024 / 1277);
+        assert_eq!(lat_nice_scale(1000, 1), 1000 * 1024 / 820);
+        assert_eq!(lat_nice_scale(1000, LAT_NICE_MIN), 1000 / LAT_SCALE_MAX);
+        assert_eq!(lat_nice_scale(1000, LAT_NICE_MAX), 1000 * LAT_SCALE_MAX);
+        assert_eq!(lat_nice_scale(1 << 58, 0), 1 << 58);
+        assert_eq!(
+            lat_nice_scale(1 << 58, LAT_NICE_MAX),
+            (1 << 58) * LAT_SCALE_MAX
+        );
+        assert_eq!(lat_nice_scale(1 << 60, LAT_NICE_MAX), u64::MAX);
+        assert_eq!(
+            lat_nice_scale(1 << 60, LAT_NICE_MIN),
+            (1 << 60) / LAT_SCALE_MAX
+        );
+
+        let dl = |lat_nice| lat_nice_deadline(0, 1000, weight::WEIGHT_DFL, lat_nice);
+        assert_eq!(dl(0), weight::vtime_deadline(0, 1000, weight::WEIGHT_DFL));
+        assert!(dl(-5) < dl(0) && dl(0) < dl(5));
+    }
+}
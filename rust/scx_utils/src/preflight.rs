@@ -0,0 +1,162 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Kernel Preflight Checks
+//!
+//! Validate the running kernel against a scheduler's declared
+//! `KernelRequirements` before loading the BPF scheduler, and report what's
+//! missing along with what to do about it. Otherwise, a missing feature
+//! usually surfaces as a libbpf load or attach error which doesn't tell the
+//! user which kernel option to enable.
+//!
+//! ```ignore
+//! preflight::check(&describe().requires).into_result()?;
+//! ```
+
+use crate::cgroup;
+use crate::compat;
+use crate::sched_info::KernelRequirements;
+use anyhow::bail;
+use anyhow::Result;
+use std::fmt;
+use std::path::Path;
+
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+const SCHED_EXT_STATE: &str = "/sys/kernel/sched_ext/state";
+
+/// The result of a single check. @hint tells the user how to fix a failed
+/// check.
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub what: String,
+    pub ok: bool,
+    pub hint: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, what: String, ok: bool, hint: &str) {
+        self.checks.push(Check {
+            what,
+            ok,
+            hint: hint.to_string(),
+        });
+    }
+
+    pub fn ok(&self) -> bool {
+        self.checks.iter().all(|c| c.ok)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|c| !c.ok)
+    }
+
+    /// Ok if all checks passed. Otherwise, an error listing the failed
+    /// checks.
+    pub fn into_result(self) -> Result<()> {
+        if self.ok() {
+            return Ok(());
+        }
+        bail!("Kernel preflight check failed\n{}", self)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.checks.iter() {
+            if c.ok {
+                writeln!(f, "  [ OK ] {}", c.what)?;
+            } else {
+                writeln!(f, "  [FAIL] {}", c.what)?;
+                writeln!(f, "         {}", c.hint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_controllers(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .map(|s| s.split_whitespace().map(|c| c.to_string()).collect())
+        .unwrap_or_default()
+}
+
+/// Check the running kernel against @req. sched_ext support and vmlinux
+/// BTF are always checked. If BTF isn't available, the kfunc and struct
+/// field checks are reported as failed without looking them up.
+pub fn check(req: &KernelRequirements) -> Report {
+    let mut report = Report::default();
+
+    let has_btf = Path::new(VMLINUX_BTF).exists();
+    report.push(
+        format!("vmlinux BTF ({})", VMLINUX_BTF),
+        has_btf,
+        "Enable CONFIG_DEBUG_INFO_BTF in the kernel config",
+    );
+
+    report.push(
+        "sched_ext support".into(),
+        Path::new(SCHED_EXT_STATE).exists(),
+        "Enable CONFIG_SCHED_CLASS_EXT in the kernel config or boot a sched_ext enabled kernel",
+    );
+
+    for kfunc in req.kfuncs.iter() {
+        let ok = has_btf && compat::kfunc_exists(kfunc).unwrap_or(false);
+        report.push(
+            format!("kfunc {}()", kfunc),
+            ok,
+            "The kernel is too old or built without the feature, upgrade the kernel",
+        );
+    }
+
+    for (type_name, field) in req.struct_fields.iter() {
+        let ok = has_btf && compat::struct_has_field(type_name, field).unwrap_or(false);
+        report.push(
+            format!("struct {}.{}", type_name, field),
+            ok,
+            "The kernel is too old for this scheduler, upgrade the kernel",
+        );
+    }
+
+    if !req.cgroup_controllers.is_empty() {
+        let root = cgroup::cgroup2_root().ok();
+        report.push(
+            "cgroup2 hierarchy".into(),
+            root.is_some(),
+            "Mount cgroup2, e.g. \"mount -t cgroup2 none /sys/fs/cgroup\"",
+        );
+
+        let (avail, enabled) = match root.as_ref() {
+            Some(root) => (
+                read_controllers(&root.join("cgroup.controllers")),
+                read_controllers(&root.join("cgroup.subtree_control")),
+            ),
+            None => (vec![], vec![]),
+        };
+
+        for ctrl in req.cgroup_controllers.iter() {
+            let hint = if !avail.contains(ctrl) {
+                format!("Enable the {} controller in the kernel config", ctrl)
+            } else {
+                format!(
+                    "Enable the controller with \"echo +{} > {}/cgroup.subtree_control\"",
+                    ctrl,
+                    root.as_ref().unwrap().display()
+                )
+            };
+            report.push(
+                format!("cgroup controller {}", ctrl),
+                enabled.contains(ctrl),
+                &hint,
+            );
+        }
+    }
+
+    report
+}
@@ -0,0 +1,464 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Process Sandboxing
+//!
+//! Opt-in hardening for scheduler daemons which run as root and parse
+//! kernel provided data. Once the BPF scheduler is attached, `Sandbox`
+//! confines the process:
+//!
+//! - no_new_privs is set.
+//! - Landlock limits the filesystem to reading /proc and /sys plus the
+//!   paths added with `allow_read()` and `allow_write()`. Landlock applies
+//!   to the calling thread and its future children only, so apply from the
+//!   thread which runs the scheduler.
+//! - A dangerous syscall denylist, installed as a seccomp filter
+//!   synchronized to all threads, fails syscalls no scheduler needs and an
+//!   attacker would want - exec, ptrace, mount, module loading, reboot and
+//!   namespace changes - with EPERM. bpf() is left alone so the scheduler
+//!   can keep accessing its maps and reload itself.
+//!
+//! The denylist is not a minimal syscall surface. Everything which isn't on
+//! it stays allowed. An allowlist would have to cover whatever libbpf, the
+//! allocator and libc happen to use on each architecture and kernel, and
+//! would break schedulers in the field whenever one of them changes. The
+//! filesystem, which is where most of the kernel provided data comes from,
+//! is confined by landlock instead.
+//!
+//! Landlock and the syscall denylist are skipped with a warning if the
+//! kernel doesn't support them.
+//!
+//! ```ignore
+//! let mut sched = Scheduler::init(&opts)?;
+//! Sandbox::new().allow_read(&config_path).apply()?;
+//! ```
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use log::info;
+use log::warn;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+// Landlock ABI v1 filesystem access rights
+const LANDLOCK_ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const LANDLOCK_ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const LANDLOCK_ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const LANDLOCK_ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const LANDLOCK_ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const LANDLOCK_ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const LANDLOCK_ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const LANDLOCK_ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const LANDLOCK_ACCESS_FS_ALL_V1: u64 = (1 << 13) - 1;
+
+const LANDLOCK_FILE_ACCESS: u64 =
+    LANDLOCK_ACCESS_FS_EXECUTE | LANDLOCK_ACCESS_FS_WRITE_FILE | LANDLOCK_ACCESS_FS_READ_FILE;
+const LANDLOCK_READ_ACCESS: u64 = LANDLOCK_ACCESS_FS_READ_FILE | LANDLOCK_ACCESS_FS_READ_DIR;
+const LANDLOCK_WRITE_ACCESS: u64 = LANDLOCK_READ_ACCESS
+    | LANDLOCK_ACCESS_FS_WRITE_FILE
+    | LANDLOCK_ACCESS_FS_REMOVE_DIR
+    | LANDLOCK_ACCESS_FS_REMOVE_FILE
+    | LANDLOCK_ACCESS_FS_MAKE_DIR
+    | LANDLOCK_ACCESS_FS_MAKE_REG;
+
+#[repr(C)]
+struct LandlockRulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct LandlockPathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+// Classic BPF and seccomp constants from linux/filter.h and linux/seccomp.h
+const BPF_LD_W_ABS: u16 = 0x20; // BPF_LD | BPF_W | BPF_ABS
+const BPF_JMP_JEQ_K: u16 = 0x15; // BPF_JMP | BPF_JEQ | BPF_K
+const BPF_JMP_JGE_K: u16 = 0x35; // BPF_JMP | BPF_JGE | BPF_K
+const BPF_RET_K: u16 = 0x06; // BPF_RET | BPF_K
+
+const SECCOMP_SET_MODE_FILTER: u32 = 1;
+const SECCOMP_FILTER_FLAG_TSYNC: u32 = 1 << 0;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+// offsets into struct seccomp_data
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+// x32 syscalls on x86_64 have this bit set and must not bypass the filter
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+// Syscalls which a compromised scheduler could use to escalate or persist
+const DANGEROUS_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+    libc::SYS_setns,
+    libc::SYS_unshare,
+    libc::SYS_open_by_handle_at,
+];
+
+#[repr(C)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+fn stmt(code: u16, k: u32) -> SockFilter {
+    SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+fn seccomp_filter(arch: u32) -> Vec<SockFilter> {
+    let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let mut prog = vec![
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+        jump(BPF_JMP_JEQ_K, arch, 1, 0),
+        stmt(BPF_RET_K, deny),
+        stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+    ];
+    if let Some(bit) = X32_SYSCALL_BIT {
+        prog.push(jump(BPF_JMP_JGE_K, bit, 0, 1));
+        prog.push(stmt(BPF_RET_K, deny));
+    }
+    for nr in DANGEROUS_SYSCALLS.iter() {
+        prog.push(jump(BPF_JMP_JEQ_K, *nr as u32, 0, 1));
+        prog.push(stmt(BPF_RET_K, deny));
+    }
+    prog.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+    prog
+}
+
+/// What `Sandbox::apply()` managed to enable.
+#[derive(Clone, Debug, Default)]
+pub struct SandboxStatus {
+    /// Landlock ABI version if landlock is in effect.
+    pub landlock_abi: Option<u32>,
+    /// Whether the dangerous syscall denylist is in effect.
+    pub syscall_denylist: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    read_paths: Vec<PathBuf>,
+    write_paths: Vec<PathBuf>,
+    landlock: bool,
+    syscall_denylist: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self {
+            read_paths: vec!["/proc".into(), "/sys".into()],
+            write_paths: vec![],
+            landlock: true,
+            syscall_denylist: true,
+        }
+    }
+
+    /// Allow reading @path and, if it's a directory, everything beneath it.
+    pub fn allow_read<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.read_paths.push(path.as_ref().into());
+        self
+    }
+
+    /// Allow reading, writing, creating and removing files at and beneath
+    /// @path, e.g. a crash report directory.
+    pub fn allow_write<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.write_paths.push(path.as_ref().into());
+        self
+    }
+
+    pub fn landlock(mut self, enable: bool) -> Self {
+        self.landlock = enable;
+        self
+    }
+
+    /// Whether to install the dangerous syscall denylist. See the module
+    /// documentation.
+    pub fn syscall_denylist(mut self, enable: bool) -> Self {
+        self.syscall_denylist = enable;
+        self
+    }
+
+    fn landlock_abi() -> Option<u32> {
+        let abi = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<LandlockRulesetAttr>(),
+                0,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        if abi > 0 {
+            Some(abi as u32)
+        } else {
+            None
+        }
+    }
+
+    fn add_rule(ruleset_fd: i32, path: &Path, access: u64) -> Result<()> {
+        let file = match std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", path)),
+        };
+        let is_dir = file.metadata().map(|md| md.is_dir()).unwrap_or(false);
+
+        let attr = LandlockPathBeneathAttr {
+            allowed_access: if is_dir {
+                access
+            } else {
+                access & LANDLOCK_FILE_ACCESS
+            },
+            parent_fd: file.as_raw_fd(),
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset_fd,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &attr as *const LandlockPathBeneathAttr,
+                0,
+            )
+        };
+        if ret < 0 {
+            bail!(
+                "Failed to add landlock rule for {:?} ({})",
+                path,
+                std::io::Error::last_os_error()
+            );
+        }
+        Ok(())
+    }
+
+    fn apply_landlock(&self) -> Result<Option<u32>> {
+        let abi = match Self::landlock_abi() {
+            Some(abi) => abi,
+            None => {
+                warn!("Landlock is not supported by the kernel, skipping");
+                return Ok(None);
+            }
+        };
+
+        let attr = LandlockRulesetAttr {
+            handled_access_fs: LANDLOCK_ACCESS_FS_ALL_V1,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const LandlockRulesetAttr,
+                std::mem::size_of::<LandlockRulesetAttr>(),
+                0,
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to create landlock ruleset ({})",
+                std::io::Error::last_os_error()
+            );
+        }
+        let fd = fd as i32;
+
+        let result = (|| {
+            for path in self.read_paths.iter() {
+                Self::add_rule(fd, path, LANDLOCK_READ_ACCESS)?;
+            }
+            for path in self.write_paths.iter() {
+                Self::add_rule(fd, path, LANDLOCK_WRITE_ACCESS)?;
+            }
+            let ret = unsafe { libc::syscall(libc::SYS_landlock_restrict_self, fd, 0) };
+            if ret < 0 {
+                bail!(
+                    "Failed to apply landlock ruleset ({})",
+                    std::io::Error::last_os_error()
+                );
+            }
+            Ok(())
+        })();
+
+        unsafe { libc::close(fd) };
+        result.map(|_| Some(abi))
+    }
+
+    fn apply_syscall_denylist(&self) -> Result<bool> {
+        let arch = match AUDIT_ARCH {
+            Some(arch) => arch,
+            None => {
+                warn!("seccomp filter is not supported on this architecture, skipping");
+                return Ok(false);
+            }
+        };
+
+        let filter = seccomp_filter(arch);
+        let prog = SockFprog {
+            len: filter.len() as u16,
+            filter: filter.as_ptr(),
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                SECCOMP_SET_MODE_FILTER,
+                SECCOMP_FILTER_FLAG_TSYNC,
+                &prog as *const SockFprog,
+            )
+        };
+        if ret != 0 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::EINVAL) || err.raw_os_error() == Some(libc::ENOSYS)
+            {
+                warn!("seccomp filter is not supported by the kernel, skipping");
+                return Ok(false);
+            }
+            bail!("Failed to install seccomp filter ({})", err);
+        }
+        Ok(true)
+    }
+
+    /// Confine the process. This can't be undone.
+    pub fn apply(self) -> Result<SandboxStatus> {
+        let ret = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+        if ret != 0 {
+            bail!(
+                "Failed to set no_new_privs ({})",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        let mut status = SandboxStatus::default();
+        if self.landlock {
+            status.landlock_abi = self.apply_landlock()?;
+        }
+        if self.syscall_denylist {
+            status.syscall_denylist = self.apply_syscall_denylist()?;
+        }
+
+        info!(
+            "Sandbox applied (landlock={} syscall_denylist={})",
+            status
+                .landlock_abi
+                .map_or("off".to_string(), |abi| format!("v{}", abi)),
+            if status.syscall_denylist { "on" } else { "off" }
+        );
+        Ok(status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run the classic BPF filter @prog against a syscall.
+    fn run_filter(prog: &[SockFilter], arch: u32, nr: u32) -> u32 {
+        let mut acc = 0;
+        let mut pc = 0;
+        loop {
+            let insn = &prog[pc];
+            pc += 1;
+            match insn.code {
+                BPF_LD_W_ABS if insn.k == SECCOMP_DATA_ARCH => acc = arch,
+                BPF_LD_W_ABS if insn.k == SECCOMP_DATA_NR => acc = nr,
+                BPF_JMP_JEQ_K | BPF_JMP_JGE_K => {
+                    let taken = match insn.code {
+                        BPF_JMP_JEQ_K => acc == insn.k,
+                        _ => acc >= insn.k,
+                    };
+                    let off = if taken { insn.jt } else { insn.jf };
+                    pc += off as usize;
+                }
+                BPF_RET_K => return insn.k,
+                _ => panic!("unexpected instruction {:#x}", insn.code),
+            }
+        }
+    }
+
+    #[test]
+    fn test_syscall_denylist() {
+        let arch = match AUDIT_ARCH {
+            Some(arch) => arch,
+            None => return,
+        };
+        let prog = seccomp_filter(arch);
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+        for nr in DANGEROUS_SYSCALLS.iter() {
+            assert_eq!(run_filter(&prog, arch, *nr as u32), deny);
+        }
+        for nr in [libc::SYS_read, libc::SYS_bpf, libc::SYS_openat] {
+            assert_eq!(run_filter(&prog, arch, nr as u32), SECCOMP_RET_ALLOW);
+        }
+    }
+
+    #[test]
+    fn test_syscall_denylist_foreign_abi() {
+        let arch = match AUDIT_ARCH {
+            Some(arch) => arch,
+            None => return,
+        };
+        let prog = seccomp_filter(arch);
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+        assert_eq!(run_filter(&prog, !arch, libc::SYS_read as u32), deny);
+        if let Some(bit) = X32_SYSCALL_BIT {
+            assert_eq!(run_filter(&prog, arch, bit | libc::SYS_read as u32), deny);
+        }
+    }
+}
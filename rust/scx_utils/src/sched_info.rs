@@ -0,0 +1,178 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Scheduler Self-Description
+//!
+//! `SchedulerInfo` describes a scheduler in a machine readable form - name,
+//! version, tunables, supported modes and the kernel features it requires -
+//! so that tools like scx_loader and scxctl can present the right options
+//! for each installed scheduler instead of hardcoding them. Schedulers
+//! print it as JSON when invoked with `--describe`.
+//!
+//! The tunables are usually generated from the scheduler's clap options.
+//!
+//! ```ignore
+//! let info = SchedulerInfo::from_clap(&Opts::command(), env!("CARGO_PKG_VERSION"))
+//!     .mode("partial", "Only switch SCHED_EXT tasks", &["--partial"])
+//!     .kfunc("scx_bpf_dispatch_vtime");
+//! println!("{}", info.to_json()?);
+//! ```
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A command line option of a scheduler.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tunable {
+    /// Long option name without the leading dashes.
+    pub name: String,
+    pub short: Option<char>,
+    pub help: String,
+    /// Whether the option takes a value. If not, it's a flag.
+    pub takes_value: bool,
+    pub default: Vec<String>,
+    /// Environment variable which overrides the default.
+    pub env: Option<String>,
+}
+
+/// A named preset of options, e.g. "partial" for `--partial`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Mode {
+    pub name: String,
+    pub help: String,
+    pub args: Vec<String>,
+}
+
+/// Kernel features the scheduler needs on top of sched_ext itself.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct KernelRequirements {
+    pub kfuncs: Vec<String>,
+    /// (struct name, field name) pairs which must exist in the kernel BTF.
+    pub struct_fields: Vec<(String, String)>,
+    pub cgroup_controllers: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SchedulerInfo {
+    pub name: String,
+    pub version: String,
+    pub description: String,
+    pub tunables: Vec<Tunable>,
+    pub modes: Vec<Mode>,
+    pub requires: KernelRequirements,
+}
+
+/// Describe the options of @cmd. Hidden options and `--describe` itself
+/// are skipped.
+pub fn tunables_from_clap(cmd: &clap::Command) -> Vec<Tunable> {
+    cmd.get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+        .filter(|arg| arg.get_id() != "describe")
+        .map(|arg| Tunable {
+            name: arg
+                .get_long()
+                .unwrap_or_else(|| arg.get_id().as_str())
+                .to_string(),
+            short: arg.get_short(),
+            help: arg.get_help().map(|h| h.to_string()).unwrap_or_default(),
+            takes_value: arg.get_action().takes_values(),
+            default: arg
+                .get_default_values()
+                .iter()
+                .map(|v| v.to_string_lossy().to_string())
+                .collect(),
+            env: arg.get_env().map(|e| e.to_string_lossy().to_string()),
+        })
+        .collect()
+}
+
+impl SchedulerInfo {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Describe the scheduler from its clap command. The name and
+    /// description come from the command's name and about text.
+    pub fn from_clap(cmd: &clap::Command, version: &str) -> Self {
+        Self {
+            description: cmd.get_about().map(|a| a.to_string()).unwrap_or_default(),
+            tunables: tunables_from_clap(cmd),
+            ..Self::new(cmd.get_name(), version)
+        }
+    }
+
+    pub fn mode(mut self, name: &str, help: &str, args: &[&str]) -> Self {
+        self.modes.push(Mode {
+            name: name.into(),
+            help: help.into(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        });
+        self
+    }
+
+    pub fn kfunc(mut self, kfunc: &str) -> Self {
+        self.requires.kfuncs.push(kfunc.into());
+        self
+    }
+
+    pub fn struct_field(mut self, type_name: &str, field: &str) -> Self {
+        self.requires
+            .struct_fields
+            .push((type_name.into(), field.into()));
+        self
+    }
+
+    pub fn cgroup_controller(mut self, controller: &str) -> Self {
+        self.requires.cgroup_controllers.push(controller.into());
+        self
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use clap::Parser;
+
+    /// A test scheduler.
+    #[derive(Parser)]
+    #[clap(name = "scx_test")]
+    struct Opts {
+        /// Slice in usecs.
+        #[clap(short = 's', long, default_value = "20000")]
+        slice_us: u64,
+
+        /// Only switch SCHED_EXT tasks.
+        #[clap(long, action = clap::ArgAction::SetTrue)]
+        partial: bool,
+    }
+
+    #[test]
+    fn test_from_clap() {
+        let info = SchedulerInfo::from_clap(&Opts::command(), "1.0")
+            .mode("partial", "Only switch SCHED_EXT tasks", &["--partial"])
+            .kfunc("scx_bpf_dispatch_vtime");
+        assert_eq!(info.name, "scx_test");
+        assert_eq!(info.description, "A test scheduler");
+        assert_eq!(info.tunables.len(), 2);
+        assert_eq!(info.tunables[0].name, "slice-us");
+        assert_eq!(info.tunables[0].short, Some('s'));
+        assert_eq!(info.tunables[0].default, vec!["20000".to_string()]);
+        assert!(info.tunables[0].takes_value);
+        assert!(!info.tunables[1].takes_value);
+
+        let parsed: SchedulerInfo = serde_json::from_str(&info.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, info);
+    }
+}
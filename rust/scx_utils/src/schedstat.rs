@@ -0,0 +1,404 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Per-Task Run Delay
+//!
+//! Whether a policy change helps a workload mostly comes down to whether
+//! its tasks wait less to run. `SchedstatSampler` reads
+//! /proc/PID/task/TID/schedstat, the time spent running and waiting on a
+//! runqueue, for a set of tasks. Each sample reports the per-task deltas
+//! since the previous one. The read buffer is reused across tasks and
+//! samples, and tasks which exited are dropped automatically.
+//!
+//! ```ignore
+//! let mut sampler = SchedstatSampler::new();
+//! sampler.add_process(pid)?;
+//! loop {
+//!     std::thread::sleep(Duration::from_secs(1));
+//!     for (tid, delta) in sampler.sample().iter() {
+//!         info!("{}: run {}ms wait {}ms", tid, delta.run_ns / 1_000_000,
+//!               delta.wait_ns / 1_000_000);
+//!     }
+//! }
+//! ```
+//!
+//! `Taskstats` queries the taskstats delay accounting over generic
+//! netlink, which breaks the delays down further into CPU, block IO, swap
+//! in, memory reclaim and thrashing. This requires CONFIG_TASK_DELAY_ACCT
+//! and delay accounting enabled with the kernel.task_delayacct sysctl or
+//! the delayacct boot parameter.
+
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Contents of /proc/PID/schedstat.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Schedstat {
+    /// Time spent running.
+    pub run_ns: u64,
+    /// Time spent runnable waiting on a runqueue.
+    pub wait_ns: u64,
+    /// Number of times the task was scheduled in.
+    pub nr_slices: u64,
+}
+
+impl Schedstat {
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut vals = text.split_whitespace().map(|v| v.parse::<u64>());
+        Some(Self {
+            run_ns: vals.next()?.ok()?,
+            wait_ns: vals.next()?.ok()?,
+            nr_slices: vals.next()?.ok()?,
+        })
+    }
+
+    pub fn read(pid: i32) -> Result<Self> {
+        let path = format!("/proc/{}/schedstat", pid);
+        let text =
+            std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", &path))?;
+        match Self::parse(&text) {
+            Some(stat) => Ok(stat),
+            None => bail!("Failed to parse {}", &path),
+        }
+    }
+
+    /// Average wait per scheduling. 0 if the task was never scheduled.
+    pub fn avg_wait_ns(&self) -> u64 {
+        self.wait_ns.checked_div(self.nr_slices).unwrap_or(0)
+    }
+
+    fn delta(&self, prev: &Self) -> Self {
+        Self {
+            run_ns: self.run_ns.saturating_sub(prev.run_ns),
+            wait_ns: self.wait_ns.saturating_sub(prev.wait_ns),
+            nr_slices: self.nr_slices.saturating_sub(prev.nr_slices),
+        }
+    }
+}
+
+/// Samples Schedstat of a set of tasks, see the module documentation.
+#[derive(Debug, Default)]
+pub struct SchedstatSampler {
+    /// (pid, tid) -> the last sample.
+    tasks: BTreeMap<(i32, i32), Option<Schedstat>>,
+    buf: String,
+}
+
+impl SchedstatSampler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Track thread @tid of process @pid.
+    pub fn add_task(&mut self, pid: i32, tid: i32) {
+        self.tasks.entry((pid, tid)).or_insert(None);
+    }
+
+    /// Track all the current threads of process @pid.
+    pub fn add_process(&mut self, pid: i32) -> Result<()> {
+        let path = format!("/proc/{}/task", pid);
+        for entry in
+            std::fs::read_dir(&path).with_context(|| format!("Failed to read {}", &path))?
+        {
+            if let Some(tid) = entry?.file_name().to_str().and_then(|t| t.parse().ok()) {
+                self.add_task(pid, tid);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove_task(&mut self, pid: i32, tid: i32) {
+        self.tasks.remove(&(pid, tid));
+    }
+
+    pub fn nr_tasks(&self) -> usize {
+        self.tasks.len()
+    }
+
+    fn read_into(buf: &mut String, pid: i32, tid: i32) -> Option<Schedstat> {
+        let path = format!("/proc/{}/task/{}/schedstat", pid, tid);
+        buf.clear();
+        std::fs::File::open(path).ok()?.read_to_string(buf).ok()?;
+        Schedstat::parse(buf)
+    }
+
+    /// Read all the tracked tasks and return the delta since the previous
+    /// sample keyed by tid. Tasks sampled for the first time report their
+    /// whole lifetime. Tasks which exited are dropped.
+    pub fn sample(&mut self) -> BTreeMap<i32, Schedstat> {
+        let mut deltas = BTreeMap::new();
+        let buf = &mut self.buf;
+        self.tasks.retain(|(pid, tid), last| {
+            let cur = match Self::read_into(buf, *pid, *tid) {
+                Some(cur) => cur,
+                None => return false,
+            };
+            let delta = match last {
+                Some(last) => cur.delta(last),
+                None => cur,
+            };
+            deltas.insert(*tid, delta);
+            *last = Some(cur);
+            true
+        });
+        deltas
+    }
+}
+
+/// Delay accounting of a task from taskstats. Delays are totals in nsecs
+/// and counts are the number of delays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DelayStats {
+    pub version: u16,
+    /// Waiting for a CPU while runnable.
+    pub cpu_count: u64,
+    pub cpu_delay_ns: u64,
+    /// Waiting for synchronous block IO.
+    pub blkio_count: u64,
+    pub blkio_delay_ns: u64,
+    /// Waiting for pages to be swapped in.
+    pub swapin_count: u64,
+    pub swapin_delay_ns: u64,
+    /// Voluntary and involuntary context switches.
+    pub nvcsw: u64,
+    pub nivcsw: u64,
+    /// Memory reclaim.
+    pub freepages_count: u64,
+    pub freepages_delay_ns: u64,
+    /// Refaulting recently evicted pages.
+    pub thrashing_count: u64,
+    pub thrashing_delay_ns: u64,
+}
+
+// Generic netlink constants from linux/genetlink.h and linux/taskstats.h.
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+const TASKSTATS_GENL_NAME: &str = "TASKSTATS";
+const TASKSTATS_CMD_GET: u8 = 1;
+const TASKSTATS_CMD_ATTR_PID: u16 = 1;
+const TASKSTATS_TYPE_STATS: u16 = 3;
+const TASKSTATS_TYPE_AGGR_PID: u16 = 4;
+
+const NLMSG_HDRLEN: usize = 16;
+const GENL_HDRLEN: usize = 4;
+const NLA_HDRLEN: usize = 4;
+
+fn nla_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u16_at(buf: &[u8], off: usize) -> u16 {
+    u16::from_ne_bytes([buf[off], buf[off + 1]])
+}
+
+fn u32_at(buf: &[u8], off: usize) -> u32 {
+    u32::from_ne_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+/// Iterate the netlink attributes in @buf as (type, payload).
+fn nla_iter(mut buf: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = u16_at(buf, 0) as usize;
+        if len < NLA_HDRLEN || len > buf.len() {
+            return None;
+        }
+        let attr = (u16_at(buf, 2) & 0x3fff, &buf[NLA_HDRLEN..len]);
+        buf = &buf[nla_align(len).min(buf.len())..];
+        Some(attr)
+    })
+}
+
+/// Parse the relevant fields of struct taskstats. Fields added after
+/// @stats' version read as 0.
+fn parse_taskstats(stats: &[u8]) -> DelayStats {
+    let u64_at = |off: usize| match stats.get(off..off + 8) {
+        Some(b) => u64::from_ne_bytes(b.try_into().unwrap()),
+        None => 0,
+    };
+    DelayStats {
+        version: if stats.len() >= 2 {
+            u16_at(stats, 0)
+        } else {
+            0
+        },
+        cpu_count: u64_at(16),
+        cpu_delay_ns: u64_at(24),
+        blkio_count: u64_at(32),
+        blkio_delay_ns: u64_at(40),
+        swapin_count: u64_at(48),
+        swapin_delay_ns: u64_at(56),
+        nvcsw: u64_at(272),
+        nivcsw: u64_at(280),
+        freepages_count: u64_at(312),
+        freepages_delay_ns: u64_at(320),
+        thrashing_count: u64_at(328),
+        thrashing_delay_ns: u64_at(336),
+    }
+}
+
+/// Generic netlink socket for taskstats queries.
+pub struct Taskstats {
+    fd: i32,
+    family: u16,
+    seq: u32,
+    buf: Vec<u8>,
+}
+
+impl Taskstats {
+    pub fn new() -> Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_GENERIC,
+            )
+        };
+        if fd < 0 {
+            bail!(
+                "Failed to open generic netlink socket ({})",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        let mut ts = Self {
+            fd,
+            family: GENL_ID_CTRL,
+            seq: 0,
+            buf: vec![0; 8192],
+        };
+
+        let mut name = TASKSTATS_GENL_NAME.as_bytes().to_vec();
+        name.push(0);
+        let reply = ts
+            .request(
+                GENL_ID_CTRL,
+                CTRL_CMD_GETFAMILY,
+                CTRL_ATTR_FAMILY_NAME,
+                &name,
+            )
+            .context("taskstats isn't available, is CONFIG_TASKSTATS enabled?")?;
+        ts.family = match nla_iter(&reply).find(|(t, _)| *t == CTRL_ATTR_FAMILY_ID) {
+            Some((_, id)) if id.len() >= 2 => u16_at(id, 0),
+            _ => bail!("Failed to resolve the taskstats netlink family"),
+        };
+        Ok(ts)
+    }
+
+    /// Send a generic netlink request with a single attribute and return
+    /// the attributes of the reply.
+    fn request(&mut self, family: u16, cmd: u8, attr: u16, payload: &[u8]) -> Result<Vec<u8>> {
+        self.seq += 1;
+        let attr_len = NLA_HDRLEN + payload.len();
+        let len = NLMSG_HDRLEN + GENL_HDRLEN + nla_align(attr_len);
+
+        let mut msg = Vec::with_capacity(len);
+        msg.extend_from_slice(&(len as u32).to_ne_bytes());
+        msg.extend_from_slice(&family.to_ne_bytes());
+        msg.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        msg.extend_from_slice(&self.seq.to_ne_bytes());
+        msg.extend_from_slice(&0u32.to_ne_bytes());
+        msg.extend_from_slice(&[cmd, 1, 0, 0]);
+        msg.extend_from_slice(&(attr_len as u16).to_ne_bytes());
+        msg.extend_from_slice(&attr.to_ne_bytes());
+        msg.extend_from_slice(payload);
+        msg.resize(len, 0);
+
+        let ret = unsafe { libc::send(self.fd, msg.as_ptr() as *const _, msg.len(), 0) };
+        if ret < 0 {
+            bail!("netlink send failed ({})", std::io::Error::last_os_error());
+        }
+
+        loop {
+            let ret =
+                unsafe { libc::recv(self.fd, self.buf.as_mut_ptr() as *mut _, self.buf.len(), 0) };
+            if ret < 0 {
+                bail!("netlink recv failed ({})", std::io::Error::last_os_error());
+            }
+            let reply = &self.buf[..ret as usize];
+            if reply.len() < NLMSG_HDRLEN {
+                bail!("Truncated netlink reply");
+            }
+
+            let msg_len = (u32_at(reply, 0) as usize).min(reply.len());
+            let msg_type = u16_at(reply, 4);
+            if u32_at(reply, 8) != self.seq {
+                continue;
+            }
+            if msg_type == libc::NLMSG_ERROR as u16 {
+                let err = -(u32_at(reply, NLMSG_HDRLEN) as i32);
+                bail!("netlink error ({})", std::io::Error::from_raw_os_error(err));
+            }
+            if msg_len < NLMSG_HDRLEN + GENL_HDRLEN {
+                bail!("Truncated netlink reply");
+            }
+            return Ok(reply[NLMSG_HDRLEN + GENL_HDRLEN..msg_len].to_vec());
+        }
+    }
+
+    /// Delay accounting of thread @tid.
+    pub fn get(&mut self, tid: i32) -> Result<DelayStats> {
+        let reply = self
+            .request(
+                self.family,
+                TASKSTATS_CMD_GET,
+                TASKSTATS_CMD_ATTR_PID,
+                &(tid as u32).to_ne_bytes(),
+            )
+            .with_context(|| format!("Failed to query taskstats of {}", tid))?;
+
+        for (ty, aggr) in nla_iter(&reply) {
+            if ty != TASKSTATS_TYPE_AGGR_PID {
+                continue;
+            }
+            if let Some((_, stats)) = nla_iter(aggr).find(|(t, _)| *t == TASKSTATS_TYPE_STATS) {
+                return Ok(parse_taskstats(stats));
+            }
+        }
+        bail!("No taskstats in the reply for {}", tid)
+    }
+}
+
+impl Drop for Taskstats {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedstat() {
+        assert_eq!(
+            Schedstat::parse("1000 300 3\n"),
+            Some(Schedstat {
+                run_ns: 1000,
+                wait_ns: 300,
+                nr_slices: 3
+            })
+        );
+        assert_eq!(Schedstat::parse("1000 300\n"), None);
+
+        let pid = std::process::id() as i32;
+        let mut sampler = SchedstatSampler::new();
+        sampler.add_process(pid).unwrap();
+        sampler.add_task(pid, -1);
+        assert!(sampler.nr_tasks() >= 2);
+
+        // The bogus task is dropped on the first sample.
+        assert!(sampler.sample().contains_key(&pid));
+        assert!(sampler.sample().contains_key(&pid));
+        assert!(!sampler.tasks.contains_key(&(pid, -1)));
+    }
+}
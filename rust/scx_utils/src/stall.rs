@@ -0,0 +1,367 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Stall Fingerprinting
+//!
+//! A single stall dump says little about whether the stall is a one-off or
+//! the same bug hitting over and over again. `StallFingerprint` reduces a
+//! stall exit dump to what identifies the stall - the stalled CPU, the DSQ
+//! the task was queued on, the classes of the stalled and running tasks and
+//! the top of the stalled task's backtrace. `StallHistory` keeps the
+//! fingerprints of recent stalls in the scheduler's `StateStore` so that
+//! they survive restarts and compares each new stall against them.
+//!
+//! ```ignore
+//! let history = StallHistory::new("scx_foo");
+//! let uei = uei_read!(&skel, uei);
+//! uei.report_with_stall_history(&history)
+//! ```
+
+use crate::ExitDump;
+use crate::ScxExitKind;
+use crate::StateStore;
+use crate::UserExitInfo;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// Number of fingerprints to keep by default.
+const DFL_NR_KEEP: usize = 64;
+
+/// Format version of the saved fingerprints, see StateStore.
+const STALLS_VERSION: u32 = 1;
+
+/// Name of the fingerprints in the StateStore.
+const STALLS_NAME: &str = "stalls";
+
+/// Number of backtrace frames included in the fingerprint.
+const NR_STACK_FRAMES: usize = 3;
+
+/// The compact identity of a stall.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StallFingerprint {
+    /// When the stall happened in seconds since the epoch.
+    pub at: u64,
+    pub cpu: u32,
+    /// The DSQ the stalled task was queued on if known.
+    pub dsq_id: Option<u64>,
+    /// The stalled task's comm with digits masked, e.g. "kworker/u#:#".
+    pub task_class: String,
+    /// The class of the task which was running on the stalled CPU.
+    pub curr_class: Option<String>,
+    /// The top frames of the stalled task's backtrace without offsets.
+    pub stack: Vec<String>,
+}
+
+/// Mask the digits in @comm so that e.g. the per-CPU instances of a kernel
+/// thread fall into the same class.
+fn comm_class(comm: &str) -> String {
+    let mut class = String::new();
+    for c in comm.chars() {
+        if !c.is_ascii_digit() {
+            class.push(c);
+        } else if !class.ends_with('#') {
+            class.push('#');
+        }
+    }
+    class
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl StallFingerprint {
+    /// Fingerprint the stall in @dump. None if the dump doesn't list any
+    /// waiting task.
+    pub fn from_dump(dump: &ExitDump) -> Option<Self> {
+        let (cpu, task) = dump.longest_waiting()?;
+
+        let dsq_id = task
+            .info
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .find_map(|kv| kv.strip_prefix("dsq_id=0x"))
+            .and_then(|v| u64::from_str_radix(v, 16).ok());
+
+        let stack = task
+            .backtrace
+            .iter()
+            .take(NR_STACK_FRAMES)
+            .map(|frame| frame.split('+').next().unwrap_or(frame).to_string())
+            .collect();
+
+        Some(Self {
+            at: unix_secs(),
+            cpu: cpu.cpu,
+            dsq_id,
+            task_class: comm_class(&task.comm),
+            curr_class: cpu.curr.as_ref().map(|(comm, _)| comm_class(comm)),
+            stack,
+        })
+    }
+
+    /// Whether @self and @other look like the same stall. The CPU isn't
+    /// considered as the same bug can trigger on any CPU.
+    pub fn same_signature(&self, other: &Self) -> bool {
+        self.dsq_id == other.dsq_id
+            && self.task_class == other.task_class
+            && self.stack == other.stack
+    }
+}
+
+impl fmt::Display for StallFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU {} task={}", self.cpu, self.task_class)?;
+        if let Some(dsq_id) = self.dsq_id {
+            write!(f, " dsq=0x{:x}", dsq_id)?;
+        }
+        if let Some(curr) = &self.curr_class {
+            write!(f, " curr={}", curr)?;
+        }
+        if !self.stack.is_empty() {
+            write!(f, " stack={}", self.stack.join("<"))?;
+        }
+        Ok(())
+    }
+}
+
+/// The result of comparing a stall against the earlier ones.
+#[derive(Clone, Debug, Default)]
+pub struct StallAnalysis {
+    pub fingerprint: StallFingerprint,
+    /// Number of earlier stalls in the history.
+    pub nr_prev: usize,
+    /// Number of earlier stalls with the same signature.
+    pub nr_same: usize,
+    /// Number of earlier stalls with the same signature on the same CPU.
+    pub nr_same_cpu: usize,
+    /// Number of earlier stalls of the same task class.
+    pub nr_same_class: usize,
+    /// When the first stall with the same signature happened.
+    pub first_seen: Option<u64>,
+}
+
+impl StallAnalysis {
+    /// Whether the stall has been seen before and is likely a systematic
+    /// problem rather than a one-off incident.
+    pub fn is_systematic(&self) -> bool {
+        self.nr_same > 0
+    }
+}
+
+impl fmt::Display for StallAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "stall: {}", self.fingerprint)?;
+        if !self.is_systematic() {
+            return write!(
+                f,
+                "stall: no matching stall among the last {} (one-off, {} of the same task class)",
+                self.nr_prev, self.nr_same_class
+            );
+        }
+        write!(
+            f,
+            "stall: SYSTEMATIC, {} of the last {} stalls match ({} on the same CPU) since {}",
+            self.nr_same,
+            self.nr_prev,
+            self.nr_same_cpu,
+            self.first_seen.unwrap_or(0)
+        )
+    }
+}
+
+/// Stall fingerprints of a scheduler persisted across restarts.
+pub struct StallHistory {
+    store: StateStore,
+    nr_keep: usize,
+}
+
+impl StallHistory {
+    /// Create the stall history for @sched in its default state directory.
+    /// See StateStore::new().
+    pub fn new(sched: &str) -> Self {
+        Self::with_store(StateStore::new(sched, STALLS_VERSION))
+    }
+
+    /// Create a stall history stored in the directory @dir.
+    pub fn with_dir(dir: impl Into<PathBuf>) -> Self {
+        Self::with_store(StateStore::with_dir(dir, STALLS_VERSION))
+    }
+
+    fn with_store(store: StateStore) -> Self {
+        Self {
+            store,
+            nr_keep: DFL_NR_KEEP,
+        }
+    }
+
+    /// Set the number of most recent fingerprints to keep.
+    pub fn set_nr_keep(&mut self, nr_keep: usize) {
+        self.nr_keep = nr_keep;
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        self.store.dir()
+    }
+
+    /// Read the stored fingerprints, oldest first. A missing, corrupt or
+    /// outdated history reads as an empty one.
+    pub fn load(&self) -> Vec<StallFingerprint> {
+        self.store
+            .load(STALLS_NAME)
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    /// Compare @fp against the stored fingerprints, add it to the history
+    /// and return the analysis.
+    pub fn record(&self, fp: StallFingerprint) -> Result<StallAnalysis> {
+        let mut prev = self.load();
+
+        let same: Vec<&StallFingerprint> = prev.iter().filter(|p| p.same_signature(&fp)).collect();
+        let analysis = StallAnalysis {
+            nr_prev: prev.len(),
+            nr_same: same.len(),
+            nr_same_cpu: same.iter().filter(|p| p.cpu == fp.cpu).count(),
+            nr_same_class: prev
+                .iter()
+                .filter(|p| p.task_class == fp.task_class)
+                .count(),
+            first_seen: same.iter().map(|p| p.at).min(),
+            fingerprint: fp.clone(),
+        };
+
+        prev.push(fp);
+        prev.drain(..prev.len().saturating_sub(self.nr_keep));
+        self.store.save(STALLS_NAME, &prev)?;
+
+        Ok(analysis)
+    }
+
+    /// Fingerprint and record @uei if it's a stall exit with a dump.
+    pub fn record_exit(&self, uei: &UserExitInfo) -> Result<Option<StallAnalysis>> {
+        if uei.kind() != ScxExitKind::ErrorStall as i32 {
+            return Ok(None);
+        }
+        match uei
+            .exit_dump()
+            .as_ref()
+            .and_then(StallFingerprint::from_dump)
+        {
+            Some(fp) => self.record(fp).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(comm: &str, cpu: u32, frame: &str) -> ExitDump {
+        ExitDump::parse(&format!(
+            "\
+kworker/u8:1[42] triggered exit kind 1026:
+  runnable task stall
+
+CPU states
+----------
+
+CPU {}   : nr_run=1 flags=0x1
+          curr=swapper/{}[0] class=idle_sched_class
+
+  R {}[1234] -30100ms
+      sticky/holding_cpu=-1/-1 dsq_id=0x8000000000000002
+
+    {}+0x3f/0xc0
+",
+            cpu, cpu, comm, frame
+        ))
+    }
+
+    fn history() -> (tempfile::TempDir, StallHistory) {
+        let dir = tempfile::tempdir().unwrap();
+        let history = StallHistory::with_dir(dir.path());
+        (dir, history)
+    }
+
+    fn fingerprint(comm: &str, cpu: u32, frame: &str) -> StallFingerprint {
+        StallFingerprint::from_dump(&dump(comm, cpu, frame)).unwrap()
+    }
+
+    #[test]
+    fn test_fingerprint_from_dump() {
+        let fp = fingerprint("kworker/3:2", 3, "worker_thread");
+        assert_eq!(fp.cpu, 3);
+        assert_eq!(fp.task_class, "kworker/#:#");
+        assert_eq!(fp.curr_class.as_deref(), Some("swapper/#"));
+        assert_eq!(fp.dsq_id, Some(0x8000000000000002));
+        assert_eq!(fp.stack, vec!["worker_thread"]);
+    }
+
+    #[test]
+    fn test_same_signature_ignores_cpu() {
+        let fp = fingerprint("kworker/3:2", 3, "worker_thread");
+        assert!(fp.same_signature(&fingerprint("kworker/5:0", 5, "worker_thread")));
+        assert!(!fp.same_signature(&fingerprint("kworker/3:2", 3, "do_syscall_64")));
+        assert!(!fp.same_signature(&fingerprint("stress", 3, "worker_thread")));
+    }
+
+    #[test]
+    fn test_history_one_off() {
+        let (_dir, history) = history();
+
+        assert!(!history
+            .record(fingerprint("kworker/3:2", 3, "worker_thread"))
+            .unwrap()
+            .is_systematic());
+        let a = history
+            .record(fingerprint("stress", 1, "do_syscall_64"))
+            .unwrap();
+        assert!(!a.is_systematic());
+        assert_eq!((a.nr_prev, a.nr_same, a.nr_same_class), (1, 0, 0));
+    }
+
+    #[test]
+    fn test_history_systematic() {
+        let (_dir, history) = history();
+
+        history
+            .record(fingerprint("kworker/3:2", 3, "worker_thread"))
+            .unwrap();
+        history
+            .record(fingerprint("stress", 1, "do_syscall_64"))
+            .unwrap();
+        let a = history
+            .record(fingerprint("kworker/5:0", 5, "worker_thread"))
+            .unwrap();
+        assert!(a.is_systematic());
+        assert_eq!((a.nr_prev, a.nr_same, a.nr_same_cpu), (2, 1, 0));
+        assert_eq!(history.load().len(), 3);
+    }
+
+    #[test]
+    fn test_history_keeps_most_recent() {
+        let (_dir, mut history) = history();
+        history.set_nr_keep(2);
+
+        for comm in ["a", "b", "c"] {
+            history
+                .record(fingerprint(comm, 0, "worker_thread"))
+                .unwrap();
+        }
+        let classes: Vec<String> = history.load().into_iter().map(|fp| fp.task_class).collect();
+        assert_eq!(classes, vec!["b", "c"]);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Persistent Scheduler State
+//!
+//! Schedulers learn things while running - task classifications, load
+//! balancer history, layer sizes - which are lost on every restart and have
+//! to be relearned from a cold start. `StateStore` saves such state as JSON
+//! into a runtime directory so that it can be restored on the next start.
+//!
+//! ```ignore
+//! let store = StateStore::new("scx_foo", 1);
+//! let mut history: LbHistory = store.load("lb_history")?.unwrap_or_default();
+//! ...
+//! store.save("lb_history", &history)?;
+//! ```
+//!
+//! Each saved state carries the format version passed to `StateStore::new()`.
+//! State saved with a different version is ignored on load, so bump the
+//! version when the layout of the saved data changes incompatibly.
+//!
+//! The directory is `$SCX_STATE_DIR` if set. Otherwise, it's
+//! `$RUNTIME_DIRECTORY` which systemd sets for units with
+//! `RuntimeDirectory=`, or `/run/scx/SCHED`.
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+const DFL_STATE_ROOT: &str = "/run/scx";
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    saved_at: u64,
+    data: T,
+}
+
+pub struct StateStore {
+    dir: PathBuf,
+    version: u32,
+}
+
+impl StateStore {
+    /// Create the state store for @sched in the default directory. @version
+    /// is the format version of the saved state.
+    pub fn new(sched: &str, version: u32) -> Self {
+        let dir = match (
+            std::env::var_os("SCX_STATE_DIR"),
+            std::env::var_os("RUNTIME_DIRECTORY"),
+        ) {
+            (Some(dir), _) => PathBuf::from(dir),
+            (None, Some(dir)) => PathBuf::from(dir),
+            (None, None) => PathBuf::from(DFL_STATE_ROOT).join(sched),
+        };
+        Self::with_dir(dir, version)
+    }
+
+    /// Create a state store in @dir.
+    pub fn with_dir(dir: impl Into<PathBuf>, version: u32) -> Self {
+        Self {
+            dir: dir.into(),
+            version,
+        }
+    }
+
+    pub fn dir(&self) -> &PathBuf {
+        &self.dir
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", name))
+    }
+
+    /// Save @data as @name. The file is replaced atomically so that a crash
+    /// while saving leaves the previous state intact.
+    pub fn save<T: Serialize>(&self, name: &str, data: &T) -> Result<()> {
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let envelope = Envelope {
+            version: self.version,
+            saved_at,
+            data,
+        };
+
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {:?}", &self.dir))?;
+
+        let path = self.path(name);
+        let tmp = self.dir.join(format!(".{}.json.tmp", name));
+        std::fs::write(&tmp, serde_json::to_vec(&envelope)?)
+            .with_context(|| format!("Failed to write {:?}", &tmp))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to rename {:?} to {:?}", &tmp, &path))
+    }
+
+    /// Load the state saved as @name. None if there is no saved state or
+    /// it was saved with a different format version. A file which can't be
+    /// parsed is an error.
+    pub fn load<T: DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        Ok(self.load_with_time(name)?.map(|(data, _)| data))
+    }
+
+    /// Like load() but also return when the state was saved in seconds
+    /// since the epoch so that the caller can discard stale state.
+    pub fn load_with_time<T: DeserializeOwned>(&self, name: &str) -> Result<Option<(T, u64)>> {
+        let path = self.path(name);
+        let buf = match std::fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", &path)),
+        };
+
+        let version: Envelope<serde::de::IgnoredAny> =
+            serde_json::from_slice(&buf).with_context(|| format!("Failed to parse {:?}", &path))?;
+        if version.version != self.version {
+            return Ok(None);
+        }
+
+        let envelope: Envelope<T> =
+            serde_json::from_slice(&buf).with_context(|| format!("Failed to parse {:?}", &path))?;
+        Ok(Some((envelope.data, envelope.saved_at)))
+    }
+
+    /// Remove the state saved as @name if it exists.
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let path = self.path(name);
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {:?}", &path))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn store(version: u32) -> (tempfile::TempDir, StateStore) {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateStore::with_dir(dir.path(), version);
+        (dir, store)
+    }
+
+    fn classes() -> BTreeMap<String, u32> {
+        [("ffmpeg".into(), 2), ("make".into(), 1)].into()
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let (_dir, store) = store(1);
+        assert_eq!(store.load::<u64>("missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let (_dir, store) = store(1);
+        store.save("classes", &classes()).unwrap();
+        assert_eq!(store.load("classes").unwrap(), Some(classes()));
+
+        let (_, saved_at) = store
+            .load_with_time::<BTreeMap<String, u32>>("classes")
+            .unwrap()
+            .unwrap();
+        assert!(saved_at > 0);
+    }
+
+    #[test]
+    fn test_version_mismatch() {
+        let (dir, store) = store(1);
+        store.save("classes", &classes()).unwrap();
+
+        let store_v2 = StateStore::with_dir(dir.path(), 2);
+        assert_eq!(
+            store_v2.load::<BTreeMap<String, u32>>("classes").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_load_corrupt() {
+        let (dir, store) = store(1);
+        std::fs::write(dir.path().join("bad.json"), "{").unwrap();
+        assert!(store.load::<u64>("bad").is_err());
+    }
+
+    #[test]
+    fn test_remove() {
+        let (_dir, store) = store(1);
+        store.save("classes", &classes()).unwrap();
+        store.remove("classes").unwrap();
+        assert_eq!(store.load::<u64>("classes").unwrap(), None);
+        // Removing state which doesn't exist isn't an error.
+        store.remove("classes").unwrap();
+    }
+}
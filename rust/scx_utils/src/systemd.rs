@@ -0,0 +1,211 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # systemd Integration
+//!
+//! Schedulers run as systemd services should exit with a status which tells
+//! a clean unregistration apart from a failure so that `Restart=on-failure`
+//! does the right thing, and `systemctl status` should show why the
+//! scheduler went away rather than just that it did. `exit_status()` maps
+//! the exit kind to the recommended process exit status and `notify_exit()`
+//! reports the exit reason through sd_notify(STATUS=...).
+//!
+//! | Exit kind                | Status                        |
+//! |--------------------------|-------------------------------|
+//! | None, Done, Unreg, SysRq | 0                             |
+//! | UnregBPF                 | scx_bpf_exit() code if 0-255  |
+//! | Error                    | `EXIT_STATUS_ERROR`           |
+//! | ErrorBPF                 | `EXIT_STATUS_ERROR_BPF`       |
+//! | ErrorStall               | `EXIT_STATUS_ERROR_STALL`     |
+//!
+//! ```ignore
+//! systemd::notify_ready()?;
+//! let uei = sched.run(shutdown)?;
+//! systemd::report_and_exit(&uei);
+//! ```
+//!
+//! `RestartPreventExitStatus=` and `SuccessExitStatus=` can then single out
+//! specific failures, e.g. to avoid restarting after repeated stalls.
+
+use crate::ScxExitKind;
+use crate::UserExitInfo;
+use anyhow::Context;
+use anyhow::Result;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+
+/// Generic error exit, also used for scx_bpf_exit() codes which don't fit
+/// in an exit status.
+pub const EXIT_STATUS_ERROR: i32 = 1;
+/// The BPF scheduler called scx_bpf_error().
+pub const EXIT_STATUS_ERROR_BPF: i32 = 3;
+/// The watchdog killed the BPF scheduler for stalling runnable tasks.
+pub const EXIT_STATUS_ERROR_STALL: i32 = 4;
+
+/// The recommended process exit status after the BPF scheduler exited as
+/// described by @uei.
+pub fn exit_status(uei: &UserExitInfo) -> i32 {
+    match uei.kind() {
+        k if k == ScxExitKind::UnregBPF as i32 => match uei.exit_code() {
+            Some(code) if (0..=255).contains(&code) => code as i32,
+            _ => EXIT_STATUS_ERROR,
+        },
+        k if k == ScxExitKind::ErrorBPF as i32 => EXIT_STATUS_ERROR_BPF,
+        k if k == ScxExitKind::ErrorStall as i32 => EXIT_STATUS_ERROR_STALL,
+        _ if uei.is_error() => EXIT_STATUS_ERROR,
+        _ => 0,
+    }
+}
+
+fn notify_socket_addr(path: &str) -> Result<SocketAddr> {
+    match path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())
+        }
+        None => SocketAddr::from_pathname(path),
+    }
+    .with_context(|| format!("Invalid NOTIFY_SOCKET {:?}", path))
+}
+
+fn notify_to(path: &str, state: &str) -> Result<()> {
+    let addr = notify_socket_addr(path)?;
+    let sock = UnixDatagram::unbound().context("Failed to create notify socket")?;
+    sock.send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("Failed to send {:?} to {}", state, path))?;
+    Ok(())
+}
+
+/// Send @state, newline separated assignments such as "READY=1", to the
+/// service manager. Returns false if not running under systemd with
+/// notification enabled.
+pub fn notify(state: &str) -> Result<bool> {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => notify_to(&path, state).map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Tell the service manager that the scheduler is up and running. For
+/// units with `Type=notify`.
+pub fn notify_ready() -> Result<bool> {
+    notify("READY=1")
+}
+
+/// Show @status in `systemctl status`.
+pub fn notify_status(status: &str) -> Result<bool> {
+    // A newline would start a new assignment.
+    notify(&format!("STATUS={}", status.replace('\n', " ")))
+}
+
+fn exit_state(uei: &UserExitInfo) -> String {
+    format!(
+        "STOPPING=1\nSTATUS={}",
+        uei.exit_message().replace('\n', " ")
+    )
+}
+
+/// Tell the service manager that the scheduler is stopping and why.
+pub fn notify_exit(uei: &UserExitInfo) -> Result<bool> {
+    notify(&exit_state(uei))
+}
+
+/// Report the exit as UserExitInfo::report() does, notify the service
+/// manager and exit the process with exit_status().
+pub fn report_and_exit(uei: &UserExitInfo) -> ! {
+    if let Err(e) = uei.report() {
+        eprintln!("Error: {:#}", e);
+    }
+    if let Err(e) = notify_exit(uei) {
+        eprintln!("Failed to notify systemd: {:#}", e);
+    }
+    std::process::exit(exit_status(uei))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::raw::c_char;
+
+    fn uei(kind: ScxExitKind, exit_code: i64, reason: &[u8]) -> UserExitInfo {
+        let kind = kind as i32;
+        let mut buf = vec![0 as c_char; reason.len() + 1];
+        for (b, c) in buf.iter_mut().zip(reason.iter()) {
+            *b = *c as c_char;
+        }
+        let empty = [0 as c_char];
+        UserExitInfo::new(
+            &kind,
+            &exit_code,
+            buf.as_ptr(),
+            empty.as_ptr(),
+            std::ptr::null(),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_exit_status_clean() {
+        assert_eq!(exit_status(&uei(ScxExitKind::None, 0, b"")), 0);
+        assert_eq!(exit_status(&uei(ScxExitKind::Unreg, 0, b"unreg")), 0);
+    }
+
+    #[test]
+    fn test_exit_status_bpf_exit_code() {
+        assert_eq!(exit_status(&uei(ScxExitKind::UnregBPF, 7, b"bpf")), 7);
+        // Codes which don't fit in an exit status are generic errors.
+        assert_eq!(
+            exit_status(&uei(ScxExitKind::UnregBPF, 1 << 48, b"bpf")),
+            EXIT_STATUS_ERROR
+        );
+    }
+
+    #[test]
+    fn test_exit_status_errors() {
+        assert_eq!(
+            exit_status(&uei(ScxExitKind::Error, 0, b"err")),
+            EXIT_STATUS_ERROR
+        );
+        assert_eq!(
+            exit_status(&uei(ScxExitKind::ErrorBPF, 0, b"err")),
+            EXIT_STATUS_ERROR_BPF
+        );
+        assert_eq!(
+            exit_status(&uei(ScxExitKind::ErrorStall, 0, b"stall")),
+            EXIT_STATUS_ERROR_STALL
+        );
+    }
+
+    #[test]
+    fn test_notify_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify");
+        let sock = UnixDatagram::bind(&path).unwrap();
+
+        let stall = uei(ScxExitKind::ErrorStall, 0, b"runnable task\nstall");
+        notify_to(path.to_str().unwrap(), &exit_state(&stall)).unwrap();
+
+        // The newline in the reason mustn't start a new assignment.
+        let mut buf = [0u8; 256];
+        let len = sock.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1\nSTATUS=EXIT: runnable task stall");
+    }
+
+    #[test]
+    fn test_notify_socket_addr() {
+        let addr = notify_socket_addr("/run/systemd/notify").unwrap();
+        assert_eq!(
+            addr.as_pathname(),
+            Some(std::path::Path::new("/run/systemd/notify"))
+        );
+
+        let addr = notify_socket_addr("@/org/freedesktop/systemd1/notify").unwrap();
+        assert_eq!(
+            addr.as_abstract_name(),
+            Some(&b"/org/freedesktop/systemd1/notify"[..])
+        );
+    }
+}
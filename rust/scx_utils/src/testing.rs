@@ -0,0 +1,233 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Mock Skeleton
+//!
+//! `MockSkel` mimics the parts of a libbpf-rs generated skeleton which the
+//! `uei_*` macros touch - `data()`, `rodata_mut()`, `maps()`, `maps_mut()`
+//! and `struct_ops` - so that exit handling paths can be unit tested
+//! without loading a BPF scheduler. The user_exit_info instance is named
+//! `uei` and the struct_ops `mock_ops`.
+//!
+//! ```ignore
+//! let mut skel = MockSkel::default();
+//! uei_set_size!(skel, mock_ops, uei);
+//! skel.set_dump(b"dump");
+//! skel.exit(ScxExitKind::Error as i32, 0, b"error", b"msg");
+//! assert!(uei_exited!(&skel, uei));
+//! assert!(uei_report!(&skel, uei).is_err());
+//! ```
+
+use crate::UEI_MSG_LEN;
+use crate::UEI_REASON_LEN;
+use anyhow::Result;
+use std::os::raw::c_char;
+
+/// Mirrors C struct user_exit_info.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MockUei {
+    pub kind: i32,
+    pub exit_code: i64,
+    pub reason: [c_char; UEI_REASON_LEN],
+    pub msg: [c_char; UEI_MSG_LEN],
+}
+
+impl Default for MockUei {
+    fn default() -> Self {
+        Self {
+            kind: 0,
+            exit_code: 0,
+            reason: [0; UEI_REASON_LEN],
+            msg: [0; UEI_MSG_LEN],
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MockData {
+    pub uei: MockUei,
+}
+
+#[derive(Default)]
+pub struct MockRodata {
+    pub uei_dump_len: u32,
+}
+
+/// A resizable global data map such as `data_uei_dump`.
+#[derive(Default)]
+pub struct MockMap {
+    value: Vec<u8>,
+}
+
+impl MockMap {
+    pub fn set_value_size(&mut self, size: u32) -> Result<()> {
+        self.value = vec![0; size as usize];
+        Ok(())
+    }
+
+    pub fn initial_value(&self) -> Option<&[u8]> {
+        Some(&self.value)
+    }
+}
+
+#[derive(Default)]
+pub struct MockMaps {
+    data_uei_dump: MockMap,
+}
+
+impl MockMaps {
+    pub fn data_uei_dump(&self) -> &MockMap {
+        &self.data_uei_dump
+    }
+}
+
+pub struct MockMapsMut<'a> {
+    maps: &'a mut MockMaps,
+}
+
+impl<'a> MockMapsMut<'a> {
+    pub fn data_uei_dump(&mut self) -> &mut MockMap {
+        &mut self.maps.data_uei_dump
+    }
+}
+
+#[derive(Default)]
+pub struct MockOps {
+    pub exit_dump_len: u32,
+}
+
+#[derive(Default)]
+pub struct MockStructOps {
+    mock_ops: MockOps,
+}
+
+impl MockStructOps {
+    pub fn mock_ops(&self) -> &MockOps {
+        &self.mock_ops
+    }
+
+    pub fn mock_ops_mut(&mut self) -> &mut MockOps {
+        &mut self.mock_ops
+    }
+}
+
+#[derive(Default)]
+pub struct MockSkel {
+    pub struct_ops: MockStructOps,
+    data: MockData,
+    rodata: MockRodata,
+    maps: MockMaps,
+}
+
+fn copy_cstr(dst: &mut [c_char], src: &[u8]) {
+    let len = src.len().min(dst.len().saturating_sub(1));
+    for (d, s) in dst.iter_mut().zip(src[..len].iter()) {
+        *d = *s as c_char;
+    }
+    dst[len..].iter_mut().for_each(|d| *d = 0);
+}
+
+impl MockSkel {
+    pub fn data(&self) -> &MockData {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut MockData {
+        &mut self.data
+    }
+
+    pub fn rodata(&self) -> &MockRodata {
+        &self.rodata
+    }
+
+    pub fn rodata_mut(&mut self) -> &mut MockRodata {
+        &mut self.rodata
+    }
+
+    pub fn maps(&self) -> &MockMaps {
+        &self.maps
+    }
+
+    pub fn maps_mut(&mut self) -> MockMapsMut<'_> {
+        MockMapsMut {
+            maps: &mut self.maps,
+        }
+    }
+
+    /// Simulate UEI_RECORD() on BPF scheduler exit. The strings are
+    /// truncated and NUL terminated like bpf_probe_read_kernel_str() does
+    /// and may contain arbitrary bytes.
+    pub fn exit(&mut self, kind: i32, exit_code: i64, reason: &[u8], msg: &[u8]) {
+        let uei = &mut self.data.uei;
+        copy_cstr(&mut uei.reason, reason);
+        copy_cstr(&mut uei.msg, msg);
+        uei.exit_code = exit_code;
+        uei.kind = kind;
+    }
+
+    /// Fill the dump area sized by uei_set_size!(). The buffer is updated
+    /// in place so that UEI_DUMP_PTRS stays valid.
+    pub fn set_dump(&mut self, dump: &[u8]) {
+        let value = &mut self.maps.data_uei_dump.value;
+        let len = dump.len().min(value.len().saturating_sub(1));
+        value[..len].copy_from_slice(&dump[..len]);
+        value[len..].iter_mut().for_each(|v| *v = 0);
+    }
+}
+
+impl Drop for MockSkel {
+    /// Unregister the dump area registered by uei_set_size!() so that
+    /// UEI_DUMP_PTRS doesn't keep pointing into the freed buffer.
+    fn drop(&mut self) {
+        let ptr = self.maps.data_uei_dump.value.as_ptr() as *const c_char;
+        let mut dump_ptrs = crate::UEI_DUMP_PTRS.lock().unwrap();
+        if dump_ptrs.get("uei").is_some_and(|dump| dump.ptr == ptr) {
+            dump_ptrs.remove("uei");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ScxConsts;
+    use crate::ScxExitKind;
+    use crate::UEI_DUMP_PTRS;
+
+    #[test]
+    fn test_uei_macros() {
+        let mut skel = MockSkel::default();
+
+        crate::uei_set_size!(skel, mock_ops, uei);
+        let dfl_len = ScxConsts::ExitDumpDflLen as usize;
+        assert_eq!(skel.rodata().uei_dump_len as usize, dfl_len);
+        assert_eq!(
+            skel.maps().data_uei_dump().initial_value().unwrap().len(),
+            dfl_len
+        );
+        assert!(!UEI_DUMP_PTRS.lock().unwrap()["uei"].ptr.is_null());
+
+        assert!(!crate::uei_exited!(&skel, uei));
+        assert!(crate::uei_report!(&skel, uei).is_ok());
+
+        skel.set_dump(b"CPU states\n----------\n\nCPU 0   : nr_run=1\n");
+        skel.exit(ScxExitKind::ErrorStall as i32, 0, b"stall", b"");
+        assert!(crate::uei_exited!(&skel, uei));
+
+        let uei = crate::uei_read!(&skel, uei);
+        assert!(uei.is_error());
+        assert_eq!(uei.exit_dump().unwrap().cpus.len(), 1);
+        assert!(crate::uei_report!(&skel, uei).is_err());
+
+        skel.exit(ScxExitKind::UnregBPF as i32, 42, b"done", b"");
+        assert!(crate::uei_read!(&skel, uei).exit_code().is_some());
+        assert!(crate::uei_exited_any!(&skel, uei));
+        assert!(crate::uei_report_all!(&skel, uei).is_ok());
+
+        drop(skel);
+        assert!(!UEI_DUMP_PTRS.lock().unwrap().contains_key("uei"));
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Time Utilities
+//!
+//! Clock reads which return the same values as the BPF clock helpers and
+//! conversions between the units used on the command line and the
+//! nanosecond values BPF schedulers expect, e.g. for `slice_ns`.
+//!
+//! ```ignore
+//! skel.rodata_mut().slice_ns = time::us_to_ns(opts.slice_us);
+//! let age = time::now_monotonic() - task_ctx.runnable_at;
+//! ```
+
+use std::time::Duration;
+
+pub const NSEC_PER_USEC: u64 = 1_000;
+pub const NSEC_PER_MSEC: u64 = 1_000_000;
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+fn clock_ns(clock: libc::clockid_t) -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let ret = unsafe { libc::clock_gettime(clock, &mut time) };
+    assert!(ret == 0);
+    time.tv_sec as u64 * NSEC_PER_SEC + time.tv_nsec as u64
+}
+
+/// CLOCK_MONOTONIC in nsecs. Matches bpf_ktime_get_ns().
+pub fn now_monotonic() -> u64 {
+    clock_ns(libc::CLOCK_MONOTONIC)
+}
+
+/// CLOCK_BOOTTIME in nsecs which includes the time spent in suspend.
+/// Matches bpf_ktime_get_boot_ns().
+pub fn now_boottime() -> u64 {
+    clock_ns(libc::CLOCK_BOOTTIME)
+}
+
+// The conversions to nsecs saturate instead of overflowing so that a
+// large value on the command line can't wrap into a tiny slice.
+
+pub fn us_to_ns(us: u64) -> u64 {
+    us.saturating_mul(NSEC_PER_USEC)
+}
+
+pub fn ms_to_ns(ms: u64) -> u64 {
+    ms.saturating_mul(NSEC_PER_MSEC)
+}
+
+pub fn secs_f64_to_ns(secs: f64) -> u64 {
+    (secs * NSEC_PER_SEC as f64) as u64
+}
+
+pub fn ns_to_us(ns: u64) -> u64 {
+    ns / NSEC_PER_USEC
+}
+
+pub fn ns_to_ms(ns: u64) -> u64 {
+    ns / NSEC_PER_MSEC
+}
+
+pub fn duration_to_ns(dur: Duration) -> u64 {
+    dur.as_nanos().try_into().unwrap_or(u64::MAX)
+}
@@ -51,6 +51,10 @@
 //! Every object contains a Cpumask that spans all CPUs in that point in the
 //! topological hierarchy.
 //!
+//! GPUs and other accelerators on the PCI bus are enumerated alongside as Gpu
+//! objects. Each records the NUMA node of its PCIe root and the CPUs local to
+//! it so that schedulers can place tasks feeding a GPU close to it.
+//!
 //! Creating Topology
 //! -----------------
 //!
@@ -101,6 +105,63 @@ impl Cpu {
     }
 }
 
+/// A GPU or other accelerator on the PCI bus.
+#[derive(Debug, Clone)]
+pub struct Gpu {
+    id: usize,
+    pci_addr: String,
+    vendor: u16,
+    device: u16,
+    class: u32,
+    driver: Option<String>,
+    node: Option<usize>,
+    local_cpus: Cpumask,
+}
+
+impl Gpu {
+    /// Get the ID of this Gpu, its index in Topology::gpus()
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the PCI address of this Gpu, e.g. "0000:01:00.0"
+    pub fn pci_addr(&self) -> &str {
+        &self.pci_addr
+    }
+
+    /// Get the PCI vendor ID of this Gpu
+    pub fn vendor(&self) -> u16 {
+        self.vendor
+    }
+
+    /// Get the PCI device ID of this Gpu
+    pub fn device(&self) -> u16 {
+        self.device
+    }
+
+    /// Get the PCI class code of this Gpu, either a display controller
+    /// (0x03xxxx) or a processing accelerator (0x12xxxx)
+    pub fn class(&self) -> u32 {
+        self.class
+    }
+
+    /// Get the name of the driver bound to this Gpu, if any
+    pub fn driver(&self) -> Option<&str> {
+        self.driver.as_deref()
+    }
+
+    /// Get the NUMA node this Gpu is attached to. None if the platform
+    /// doesn't report PCIe locality.
+    pub fn node(&self) -> Option<usize> {
+        self.node
+    }
+
+    /// Get a Cpumask of the online CPUs closest to this Gpu
+    pub fn local_cpus(&self) -> &Cpumask {
+        &self.local_cpus
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Core {
     id: usize,
@@ -178,7 +239,10 @@ pub struct Topology {
     nodes: Vec<Node>,
     cores: Vec<Core>,
     cpus: BTreeMap<usize, Cpu>,
+    gpus: Vec<Gpu>,
     span: Cpumask,
+    isolated: Cpumask,
+    nohz_full: Cpumask,
     nr_cpus_possible: usize,
 }
 
@@ -208,8 +272,12 @@ impl Topology {
             }
         }
 
+        let gpus = create_gpus(&span, &nodes)?;
+
+        let (isolated, nohz_full) = cpus_isolated()?;
+
         let nr_cpus_possible = libbpf_rs::num_possible_cpus().unwrap();
-        Ok(Topology { nodes, cores, cpus, span, nr_cpus_possible, })
+        Ok(Topology { nodes, cores, cpus, gpus, span, isolated, nohz_full, nr_cpus_possible, })
     }
 
     /// Get a slice of the NUMA nodes on the host.
@@ -227,11 +295,45 @@ impl Topology {
         &self.cpus
     }
 
+    /// Get a slice of all GPUs and accelerators on the host
+    pub fn gpus(&self) -> &[Gpu] {
+        &self.gpus
+    }
+
+    /// Get the GPUs attached to NUMA node @node_id
+    pub fn node_gpus(&self, node_id: usize) -> Vec<&Gpu> {
+        self.gpus.iter().filter(|gpu| gpu.node == Some(node_id)).collect()
+    }
+
     /// Get a cpumask of all the online CPUs on the host
     pub fn span(&self) -> &Cpumask {
         &self.span
     }
 
+    /// Get a cpumask of the CPUs isolated from scheduling with isolcpus=
+    pub fn isolated(&self) -> &Cpumask {
+        &self.isolated
+    }
+
+    /// Get a cpumask of the nohz_full CPUs
+    pub fn nohz_full(&self) -> &Cpumask {
+        &self.nohz_full
+    }
+
+    /// Get a cpumask of the online CPUs which aren't isolated or nohz_full,
+    /// i.e. the CPUs a scheduler should place tasks on by default. The user
+    /// isolated the rest to keep them free of everything but the workloads
+    /// explicitly pinned there.
+    pub fn housekeeping_span(&self) -> Cpumask {
+        self.exclude_isolated(&self.span)
+    }
+
+    /// Get @mask without the isolated and nohz_full CPUs, e.g. to build
+    /// scheduling domains from Cache or Node spans
+    pub fn exclude_isolated(&self, mask: &Cpumask) -> Cpumask {
+        mask.and_not(&self.isolated.or(&self.nohz_full))
+    }
+
     /// Get the maximum possible number of CPUs. Note that this number is likely
     /// only applicable in the context of storing and extracting per-CPU data
     /// between user space and BPF, as it doesn't necessarily reflect the actual
@@ -320,12 +422,40 @@ fn read_file_usize(path: &Path) -> Result<usize> {
     }
 }
 
-fn cpus_online() -> Result<Cpumask> {
-    let path = "/sys/devices/system/cpu/online";
-    let online = std::fs::read_to_string(&path)?;
-    let online_groups: Vec<&str> = online.split(',').collect();
+fn read_file_hex(path: &Path) -> Result<u64> {
+    let val = match std::fs::read_to_string(path) {
+        Ok(val) => val,
+        Err(_) => {
+            bail!("Failed to open or read file {:?}", path);
+        }
+    };
+
+    match u64::from_str_radix(val.trim().trim_start_matches("0x"), 16) {
+        Ok(parsed) => Ok(parsed),
+        Err(_) => {
+            bail!("Failed to parse {}", val);
+        }
+    }
+}
+
+fn read_cpulist(path: &Path) -> Result<Cpumask> {
+    let cpulist = std::fs::read_to_string(path)?;
+    parse_cpulist(&cpulist)
+}
+
+fn parse_cpulist(cpulist: &str) -> Result<Cpumask> {
     let mut mask = Cpumask::new()?;
-    for group in online_groups.iter() {
+    for cpu in parse_cpulist_cpus(cpulist)? {
+        mask.set_cpu(cpu)?;
+    }
+
+    Ok(mask)
+}
+
+fn parse_cpulist_cpus(cpulist: &str) -> Result<Vec<usize>> {
+    let groups: Vec<&str> = cpulist.split(',').filter(|g| !g.trim().is_empty()).collect();
+    let mut cpus = vec![];
+    for group in groups.iter() {
         let (min, max) = match sscanf!(group.trim(), "{usize}-{usize}") {
             Ok((x, y)) => (x, y),
             Err(_) => {
@@ -337,12 +467,121 @@ fn cpus_online() -> Result<Cpumask> {
                 }
             },
         };
-        for i in min..(max + 1) {
-            mask.set_cpu(i)?;
-        }
+        cpus.extend(min..(max + 1));
     }
 
-    Ok(mask)
+    Ok(cpus)
+}
+
+fn cpus_online() -> Result<Cpumask> {
+    read_cpulist(Path::new("/sys/devices/system/cpu/online"))
+}
+
+/// Find the value of the boot parameter @param in @cmdline. If specified
+/// multiple times, the last one wins.
+fn cmdline_param<'a>(cmdline: &'a str, param: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix(param)?.strip_prefix('='))
+        .next_back()
+}
+
+/// Find the CPUs of the cpulist boot parameter @param in @cmdline. CPU
+/// lists using the "N" or stride syntax are ignored.
+fn cmdline_cpulist(cmdline: &str, param: &str) -> Option<Vec<usize>> {
+    parse_cpulist_cpus(cmdline_param(cmdline, param)?).ok()
+}
+
+/// Find the CPUs isolated from the scheduler domains with isolcpus= in
+/// @cmdline. The optional leading flags, e.g. "isolcpus=nohz,domain,2-3",
+/// are skipped. Without flags, "domain" is implied. With flags but without
+/// "domain", e.g. "isolcpus=managed_irq,2-3", the CPUs are still scheduled
+/// normally and None is returned.
+fn cmdline_isolcpus(cmdline: &str) -> Option<Vec<usize>> {
+    let toks: Vec<&str> = cmdline_param(cmdline, "isolcpus")?.split(',').collect();
+    let nr_flags = toks
+        .iter()
+        .take_while(|tok| tok.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .count();
+    let flags = &toks[..nr_flags];
+    if !flags.is_empty() && !flags.contains(&"domain") {
+        return None;
+    }
+    parse_cpulist_cpus(&toks[nr_flags..].join(",")).ok()
+}
+
+/// The CPUs isolated with isolcpus= and the nohz_full CPUs. sysfs reports
+/// the effective masks. The boot parameters are checked too in case the
+/// sysfs files are missing. CPUs which aren't possible are ignored.
+fn cpus_isolated() -> Result<(Cpumask, Cpumask)> {
+    let cmdline = std::fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    let sys_path = Path::new("/sys/devices/system/cpu");
+
+    let mut isolated = read_cpulist(&sys_path.join("isolated")).or_else(|_| Cpumask::new())?;
+    for cpu in cmdline_isolcpus(&cmdline).unwrap_or_default() {
+        isolated.set_cpu(cpu).ok();
+    }
+
+    let mut nohz_full = read_cpulist(&sys_path.join("nohz_full")).or_else(|_| Cpumask::new())?;
+    for cpu in cmdline_cpulist(&cmdline, "nohz_full").unwrap_or_default() {
+        nohz_full.set_cpu(cpu).ok();
+    }
+
+    Ok((isolated, nohz_full))
+}
+
+// PCI base classes of display controllers and processing accelerators
+const PCI_BASE_CLASS_DISPLAY: u32 = 0x03;
+const PCI_BASE_CLASS_ACCELERATOR: u32 = 0x12;
+
+fn create_gpus(online_mask: &Cpumask, nodes: &[Node]) -> Result<Vec<Gpu>> {
+    let mut gpus: Vec<Gpu> = Vec::new();
+
+    // No PCI bus, e.g. on some embedded platforms, means no GPUs.
+    let dev_paths = glob("/sys/bus/pci/devices/*")?;
+    for dev_path in dev_paths.filter_map(Result::ok) {
+        let class = match read_file_hex(&dev_path.join("class")) {
+            Ok(val) => val as u32,
+            Err(_) => continue,
+        };
+        if class >> 16 != PCI_BASE_CLASS_DISPLAY && class >> 16 != PCI_BASE_CLASS_ACCELERATOR {
+            continue;
+        }
+
+        let pci_addr = dev_path.file_name().unwrap().to_string_lossy().to_string();
+        let vendor = read_file_hex(&dev_path.join("vendor")).unwrap_or(0) as u16;
+        let device = read_file_hex(&dev_path.join("device")).unwrap_or(0) as u16;
+        let driver = std::fs::read_link(dev_path.join("driver"))
+            .ok()
+            .and_then(|p| p.file_name().map(|f| f.to_string_lossy().to_string()));
+
+        // numa_node is -1 if the platform doesn't report PCIe locality.
+        let node = std::fs::read_to_string(dev_path.join("numa_node"))
+            .ok()
+            .and_then(|val| val.trim().parse::<usize>().ok());
+
+        // Prefer the device's own local CPU list and fall back to its NUMA
+        // node's span. Without either, all online CPUs are equally close.
+        let local_cpus = match read_cpulist(&dev_path.join("local_cpulist")) {
+            Ok(mask) if mask.and(online_mask).weight() > 0 => mask.and(online_mask),
+            _ => match node.and_then(|id| nodes.iter().find(|n| n.id == id)) {
+                Some(node) => node.span.clone(),
+                None => online_mask.clone(),
+            },
+        };
+
+        gpus.push(Gpu {
+            id: gpus.len(),
+            pci_addr,
+            vendor,
+            device,
+            class,
+            driver,
+            node,
+            local_cpus,
+        });
+    }
+    Ok(gpus)
 }
 
 fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
@@ -443,3 +682,32 @@ fn create_numa_nodes(online_mask: &Cpumask) -> Result<Vec<Node>> {
     }
     Ok(nodes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmdline_cpulist() {
+        let cmdline = "BOOT_IMAGE=/vmlinuz nohz_full=4-5,7 quiet nohz_full=1";
+        assert_eq!(cmdline_cpulist(cmdline, "nohz_full"), Some(vec![1]));
+        assert_eq!(
+            cmdline_cpulist("nohz_full=4-5,7", "nohz_full"),
+            Some(vec![4, 5, 7])
+        );
+        assert_eq!(cmdline_cpulist("nohz_full=1-N", "nohz_full"), None);
+        assert_eq!(cmdline_cpulist(cmdline, "rcu_nocbs"), None);
+    }
+
+    #[test]
+    fn test_cmdline_isolcpus_flags() {
+        assert_eq!(cmdline_isolcpus("isolcpus=2-3,6"), Some(vec![2, 3, 6]));
+        assert_eq!(
+            cmdline_isolcpus("isolcpus=managed_irq,domain,2-3"),
+            Some(vec![2, 3])
+        );
+        assert_eq!(cmdline_isolcpus("isolcpus=managed_irq,2-3"), None);
+        assert_eq!(cmdline_isolcpus("isolcpus=nohz,2-3"), None);
+        assert_eq!(cmdline_isolcpus("quiet"), None);
+    }
+}
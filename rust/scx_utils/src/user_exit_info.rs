@@ -3,20 +3,28 @@
 // This software may be used and distributed according to the terms of the
 // GNU General Public License version 2.
 use crate::bindings;
+use crate::journal;
+use crate::ksym::Symbolizer;
+use crate::ExitDump;
+use crate::StallHistory;
 use anyhow::bail;
 use anyhow::Result;
-use std::ffi::CStr;
+use std::collections::BTreeMap;
 use std::os::raw::c_char;
 use std::sync::Mutex;
 
+pub const UEI_REASON_LEN: usize = bindings::uei_sizes_UEI_REASON_LEN as usize;
+pub const UEI_MSG_LEN: usize = bindings::uei_sizes_UEI_MSG_LEN as usize;
+
 pub struct UeiDumpPtr {
     pub ptr: *const c_char,
+    pub len: usize,
 }
 unsafe impl Send for UeiDumpPtr {}
 
-pub static UEI_DUMP_PTR_MUTEX: Mutex<UeiDumpPtr> = Mutex::new(UeiDumpPtr {
-    ptr: std::ptr::null(),
-});
+/// Debug dump areas set up by uei_set_size!() indexed by the name of the
+/// user_exit_info instance.
+pub static UEI_DUMP_PTRS: Mutex<BTreeMap<&'static str, UeiDumpPtr>> = Mutex::new(BTreeMap::new());
 
 pub enum ScxExitKind {
     None = bindings::scx_exit_kind_SCX_EXIT_NONE as isize,
@@ -40,7 +48,11 @@ macro_rules! uei_read {
     ($skel: expr, $uei:ident) => {{
         scx_utils::paste! {
             let bpf_uei = $skel.data().$uei;
-            let bpf_dump = scx_utils::UEI_DUMP_PTR_MUTEX.lock().unwrap().ptr;
+            let (bpf_dump, bpf_dump_len) =
+                match scx_utils::UEI_DUMP_PTRS.lock().unwrap().get(stringify!($uei)) {
+                    Some(dump) => (dump.ptr, dump.len),
+                    None => (std::ptr::null(), 0),
+                };
             let exit_code_ptr = match scx_utils::compat::struct_has_field("scx_exit_info", "exit_code") {
                 Ok(true) => &bpf_uei.exit_code as *const _,
                 _ => std::ptr::null(),
@@ -52,6 +64,7 @@ macro_rules! uei_read {
                 bpf_uei.reason.as_ptr() as *const _,
                 bpf_uei.msg.as_ptr() as *const _,
                 bpf_dump,
+                bpf_dump_len,
             )
         }
     }};
@@ -59,7 +72,8 @@ macro_rules! uei_read {
 
 /// Resize debug dump area according to ops.exit_dump_len. If this macro is
 /// not called, debug dump area is not allocated and debug dump won't be
-/// printed out.
+/// printed out. Each user_exit_info instance, e.g. of schedulers with
+/// multiple struct_ops, should be set up separately.
 #[macro_export]
 macro_rules! uei_set_size {
     ($skel: expr, $ops: ident, $uei:ident) => {{
@@ -71,15 +85,18 @@ macro_rules! uei_set_size {
             $skel.rodata_mut().[<$uei _dump_len>] = len;
             $skel.maps_mut().[<data_ $uei _dump>]().set_value_size(len).unwrap();
 
-            let mut ptr = scx_utils::UEI_DUMP_PTR_MUTEX.lock().unwrap();
-            *ptr = scx_utils::UeiDumpPtr { ptr:
-                       $skel
-                       .maps()
-                       .[<data_ $uei _dump>]()
-                       .initial_value()
-                       .unwrap()
-                       .as_ptr() as *const _,
-            };
+            scx_utils::UEI_DUMP_PTRS.lock().unwrap().insert(
+                stringify!($uei),
+                scx_utils::UeiDumpPtr { ptr:
+                    $skel
+                    .maps()
+                    .[<data_ $uei _dump>]()
+                    .initial_value()
+                    .unwrap()
+                    .as_ptr() as *const _,
+                    len: len as usize,
+                },
+            );
         }
     }};
 }
@@ -89,11 +106,19 @@ macro_rules! uei_set_size {
 #[macro_export]
 macro_rules! uei_exited {
     ($skel: expr, $uei:ident) => {{
-        let bpf_uei = $skel.data().uei;
+        let bpf_uei = $skel.data().$uei;
         (unsafe { std::ptr::read_volatile(&bpf_uei.kind as *const _) } != 0)
     }};
 }
 
+/// Test whether any of the listed user_exit_info instances indicates exit.
+#[macro_export]
+macro_rules! uei_exited_any {
+    ($skel: expr, $($uei:ident),+) => {{
+        false $(|| scx_utils::uei_exited!($skel, $uei))+
+    }};
+}
+
 /// Takes a reference to C struct user_exit_info, reads it and invokes
 /// UserExitInfo::report() on it. See UserExitInfo.
 #[macro_export]
@@ -103,6 +128,17 @@ macro_rules! uei_report {
     }};
 }
 
+/// Read all the listed user_exit_info instances and report them together
+/// with UserExitInfo::report_all().
+#[macro_export]
+macro_rules! uei_report_all {
+    ($skel: expr, $($uei:ident),+) => {{
+        scx_utils::UserExitInfo::report_all(&[
+            $((stringify!($uei), &scx_utils::uei_read!($skel, $uei))),+
+        ])
+    }};
+}
+
 /// Rust counterpart of C struct user_exit_info.
 #[derive(Debug, Default)]
 pub struct UserExitInfo {
@@ -113,6 +149,25 @@ pub struct UserExitInfo {
     reason: Option<String>,
     msg: Option<String>,
     dump: Option<String>,
+    raw_reason: Vec<u8>,
+    raw_msg: Vec<u8>,
+    raw_dump: Vec<u8>,
+}
+
+/// Read the NUL terminated string at @ptr without reading more than @max
+/// bytes. The kernel and BPF code may leave the buffers unterminated or
+/// with arbitrary bytes, so this never fails.
+fn read_cstr_bytes(ptr: *const c_char, max: usize) -> Vec<u8> {
+    if ptr.is_null() || max == 0 {
+        return vec![];
+    }
+    let buf = unsafe { std::slice::from_raw_parts(ptr as *const u8, max) };
+    let len = buf.iter().position(|c| *c == 0).unwrap_or(max);
+    buf[..len].to_vec()
+}
+
+fn lossy_string(bytes: &[u8]) -> Option<String> {
+    Some(String::from_utf8_lossy(bytes).into_owned()).filter(|s| !s.is_empty())
 }
 
 impl UserExitInfo {
@@ -121,12 +176,17 @@ impl UserExitInfo {
     /// user_exit_info, so we can't take the type directly. Instead, this
     /// method takes each member field. Use the macro uei_read!() on the C
     /// type which then calls this method with the individual fields.
+    ///
+    /// @dump_ptr points to the debug dump area of @dump_len bytes. Strings
+    /// which aren't valid UTF-8 are converted lossily. The raw bytes are
+    /// available through raw_reason(), raw_msg() and raw_dump().
     pub fn new(
         kind_ptr: *const i32,
         exit_code_ptr: *const i64,
         reason_ptr: *const c_char,
         msg_ptr: *const c_char,
         dump_ptr: *const c_char,
+        dump_len: usize,
     ) -> Self {
         let kind = unsafe { std::ptr::read_volatile(kind_ptr) };
         let exit_code = if exit_code_ptr.is_null() {
@@ -135,73 +195,148 @@ impl UserExitInfo {
             unsafe { std::ptr::read_volatile(exit_code_ptr) }
         };
 
-        let (reason, msg) = (
-            Some(
-                unsafe { CStr::from_ptr(reason_ptr) }
-                    .to_str()
-                    .expect("Failed to convert reason to string")
-                    .to_string(),
-            )
-            .filter(|s| !s.is_empty()),
-            Some(
-                unsafe { CStr::from_ptr(msg_ptr) }
-                    .to_str()
-                    .expect("Failed to convert msg to string")
-                    .to_string(),
-            )
-            .filter(|s| !s.is_empty()),
-        );
-
-        let dump = if dump_ptr.is_null() {
-            None
-        } else {
-            Some(
-                unsafe { CStr::from_ptr(dump_ptr) }
-                    .to_str()
-                    .expect("Failed to convert msg to string")
-                    .to_string(),
-            )
-            .filter(|s| !s.is_empty())
-        };
+        let raw_reason = read_cstr_bytes(reason_ptr, UEI_REASON_LEN);
+        let raw_msg = read_cstr_bytes(msg_ptr, UEI_MSG_LEN);
+        let raw_dump = read_cstr_bytes(dump_ptr, dump_len);
 
         Self {
             kind,
             exit_code,
-            reason,
-            msg,
-            dump,
+            reason: lossy_string(&raw_reason),
+            msg: lossy_string(&raw_msg),
+            dump: lossy_string(&raw_dump),
+            raw_reason,
+            raw_msg,
+            raw_dump,
         }
     }
 
     /// Print out the exit message to stderr if the exit was normal. After
     /// an error exit, it throws an error containing the exit message
-    /// instead. If debug dump exists, it's always printed to stderr.
+    /// instead. If debug dump exists, it's always printed to stderr. If
+    /// journald logging is enabled, everything is sent to journald instead.
     pub fn report(&self) -> Result<()> {
         if self.kind == 0 {
             return Ok(());
         }
 
-        if let Some(dump) = &self.dump {
-            eprintln!("\nDEBUG DUMP");
-            eprintln!("================================================================================\n");
-            eprintln!("{}", dump);
-            eprintln!("================================================================================\n");
-        }
-
         let why = match (&self.reason, &self.msg) {
             (Some(reason), None) => format!("EXIT: {}", reason),
             (Some(reason), Some(msg)) => format!("EXIT: {} ({})", reason, msg),
             _ => "<UNKNOWN>".into(),
         };
 
-        if self.kind <= ScxExitKind::UnregBPF as i32 {
-            eprintln!("{}", why);
+        // With journald logging, the exit info is sent as a structured
+        // record instead of being printed to stderr.
+        let journaled = match journal::journal_identifier() {
+            Some(identifier) => self.send_to_journal(identifier).is_ok(),
+            None => false,
+        };
+
+        if let Some(dump) = self.dump.as_ref().filter(|_| !journaled) {
+            eprintln!("\nDEBUG DUMP");
+            eprintln!("================================================================================\n");
+            eprintln!("{}", dump);
+            eprintln!("================================================================================\n");
+        }
+
+        if !self.is_error() {
+            if !journaled {
+                eprintln!("{}", why);
+            }
             Ok(())
         } else {
             bail!("{}", why)
         }
     }
 
+    /// On stall exits, record the stall in @history and report how it
+    /// compares to the earlier stalls. Failing to update the history is
+    /// only reported.
+    pub fn record_stall(&self, history: &StallHistory) {
+        match history.record_exit(self) {
+            Ok(Some(analysis)) => eprintln!("{}\n", analysis),
+            Ok(None) => (),
+            Err(e) => eprintln!("Failed to record stall: {:#}", e),
+        }
+    }
+
+    /// Like report() but record_stall() in @history first.
+    pub fn report_with_stall_history(&self, history: &StallHistory) -> Result<()> {
+        self.record_stall(history);
+        self.report()
+    }
+
+    /// Report multiple named UserExitInfo's, e.g. one for each struct_ops of
+    /// a scheduler. Instances which haven't exited are skipped. If any of
+    /// them exited with an error, an error listing all of them is returned.
+    pub fn report_all(ueis: &[(&str, &UserExitInfo)]) -> Result<()> {
+        let mut errors = vec![];
+
+        for (name, uei) in ueis.iter() {
+            if uei.kind == 0 {
+                continue;
+            }
+            if ueis.len() > 1 {
+                eprintln!("[{}]", name);
+            }
+            if let Err(e) = uei.report() {
+                errors.push(format!("{}: {}", name, e));
+            }
+        }
+
+        match errors.len() {
+            0 => Ok(()),
+            _ => bail!("{}", errors.join(", ")),
+        }
+    }
+
+    /// Send the exit info to journald as a single record tagged with
+    /// @identifier. The fields are available as SCX_EXIT_KIND,
+    /// SCX_EXIT_CODE, SCX_REASON, SCX_MSG and SCX_DUMP.
+    pub fn send_to_journal(&self, identifier: &str) -> Result<()> {
+        let (kind, exit_code) = (self.kind.to_string(), self.exit_code.to_string());
+        let reason = self.reason.as_deref().unwrap_or("");
+        let msg = self.msg.as_deref().unwrap_or("");
+        let message = self.exit_message();
+        let prio = match self.is_error() {
+            true => journal::PRIO_ERR,
+            false => journal::PRIO_NOTICE,
+        }
+        .to_string();
+
+        let mut fields = vec![
+            ("MESSAGE", message.as_str()),
+            ("PRIORITY", prio.as_str()),
+            ("SYSLOG_IDENTIFIER", identifier),
+            ("SCX_EXIT_KIND", kind.as_str()),
+            ("SCX_EXIT_CODE", exit_code.as_str()),
+            ("SCX_REASON", reason),
+            ("SCX_MSG", msg),
+        ];
+        if let Some(dump) = &self.dump {
+            fields.push(("SCX_DUMP", dump.as_str()));
+        }
+        journal::journal_send(&fields)
+    }
+
+    /// One line summary of the exit reason and message.
+    pub(crate) fn exit_message(&self) -> String {
+        match (
+            self.reason.as_deref().unwrap_or(""),
+            self.msg.as_deref().unwrap_or(""),
+        ) {
+            ("", _) => "EXIT: <UNKNOWN>".to_string(),
+            (reason, "") => format!("EXIT: {}", reason),
+            (reason, msg) => format!("EXIT: {} ({})", reason, msg),
+        }
+    }
+
+    /// The C enum scx_exit_kind value. Test against ScxExitKind.
+    pub fn kind(&self) -> i32 {
+        self.kind
+    }
+
     /// Return the exit code that the scheduler gracefully exited with. This
     /// only applies when the BPF scheduler exits with scx_bpf_exit(), i.e. kind
     /// ScxExitKind::UnregBPF.
@@ -212,4 +347,96 @@ impl UserExitInfo {
             None
         }
     }
+
+    /// The exit reason as read from the BPF scheduler without UTF-8
+    /// conversion. Empty if there is none.
+    pub fn raw_reason(&self) -> &[u8] {
+        &self.raw_reason
+    }
+
+    /// The exit message without UTF-8 conversion.
+    pub fn raw_msg(&self) -> &[u8] {
+        &self.raw_msg
+    }
+
+    /// The debug dump without UTF-8 conversion.
+    pub fn raw_dump(&self) -> &[u8] {
+        &self.raw_dump
+    }
+
+    /// Annotate the debug dump with kernel symbols and BPF source lines.
+    /// See ksym::Symbolizer.
+    pub fn symbolize_dump(&mut self, symbolizer: &Symbolizer) {
+        if let Some(dump) = &self.dump {
+            self.dump = Some(symbolizer.annotate(dump));
+        }
+    }
+
+    /// Parse the debug dump. None if there is no dump.
+    pub fn exit_dump(&self) -> Option<ExitDump> {
+        self.dump.as_deref().map(ExitDump::parse)
+    }
+
+    /// Whether the BPF scheduler exited with an error.
+    pub fn is_error(&self) -> bool {
+        self.kind > ScxExitKind::UnregBPF as i32
+    }
+
+    /// Format all the fields for crash reports.
+    pub(crate) fn to_text(&self) -> String {
+        format!(
+            "kind: {}\nexit_code: {}\nreason: {}\nmsg: {}\n\n{}\n",
+            self.kind,
+            self.exit_code,
+            self.reason.as_deref().unwrap_or(""),
+            self.msg.as_deref().unwrap_or(""),
+            self.dump.as_deref().unwrap_or("<NO DUMP>"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_malformed_strings() {
+        let kind = ScxExitKind::Error as i32;
+        let exit_code = 0i64;
+        let mut reason = [b'r' as c_char; UEI_REASON_LEN];
+        let mut msg = [0 as c_char; UEI_MSG_LEN];
+        let dump = [0xffu8 as c_char; 16];
+
+        // Unterminated reason, invalid UTF-8 in msg and an unterminated
+        // dump which must not be read beyond its length.
+        reason[UEI_REASON_LEN - 1] = b'x' as c_char;
+        msg[..4].copy_from_slice(&[b'a' as c_char, 0xc3u8 as c_char, 0x28, b'b' as c_char]);
+
+        let uei = UserExitInfo::new(
+            &kind,
+            &exit_code,
+            reason.as_ptr(),
+            msg.as_ptr(),
+            dump.as_ptr(),
+            8,
+        );
+
+        assert_eq!(uei.raw_reason().len(), UEI_REASON_LEN);
+        assert_eq!(uei.reason.as_deref().unwrap().len(), UEI_REASON_LEN);
+        assert_eq!(uei.raw_msg(), &[b'a', 0xc3, 0x28, b'b']);
+        assert_eq!(uei.msg.as_deref(), Some("a\u{fffd}(b"));
+        assert_eq!(uei.raw_dump(), &[0xff; 8]);
+        assert_eq!(uei.dump.as_deref().unwrap().chars().count(), 8);
+        assert!(uei.report().is_err());
+
+        let uei = UserExitInfo::new(
+            &kind,
+            &exit_code,
+            msg[4..].as_ptr(),
+            msg[4..].as_ptr(),
+            std::ptr::null(),
+            0,
+        );
+        assert_eq!((uei.reason, uei.msg, uei.dump), (None, None, None));
+    }
 }
@@ -0,0 +1,259 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Virtualization Awareness
+//!
+//! Inside a VM, the vCPUs aren't dedicated cores. The host may preempt them
+//! at any time to run other vCPUs or its own work. The guest sees that time
+//! as steal time. A scheduler which treats heavily stolen vCPUs like the
+//! others ends up stranding tasks on vCPUs that barely run.
+//!
+//! `VmInfo::detect()` tells whether we're running in a VM, under which
+//! hypervisor and whether the host hints that the vCPUs are pinned to
+//! dedicated physical CPUs. `StealSnapshot` reads the per-vCPU steal time
+//! from /proc/stat. The delta between two snapshots gives the fraction of
+//! time each vCPU was stolen in the interval.
+//!
+//! ```ignore
+//! let vm = VmInfo::detect();
+//! if vm.is_vm() && !vm.dedicated {
+//!     let mut prev = StealSnapshot::read()?;
+//!     loop {
+//!         std::thread::sleep(Duration::from_secs(1));
+//!         let cur = StealSnapshot::read()?;
+//!         for cpu in cur.delta(&prev).stolen_cpus(0.2) {
+//!             // deprioritize @cpu
+//!         }
+//!         prev = cur;
+//!     }
+//! }
+//! ```
+
+use anyhow::Context;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+const DMI_SYS_VENDOR: &str = "/sys/class/dmi/id/sys_vendor";
+const HYPERVISOR_TYPE: &str = "/sys/hypervisor/type";
+
+/// KVM_HINTS_REALTIME in CPUID 0x40000001 EDX: the vCPUs are never
+/// preempted, i.e. pinned to dedicated physical CPUs.
+#[cfg(target_arch = "x86_64")]
+const KVM_HINTS_REALTIME: u32 = 1 << 0;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VmInfo {
+    /// The hypervisor, e.g. "KVM" or "Xen". None on bare metal.
+    pub hypervisor: Option<String>,
+    /// Whether the host hints that the vCPUs are backed by dedicated
+    /// physical CPUs, in which case steal time should stay negligible.
+    pub dedicated: bool,
+}
+
+// __cpuid() is safe on newer toolchains but unsafe on older ones.
+#[cfg(target_arch = "x86_64")]
+#[allow(unused_unsafe)]
+fn cpuid_hypervisor() -> Option<(String, bool)> {
+    use std::arch::x86_64::__cpuid;
+
+    // CPUID.1:ECX bit 31 is set by all hypervisors.
+    if unsafe { __cpuid(1) }.ecx & (1 << 31) == 0 {
+        return None;
+    }
+
+    let leaf = unsafe { __cpuid(0x4000_0000) };
+    let sig: Vec<u8> = [leaf.ebx, leaf.ecx, leaf.edx]
+        .iter()
+        .flat_map(|r| r.to_le_bytes())
+        .collect();
+    let sig = String::from_utf8_lossy(&sig);
+    let name = match sig.trim_end_matches('\0') {
+        "KVMKVMKVM" => "KVM",
+        "Microsoft Hv" => "Hyper-V",
+        "VMwareVMware" => "VMware",
+        "XenVMMXenVMM" => "Xen",
+        "TCGTCGTCGTCG" => "QEMU",
+        "ACRNACRNACRN" => "ACRN",
+        " lrpepyh  vr" => "Parallels",
+        "VBoxVBoxVBox" => "VirtualBox",
+        _ => "unknown",
+    };
+
+    let dedicated = name == "KVM"
+        && leaf.eax >= 0x4000_0001
+        && unsafe { __cpuid(0x4000_0001) }.edx & KVM_HINTS_REALTIME != 0;
+
+    Some((name.to_string(), dedicated))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn cpuid_hypervisor() -> Option<(String, bool)> {
+    None
+}
+
+impl VmInfo {
+    /// Detect the hypervisor through CPUID on x86 and sysfs elsewhere.
+    pub fn detect() -> Self {
+        if let Some((hypervisor, dedicated)) = cpuid_hypervisor() {
+            return Self {
+                hypervisor: Some(hypervisor),
+                dedicated,
+            };
+        }
+
+        let read = |path| {
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|s| s.trim().to_string())
+        };
+        let hypervisor = read(HYPERVISOR_TYPE).or_else(|| match read(DMI_SYS_VENDOR)?.as_str() {
+            "QEMU" => Some("KVM".to_string()),
+            "Xen" => Some("Xen".to_string()),
+            "Microsoft Corporation" => Some("Hyper-V".to_string()),
+            "VMware, Inc." => Some("VMware".to_string()),
+            "Amazon EC2" => Some("Nitro".to_string()),
+            "Google" => Some("KVM".to_string()),
+            _ => None,
+        });
+
+        Self {
+            hypervisor,
+            dedicated: false,
+        }
+    }
+
+    pub fn is_vm(&self) -> bool {
+        self.hypervisor.is_some()
+    }
+}
+
+/// Per-CPU busy and steal times from /proc/stat in USER_HZ ticks.
+#[derive(Clone, Debug, Default)]
+pub struct StealSnapshot {
+    /// (total, steal) ticks indexed by CPU. Cumulative in snapshots and
+    /// per-interval in deltas.
+    pub cpus: BTreeMap<usize, (u64, u64)>,
+}
+
+impl StealSnapshot {
+    pub fn read() -> Result<Self> {
+        let stat = std::fs::read_to_string("/proc/stat").context("Failed to read /proc/stat")?;
+        Ok(Self::parse(&stat))
+    }
+
+    /// Parse the per-CPU lines of /proc/stat. Steal is the 8th value and
+    /// missing on very old kernels, in which case it reads as 0.
+    pub fn parse(stat: &str) -> Self {
+        let mut cpus = BTreeMap::new();
+        for line in stat.lines() {
+            let mut fields = line.split_whitespace();
+            let cpu = match fields.next().and_then(|f| f.strip_prefix("cpu")) {
+                Some(id) => match id.parse::<usize>() {
+                    Ok(cpu) => cpu,
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+
+            // guest and guest_nice are already included in user and nice.
+            let vals: Vec<u64> = fields.take(8).filter_map(|v| v.parse().ok()).collect();
+            let total = vals.iter().sum();
+            let steal = vals.get(7).copied().unwrap_or(0);
+            cpus.insert(cpu, (total, steal));
+        }
+        Self { cpus }
+    }
+
+    /// The ticks between @prev and @self.
+    pub fn delta(&self, prev: &Self) -> Self {
+        let cpus = self
+            .cpus
+            .iter()
+            .map(|(cpu, (total, steal))| {
+                let (ptotal, psteal) = prev.cpus.get(cpu).copied().unwrap_or((0, 0));
+                (
+                    *cpu,
+                    (total - ptotal.min(*total), steal - psteal.min(*steal)),
+                )
+            })
+            .collect();
+        Self { cpus }
+    }
+
+    /// Fraction of the time @cpu was stolen by the host.
+    pub fn steal_ratio(&self, cpu: usize) -> f64 {
+        match self.cpus.get(&cpu) {
+            Some((total, steal)) if *total > 0 => *steal as f64 / *total as f64,
+            _ => 0.0,
+        }
+    }
+
+    /// The CPUs whose steal ratio is above @threshold.
+    pub fn stolen_cpus(&self, threshold: f64) -> Vec<usize> {
+        self.cpus
+            .keys()
+            .copied()
+            .filter(|cpu| self.steal_ratio(*cpu) > threshold)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prev() -> StealSnapshot {
+        StealSnapshot::parse(
+            "cpu  200 0 0 200 0 0 0 20 0 0\n\
+             cpu0 100 0 0 100 0 0 0 0 0 0\n\
+             cpu1 100 0 0 100 0 0 0 20 0 0\n\
+             intr 1 2 3\n",
+        )
+    }
+
+    fn cur() -> StealSnapshot {
+        StealSnapshot::parse(
+            "cpu0 150 0 0 140 0 0 0 10 0 0\n\
+             cpu1 110 0 0 110 0 0 0 100 0 0\n",
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        // The aggregate "cpu" line and other lines are skipped.
+        let snap = prev();
+        assert_eq!(snap.cpus.len(), 2);
+        assert_eq!(snap.cpus[&1], (220, 20));
+
+        // Kernels without steal time read as no steal.
+        let snap = StealSnapshot::parse("cpu0 100 0 0 100 0 0 0\n");
+        assert_eq!(snap.cpus[&0], (200, 0));
+    }
+
+    #[test]
+    fn test_delta() {
+        let delta = cur().delta(&prev());
+        assert_eq!(delta.cpus[&0], (100, 10));
+        assert_eq!(delta.cpus[&1], (100, 80));
+    }
+
+    #[test]
+    fn test_stolen_cpus() {
+        let delta = cur().delta(&prev());
+        assert_eq!(delta.steal_ratio(0), 0.1);
+        assert_eq!(delta.steal_ratio(1), 0.8);
+        assert_eq!(delta.steal_ratio(2), 0.0);
+        assert_eq!(delta.stolen_cpus(0.5), vec![1]);
+        assert_eq!(delta.stolen_cpus(0.05), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_detect() {
+        // The result varies with where the test runs, just make sure it
+        // doesn't blow up and is consistent.
+        let vm = VmInfo::detect();
+        assert!(vm.is_vm() || !vm.dedicated);
+    }
+}
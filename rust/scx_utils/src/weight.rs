@@ -0,0 +1,96 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! # Nice and Weight Arithmetic
+//!
+//! Rust mirror of scx/weight.bpf.h. The nice to weight table is generated
+//! from the BPF header at build time and the helpers below follow their
+//! BPF counterparts operation by operation, including integer rounding,
+//! so that load calculations in userspace agree with the BPF scheduler's
+//! dispatch math.
+//!
+//! Weights are in the sched_ext scale of p->scx.weight, i.e. nice 0 is
+//! `WEIGHT_DFL` (100). Kernel load weights, nice 0 being `NICE_0_LOAD`
+//! (1024), are only used for the conversions.
+
+pub const NICE_WIDTH: usize = 40;
+pub const NICE_0_LOAD: u64 = 1024;
+pub const WEIGHT_MIN: u32 = 1;
+pub const WEIGHT_DFL: u32 = 100;
+pub const WEIGHT_MAX: u32 = 10000;
+
+// PRIO_TO_WEIGHT, the kernel's sched_prio_to_weight[] indexed by nice + 20.
+include!(concat!(env!("OUT_DIR"), "/weight_table.rs"));
+
+/// scx_load_to_weight(): kernel load weight to p->scx.weight.
+pub fn load_to_weight(load: u64) -> u32 {
+    let weight = load
+        .saturating_mul(WEIGHT_DFL as u64)
+        .saturating_add(NICE_0_LOAD / 2)
+        / NICE_0_LOAD;
+    weight.clamp(WEIGHT_MIN as u64, WEIGHT_MAX as u64) as u32
+}
+
+/// scx_nice_to_load(): kernel load weight of @nice clamped to [-20, 19].
+pub fn nice_to_load(nice: i32) -> u32 {
+    PRIO_TO_WEIGHT[(nice.clamp(-20, 19) + 20) as usize]
+}
+
+/// scx_nice_to_weight(): p->scx.weight of a task with @nice.
+pub fn nice_to_weight(nice: i32) -> u32 {
+    load_to_weight(nice_to_load(nice) as u64)
+}
+
+/// scx_scale_by_weight()
+pub fn scale_by_weight(val: u64, weight: u32) -> u64 {
+    val.wrapping_mul(weight as u64) / WEIGHT_DFL as u64
+}
+
+/// scx_scale_by_weight_inverse()
+pub fn scale_by_weight_inverse(val: u64, weight: u32) -> u64 {
+    val.wrapping_mul(WEIGHT_DFL as u64) / weight.max(WEIGHT_MIN) as u64
+}
+
+/// scx_vtime_delta(): vtime charged to a task with @weight for running
+/// @delta_ns.
+pub fn vtime_delta(delta_ns: u64, weight: u32) -> u64 {
+    scale_by_weight_inverse(delta_ns, weight)
+}
+
+/// scx_vtime_clamp(): limit the budget a sleeping task can accumulate
+/// relative to @vtime_now to @slice_ns.
+pub fn vtime_clamp(vtime: u64, vtime_now: u64, slice_ns: u64) -> u64 {
+    if vtime_now > slice_ns && (vtime.wrapping_sub(vtime_now - slice_ns) as i64) < 0 {
+        vtime_now - slice_ns
+    } else {
+        vtime
+    }
+}
+
+/// scx_vtime_deadline()
+pub fn vtime_deadline(vtime: u64, slice_ns: u64, weight: u32) -> u64 {
+    vtime.wrapping_add(vtime_delta(slice_ns, weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nice_to_weight() {
+        assert_eq!(nice_to_load(0), NICE_0_LOAD as u32);
+        assert_eq!(nice_to_weight(0), WEIGHT_DFL);
+        assert_eq!(nice_to_weight(-20), 8668);
+        assert_eq!(nice_to_weight(19), 1);
+        assert_eq!(nice_to_weight(100), nice_to_weight(19));
+        assert_eq!(load_to_weight(u64::MAX), WEIGHT_MAX);
+        assert!(PRIO_TO_WEIGHT.windows(2).all(|w| w[0] > w[1]));
+
+        assert_eq!(vtime_delta(1000, WEIGHT_DFL), 1000);
+        assert_eq!(vtime_delta(1000, 200), 500);
+        assert_eq!(vtime_clamp(0, 10_000, 1000), 9000);
+        assert_eq!(vtime_clamp(9500, 10_000, 1000), 9500);
+    }
+}
@@ -0,0 +1,89 @@
+#ifndef __SCX_DSQ_DUMP_BPF_H__
+#define __SCX_DSQ_DUMP_BPF_H__
+
+#include "dsq_dump_intf.h"
+
+/*
+ * On-demand dump of DSQ and per-CPU local DSQ states for stall triage.
+ * Including this header adds the scx_dsq_dump() syscall program which walks
+ * the requested DSQs with the DSQ iterator and reports queue depths, how long
+ * the queued tasks have been runnable and the task lists through the
+ * @scx_dsq_dump_rb ring buffer. Userspace triggers and parses the dump with
+ * scx_utils::DsqDump. Requires the DSQ iterator kfuncs, on older kernels
+ * only the queue depths are reported.
+ *
+ * Assumes vmlinux.h and scx/common.bpf.h have already been included.
+ */
+
+struct {
+	__uint(type, BPF_MAP_TYPE_RINGBUF);
+	__uint(max_entries, 256 * 1024);
+} scx_dsq_dump_rb SEC(".maps");
+
+extern unsigned int CONFIG_HZ __kconfig __weak;
+
+static __always_inline void scx_dsq_dump_emit(u32 kind, u64 id, s32 nr_queued,
+					      struct task_struct *p)
+{
+	struct scx_dsq_dump_rec *rec;
+	u64 hz = CONFIG_HZ ?: 1000;
+
+	rec = bpf_ringbuf_reserve(&scx_dsq_dump_rb, sizeof(*rec), 0);
+	if (!rec)
+		return;
+
+	__builtin_memset(rec, 0, sizeof(*rec));
+	rec->kind = kind;
+	rec->id = id;
+	rec->nr_queued = nr_queued;
+	if (p) {
+		rec->pid = p->pid;
+		rec->runnable_ms = (bpf_jiffies64() - p->scx.runnable_at) * 1000 / hz;
+		bpf_probe_read_kernel_str(rec->comm, sizeof(rec->comm), p->comm);
+	}
+
+	bpf_ringbuf_submit(rec, 0);
+}
+
+SEC("syscall")
+int scx_dsq_dump(struct scx_dsq_dump_args *args)
+{
+	struct task_struct *p;
+	u32 i, nr_dsqs, nr_cpus;
+	s32 cpu;
+
+	nr_dsqs = args->nr_dsqs;
+	nr_cpus = args->nr_cpus;
+
+	bpf_for(i, 0, SCX_DSQ_DUMP_MAX_DSQS) {
+		u64 dsq_id;
+		u32 nr_listed = 0;
+
+		if (i >= nr_dsqs)
+			break;
+
+		dsq_id = args->dsq_ids[i];
+		scx_dsq_dump_emit(SCX_DSQ_DUMP_REC_DSQ, dsq_id,
+				  scx_bpf_dsq_nr_queued(dsq_id), NULL);
+
+		bpf_rcu_read_lock();
+		__COMPAT_DSQ_FOR_EACH(p, dsq_id, 0) {
+			if (nr_listed++ >= SCX_DSQ_DUMP_MAX_TASKS)
+				break;
+			scx_dsq_dump_emit(SCX_DSQ_DUMP_REC_TASK, dsq_id, 0, p);
+		}
+		bpf_rcu_read_unlock();
+	}
+
+	bpf_for(cpu, 0, SCX_DSQ_DUMP_MAX_CPUS) {
+		if (cpu >= nr_cpus)
+			break;
+		scx_dsq_dump_emit(SCX_DSQ_DUMP_REC_CPU, cpu,
+				  scx_bpf_dsq_nr_queued(SCX_DSQ_LOCAL_ON | cpu),
+				  NULL);
+	}
+
+	return 0;
+}
+
+#endif /* __SCX_DSQ_DUMP_BPF_H__ */
@@ -0,0 +1,41 @@
+#ifndef __SCX_DSQ_DUMP_INTF_H__
+#define __SCX_DSQ_DUMP_INTF_H__
+
+/*
+ * Input and output records of the scx_dsq_dump() program in dsq_dump.bpf.h.
+ * Shared with userspace through scx_utils bindings, see
+ * scx_utils::dsq_dump.
+ */
+enum scx_dsq_dump_consts {
+	SCX_DSQ_DUMP_MAX_DSQS	= 64,	/* max DSQs per dump */
+	SCX_DSQ_DUMP_MAX_TASKS	= 32,	/* max tasks listed per DSQ */
+	SCX_DSQ_DUMP_MAX_CPUS	= 1024,
+
+	SCX_DSQ_DUMP_REC_DSQ	= 1,
+	SCX_DSQ_DUMP_REC_TASK	= 2,
+	SCX_DSQ_DUMP_REC_CPU	= 3,
+};
+
+/* input of scx_dsq_dump(), passed as the program context */
+struct scx_dsq_dump_args {
+	u64			dsq_ids[SCX_DSQ_DUMP_MAX_DSQS];
+	u32			nr_dsqs;
+	u32			nr_cpus;
+};
+
+/*
+ * Output record. REC_DSQ and REC_CPU carry the DSQ ID or CPU in @id and the
+ * queue depth in @nr_queued. REC_TASK follows its REC_DSQ and describes one
+ * queued task.
+ */
+struct scx_dsq_dump_rec {
+	u32			kind;
+	s32			nr_queued;
+	u64			id;
+	s32			pid;
+	u32			pad;
+	u64			runnable_ms;	/* how long the task has been runnable */
+	char			comm[16];
+};
+
+#endif /* __SCX_DSQ_DUMP_INTF_H__ */
@@ -0,0 +1,111 @@
+#ifndef __SCX_EVENTS_BPF_H__
+#define __SCX_EVENTS_BPF_H__
+
+#include "events_intf.h"
+
+/*
+ * Scheduling event collection. Including this header adds BPF programs which
+ * stream scheduling events into the @scx_events ring buffer. The programs
+ * attach to the scheduler tracepoints and, for kernels where a tracepoint
+ * can't be attached with BTF, kprobe based fallbacks are provided. Userspace
+ * should select which of the two to load with scx_events_select_progs!() and
+ * parse the events with scx_utils::events::SchedEvent.
+ *
+ * Collection is off until userspace sets the corresponding bits
+ * (1 << SCX_EVENT_*) in @scx_events_mask. Assumes vmlinux.h and
+ * scx/common.bpf.h have already been included.
+ */
+
+#ifndef SCX_EVENTS_RB_SIZE
+#define SCX_EVENTS_RB_SIZE	(256 * 1024)
+#endif
+
+struct {
+	__uint(type, BPF_MAP_TYPE_RINGBUF);
+	__uint(max_entries, SCX_EVENTS_RB_SIZE);
+} scx_events SEC(".maps");
+
+volatile u32 scx_events_mask;
+volatile u64 scx_events_dropped;
+
+static __always_inline void scx_event_emit(u32 kind, s32 pid, s32 prev_pid,
+					   s32 target_cpu)
+{
+	struct scx_event *ev;
+
+	if (!(scx_events_mask & (1U << kind)))
+		return;
+
+	ev = bpf_ringbuf_reserve(&scx_events, sizeof(*ev), 0);
+	if (!ev) {
+		__sync_fetch_and_add(&scx_events_dropped, 1);
+		return;
+	}
+
+	ev->ts = bpf_ktime_get_ns();
+	ev->kind = kind;
+	ev->cpu = bpf_get_smp_processor_id();
+	ev->pid = pid;
+	ev->prev_pid = prev_pid;
+	ev->target_cpu = target_cpu;
+	ev->pad = 0;
+
+	bpf_ringbuf_submit(ev, 0);
+}
+
+SEC("tp_btf/sched_wakeup")
+int BPF_PROG(scx_ev_tp_wakeup, struct task_struct *p)
+{
+	scx_event_emit(SCX_EVENT_WAKEUP, p->pid, 0, scx_bpf_task_cpu(p));
+	return 0;
+}
+
+SEC("tp_btf/sched_switch")
+int BPF_PROG(scx_ev_tp_switch, bool preempt, struct task_struct *prev,
+	     struct task_struct *next)
+{
+	scx_event_emit(SCX_EVENT_SWITCH, next->pid, prev->pid, -1);
+	return 0;
+}
+
+SEC("tp_btf/sched_migrate_task")
+int BPF_PROG(scx_ev_tp_migrate, struct task_struct *p, int dest_cpu)
+{
+	scx_event_emit(SCX_EVENT_MIGRATE, p->pid, 0, dest_cpu);
+	return 0;
+}
+
+SEC("tp_btf/sched_process_exit")
+int BPF_PROG(scx_ev_tp_exit, struct task_struct *p)
+{
+	scx_event_emit(SCX_EVENT_EXIT, p->pid, 0, -1);
+	return 0;
+}
+
+/*
+ * kprobe fallbacks. There is no reasonable kprobe equivalent of sched_switch,
+ * so context switches are only reported through the tracepoint. The wakeup
+ * target CPU isn't known yet on try_to_wake_up() entry.
+ */
+SEC("kprobe/try_to_wake_up")
+int BPF_KPROBE(scx_ev_kp_wakeup, struct task_struct *p)
+{
+	scx_event_emit(SCX_EVENT_WAKEUP, BPF_CORE_READ(p, pid), 0, -1);
+	return 0;
+}
+
+SEC("kprobe/set_task_cpu")
+int BPF_KPROBE(scx_ev_kp_migrate, struct task_struct *p, unsigned int new_cpu)
+{
+	scx_event_emit(SCX_EVENT_MIGRATE, BPF_CORE_READ(p, pid), 0, new_cpu);
+	return 0;
+}
+
+SEC("kprobe/do_exit")
+int BPF_KPROBE(scx_ev_kp_exit)
+{
+	scx_event_emit(SCX_EVENT_EXIT, (u32)bpf_get_current_pid_tgid(), 0, -1);
+	return 0;
+}
+
+#endif /* __SCX_EVENTS_BPF_H__ */
@@ -0,0 +1,25 @@
+#ifndef __SCX_EVENTS_INTF_H__
+#define __SCX_EVENTS_INTF_H__
+
+/*
+ * Scheduling event records of events.bpf.h. Shared with userspace through
+ * scx_utils bindings, see scx_utils::events.
+ */
+enum scx_event_kind {
+	SCX_EVENT_WAKEUP	= 1,
+	SCX_EVENT_SWITCH	= 2,
+	SCX_EVENT_MIGRATE	= 3,
+	SCX_EVENT_EXIT		= 4,
+};
+
+struct scx_event {
+	u64			ts;
+	u32			kind;
+	u32			cpu;		/* CPU which generated the event */
+	s32			pid;		/* woken, next, migrated or exiting */
+	s32			prev_pid;	/* SWITCH: previous task */
+	s32			target_cpu;	/* WAKEUP/MIGRATE: target CPU, -1 if unknown */
+	u32			pad;
+};
+
+#endif /* __SCX_EVENTS_INTF_H__ */
@@ -0,0 +1,94 @@
+#ifndef __SCX_IDLE_BPF_H__
+#define __SCX_IDLE_BPF_H__
+
+#include "idle_intf.h"
+
+/*
+ * Parameterized idle CPU selection to be used from ops.select_cpu().
+ * Assumes vmlinux.h and common.bpf.h have already been included.
+ *
+ * scx_idle_pick_cpu() looks for an idle CPU which @p can run on. If
+ * SCX_IDLE_SMT_FIRST is set, it first looks for a CPU whose whole core is
+ * idle and then for any idle CPU. At each of these levels, the candidates
+ * are tried in the following order, each step enabled by a flag:
+ *
+ * 1. @prev_cpu (SCX_IDLE_PREV_STICKY).
+ * 2. scx_idle_pref_cpus[] in order (SCX_IDLE_PREF_ORDER), e.g. the CPUs
+ *    sorted by max frequency on systems with preferred cores.
+ * 3. @llc_mask (SCX_IDLE_LLC_LOCAL), which should be the CPUs sharing the
+ *    LLC with @prev_cpu that @p is allowed to run on.
+ * 4. All CPUs in @p->cpus_ptr.
+ *
+ * The picked CPU is claimed, i.e. cleared from the idle masks. Returns
+ * -EBUSY if there is no idle CPU.
+ */
+
+/* set by userspace before load, the first scx_idle_nr_pref_cpus are used */
+const volatile s32 scx_idle_pref_cpus[SCX_IDLE_MAX_CPUS];
+const volatile u32 scx_idle_nr_pref_cpus;
+
+static bool __scx_idle_claim(s32 cpu, const struct cpumask *idle_smtmask, bool core)
+{
+	if (core && !bpf_cpumask_test_cpu(cpu, idle_smtmask))
+		return false;
+	return scx_bpf_test_and_clear_cpu_idle(cpu);
+}
+
+static __always_inline s32 __scx_idle_pick_level(const struct task_struct *p,
+						 s32 prev_cpu,
+						 const struct cpumask *llc_mask,
+						 const struct cpumask *idle_smtmask,
+						 u64 flags, bool core)
+{
+	u64 pick_flags = core ? SCX_PICK_IDLE_CORE : 0;
+	u32 i, nr_pref = scx_idle_nr_pref_cpus;
+	s32 cpu;
+
+	if ((flags & SCX_IDLE_PREV_STICKY) &&
+	    bpf_cpumask_test_cpu(prev_cpu, p->cpus_ptr) &&
+	    __scx_idle_claim(prev_cpu, idle_smtmask, core))
+		return prev_cpu;
+
+	if (flags & SCX_IDLE_PREF_ORDER) {
+		bpf_for(i, 0, nr_pref) {
+			if (i >= SCX_IDLE_MAX_CPUS)
+				break;
+			cpu = scx_idle_pref_cpus[i];
+			if (cpu >= 0 && bpf_cpumask_test_cpu(cpu, p->cpus_ptr) &&
+			    __scx_idle_claim(cpu, idle_smtmask, core))
+				return cpu;
+		}
+	}
+
+	if ((flags & SCX_IDLE_LLC_LOCAL) && llc_mask) {
+		cpu = scx_bpf_pick_idle_cpu(llc_mask, pick_flags);
+		if (cpu >= 0)
+			return cpu;
+	}
+
+	return scx_bpf_pick_idle_cpu(p->cpus_ptr, pick_flags);
+}
+
+static __always_inline s32 scx_idle_pick_cpu(const struct task_struct *p,
+					     s32 prev_cpu,
+					     const struct cpumask *llc_mask,
+					     u64 flags)
+{
+	const struct cpumask *idle_smtmask = scx_bpf_get_idle_smtmask();
+	s32 cpu;
+
+	if (flags & SCX_IDLE_SMT_FIRST) {
+		cpu = __scx_idle_pick_level(p, prev_cpu, llc_mask, idle_smtmask,
+					    flags, true);
+		if (cpu >= 0)
+			goto out;
+	}
+
+	cpu = __scx_idle_pick_level(p, prev_cpu, llc_mask, idle_smtmask,
+				    flags, false);
+out:
+	scx_bpf_put_idle_cpumask(idle_smtmask);
+	return cpu;
+}
+
+#endif /* __SCX_IDLE_BPF_H__ */
@@ -0,0 +1,18 @@
+#ifndef __SCX_IDLE_INTF_H__
+#define __SCX_IDLE_INTF_H__
+
+/*
+ * Parameters of scx_idle_pick_cpu() in idle.bpf.h.
+ */
+enum scx_idle_flags {
+	SCX_IDLE_PREV_STICKY	= 1 << 0,	/* try @prev_cpu first */
+	SCX_IDLE_SMT_FIRST	= 1 << 1,	/* prefer fully idle cores */
+	SCX_IDLE_LLC_LOCAL	= 1 << 2,	/* prefer @llc_mask */
+	SCX_IDLE_PREF_ORDER	= 1 << 3,	/* try scx_idle_pref_cpus[] in order */
+};
+
+enum scx_idle_consts {
+	SCX_IDLE_MAX_CPUS	= 1024,		/* size of scx_idle_pref_cpus[] */
+};
+
+#endif /* __SCX_IDLE_INTF_H__ */
@@ -0,0 +1,91 @@
+#ifndef __SCX_NICE_BPF_H__
+#define __SCX_NICE_BPF_H__
+
+#include "nice_intf.h"
+#include "weight.bpf.h"
+
+/*
+ * Nice and latency nice of tasks and their mapping to weights and
+ * deadlines so that the same nice values mean the same thing across
+ * schedulers. Assumes vmlinux.h and bpf_core_read.h have already been
+ * included.
+ *
+ * Nice maps to the weight through scx_nice_to_weight(). Latency nice
+ * doesn't affect the CPU share but scales how far in the future the
+ * deadline of a task is placed. Each latency nice level moves the deadline
+ * by the same ~1.25x factor as a nice level changes the weight, bounded by
+ * SCX_LAT_SCALE_MAX in either direction.
+ *
+ * scx_utils::nice mirrors the mapping in Rust. Keep the two in sync.
+ */
+
+/* task_struct of kernels with the latency nice patchset */
+struct task_struct___latency {
+	int latency_prio;
+} __attribute__((preserve_access_index));
+
+/* nice of @p */
+static inline s32 scx_task_nice(struct task_struct *p)
+{
+	return p->static_prio - SCX_DFL_PRIO;
+}
+
+/* latency nice of @p, 0 if the kernel doesn't support latency nice */
+static inline s32 scx_task_latency_nice(struct task_struct *p)
+{
+	struct task_struct___latency *pl = (void *)p;
+
+	if (!bpf_core_field_exists(pl->latency_prio))
+		return 0;
+	return BPF_CORE_READ(pl, latency_prio) - SCX_DFL_LATENCY_PRIO;
+}
+
+/*
+ * Scale @val by the latency nice @lat_nice, e.g. a deadline offset. If
+ * @val is too large to be multiplied first, it's divided first and the
+ * result saturates instead of wrapping.
+ */
+static inline u64 scx_lat_nice_scale(u64 val, s32 lat_nice)
+{
+	u64 load = scx_nice_to_load(lat_nice);
+	u64 scaled, max = ~0ULL;
+
+	if (val <= ~0ULL / SCX_NICE_0_LOAD)
+		scaled = val * SCX_NICE_0_LOAD / load;
+	else if (val / load <= ~0ULL / SCX_NICE_0_LOAD)
+		scaled = val / load * SCX_NICE_0_LOAD;
+	else
+		scaled = ~0ULL;
+
+	/* saturate the upper bound so that it can't wrap below the lower one */
+	if (val <= max / SCX_LAT_SCALE_MAX)
+		max = val * SCX_LAT_SCALE_MAX;
+
+	if (scaled < val / SCX_LAT_SCALE_MAX)
+		return val / SCX_LAT_SCALE_MAX;
+	if (scaled > max)
+		return max;
+	return scaled;
+}
+
+/*
+ * scx_vtime_deadline() adjusted by @lat_nice. A task with a lower latency
+ * nice gets an earlier deadline and thus runs sooner after waking up
+ * without getting more CPU time in the long run.
+ */
+static inline u64 scx_lat_nice_deadline(u64 vtime, u64 slice_ns, u32 weight,
+					s32 lat_nice)
+{
+	return vtime + scx_lat_nice_scale(scx_vtime_delta(slice_ns, weight),
+					  lat_nice);
+}
+
+/* scx_lat_nice_deadline() with the weight and latency nice of @p */
+static inline u64 scx_task_deadline(struct task_struct *p, u64 vtime,
+				    u64 slice_ns)
+{
+	return scx_lat_nice_deadline(vtime, slice_ns, p->scx.weight,
+				     scx_task_latency_nice(p));
+}
+
+#endif /* __SCX_NICE_BPF_H__ */
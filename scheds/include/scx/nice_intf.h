@@ -0,0 +1,19 @@
+#ifndef __SCX_NICE_INTF_H__
+#define __SCX_NICE_INTF_H__
+
+/*
+ * Nice and latency nice ranges and the bounds of the latency nice deadline
+ * scaling in nice.bpf.h. Shared with userspace through scx_utils bindings,
+ * see scx_utils::nice.
+ */
+enum scx_nice_consts {
+	SCX_NICE_MIN		= -20,
+	SCX_NICE_MAX		= 19,
+	SCX_LAT_NICE_MIN	= -20,
+	SCX_LAT_NICE_MAX	= 19,
+	SCX_DFL_PRIO		= 120,		/* static_prio of nice 0 */
+	SCX_DFL_LATENCY_PRIO	= 20,		/* latency_prio of latency nice 0 */
+	SCX_LAT_SCALE_MAX	= 16,		/* max deadline scaling either way */
+};
+
+#endif /* __SCX_NICE_INTF_H__ */
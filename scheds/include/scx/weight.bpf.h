@@ -0,0 +1,116 @@
+#ifndef __SCX_WEIGHT_BPF_H__
+#define __SCX_WEIGHT_BPF_H__
+
+/*
+ * Nice to weight conversion and weight-scaled vtime arithmetic to be used
+ * in BPF progs. Assumes vmlinux.h has already been included.
+ *
+ * scx_utils::weight mirrors this file and its table is generated from
+ * scx_prio_to_weight[] below at build time, so keep the table's format -
+ * one brace-enclosed list of decimal numbers - when touching it.
+ */
+enum scx_weight_consts {
+	SCX_NICE_WIDTH		= 40,		/* nice -20 .. 19 */
+	SCX_NICE_0_LOAD		= 1024,		/* kernel load weight of nice 0 */
+	SCX_WEIGHT_MIN		= 1,		/* p->scx.weight range */
+	SCX_WEIGHT_DFL		= 100,
+	SCX_WEIGHT_MAX		= 10000,
+};
+
+/*
+ * The kernel's sched_prio_to_weight[]. Each nice level is ~1.25x the
+ * weight of the next one so that a CPU-bound task gets ~10% more CPU time
+ * than a task one nice level below.
+ */
+static const u32 scx_prio_to_weight[SCX_NICE_WIDTH] = {
+ /* -20 */     88761,     71755,     56483,     46273,     36291,
+ /* -15 */     29154,     23254,     18705,     14949,     11916,
+ /* -10 */      9548,      7620,      6100,      4904,      3906,
+ /*  -5 */      3121,      2501,      1991,      1586,      1277,
+ /*   0 */      1024,       820,       655,       526,       423,
+ /*   5 */       335,       272,       215,       172,       137,
+ /*  10 */       110,        87,        70,        56,        45,
+ /*  15 */        36,        29,        23,        18,        15,
+};
+
+/*
+ * Convert a kernel load weight to the sched_ext weight in p->scx.weight.
+ * Matches sched_weight_to_cgroup() in the kernel. Loads large enough to
+ * overflow the scaling saturate to SCX_WEIGHT_MAX.
+ */
+static inline u32 scx_load_to_weight(u64 load)
+{
+	u64 weight;
+
+	if (load > (~0ULL - SCX_NICE_0_LOAD / 2) / SCX_WEIGHT_DFL)
+		return SCX_WEIGHT_MAX;
+
+	weight = (load * SCX_WEIGHT_DFL + SCX_NICE_0_LOAD / 2) / SCX_NICE_0_LOAD;
+	if (weight < SCX_WEIGHT_MIN)
+		return SCX_WEIGHT_MIN;
+	if (weight > SCX_WEIGHT_MAX)
+		return SCX_WEIGHT_MAX;
+	return weight;
+}
+
+/* kernel load weight of @nice, which is clamped to [-20, 19] */
+static inline u32 scx_nice_to_load(s32 nice)
+{
+	u32 idx;
+
+	if (nice < -20)
+		nice = -20;
+	if (nice > 19)
+		nice = 19;
+
+	idx = nice + 20;
+	if (idx >= SCX_NICE_WIDTH)
+		return SCX_NICE_0_LOAD;
+	return scx_prio_to_weight[idx];
+}
+
+/* p->scx.weight of a task with @nice */
+static inline u32 scx_nice_to_weight(s32 nice)
+{
+	return scx_load_to_weight(scx_nice_to_load(nice));
+}
+
+/* scale @val up by @weight, e.g. to give heavier tasks a larger budget */
+static inline u64 scx_scale_by_weight(u64 val, u32 weight)
+{
+	return val * weight / SCX_WEIGHT_DFL;
+}
+
+/* scale @val down by @weight, e.g. to charge vtime for @val nsecs of runtime */
+static inline u64 scx_scale_by_weight_inverse(u64 val, u32 weight)
+{
+	if (!weight)
+		weight = SCX_WEIGHT_MIN;
+	return val * SCX_WEIGHT_DFL / weight;
+}
+
+/* vtime to charge a task with @weight for running @delta_ns */
+static inline u64 scx_vtime_delta(u64 delta_ns, u32 weight)
+{
+	return scx_scale_by_weight_inverse(delta_ns, weight);
+}
+
+/*
+ * Limit the vtime budget a sleeping task can accumulate to @slice_ns so
+ * that it can't monopolize the CPU after waking up. @vtime_now is the
+ * scheduler's global vtime.
+ */
+static inline u64 scx_vtime_clamp(u64 vtime, u64 vtime_now, u64 slice_ns)
+{
+	if (vtime_now > slice_ns && (s64)(vtime - (vtime_now - slice_ns)) < 0)
+		return vtime_now - slice_ns;
+	return vtime;
+}
+
+/* deadline of a task with @weight and @vtime which is about to run @slice_ns */
+static inline u64 scx_vtime_deadline(u64 vtime, u64 slice_ns, u32 weight)
+{
+	return vtime + scx_vtime_delta(slice_ns, weight);
+}
+
+#endif /* __SCX_WEIGHT_BPF_H__ */
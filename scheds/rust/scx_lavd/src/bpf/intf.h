@@ -66,6 +66,10 @@ enum consts {
 	LAVD_LC_FREQ_MAX		= 1000000,
 	LAVD_LC_RUNTIME_MAX		= LAVD_TARGETED_LATENCY_NS,
 	LAVD_LC_RUNTIME_SHIFT		= 10,
+	LAVD_LC_FOCUS_BOOST		= 4, /* LC boost of the focused application */
+	LAVD_LC_WEIGHT_ONE		= 1000, /* cgroup LC weight of 1.0 */
+	LAVD_CGRP_LC_WEIGHT_MAX		= 1024, /* max cgroups with an LC weight */
+	LAVD_CGRP_MAX_LEVEL		= 32,
 
 	LAVD_BOOST_RANGE		= 14, /* 35% of nice range */
 	LAVD_BOOST_WAKEUP_LAT		= 1,
@@ -110,6 +114,14 @@ struct sys_cpu_util {
 	volatile u64	avg_perf_cri;	/* average performance criticality */
 };
 
+/*
+ * Statistics
+ */
+enum {
+	LAVD_STAT_LC_BKT_SHIFT		= 3, /* LC bucket width in log2 */
+	LAVD_STAT_NR_LC_BKTS		= 8, /* number of LC buckets */
+};
+
 /*
  * Per-CPU context
  */
@@ -143,6 +155,15 @@ struct cpu_ctx {
 	 */
 	volatile u64	sum_perf_cri;	/* sum of performance criticality */
 
+	/*
+	 * Cumulative statistics reported to the userspace, which are never
+	 * reset while the scheduler is running
+	 */
+	volatile u64	nr_lc_bkt[LAVD_STAT_NR_LC_BKTS]; /* schedules per LC bucket */
+	volatile u64	nr_lat_cri;	/* schedules of latency-critical tasks */
+	volatile u64	nr_preempt;	/* preemptions by kicking a victim CPU */
+	volatile u64	nr_yield;	/* yields to a higher priority task */
+
 	/*
 	 * Information of a current running task for preemption
 	 */
@@ -120,11 +120,13 @@ char _license[] SEC("license") = "GPL";
  * Sched related globals
  */
 volatile u64			nr_cpus_onln;
+volatile u32			focus_tgid;	/* set by the userspace */
 
 static struct sys_cpu_util	__sys_cpu_util[2];
 static volatile int		__sys_cpu_util_idx;
 
 const volatile bool		no_freq_scaling;
+const volatile bool		has_cgrp_lat_weights;
 const volatile u8		verbose;
 
 UEI_DEFINE(uei);
@@ -171,6 +173,18 @@ struct {
 	__uint(max_entries, 1);
 } cpu_ctx_stor SEC(".maps");
 
+/*
+ * Latency criticality weights of cgroups, which are configured by the
+ * userspace. The key is a cgroup ID and the value is a weight where
+ * LAVD_LC_WEIGHT_ONE means 1.0.
+ */
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__type(key, u64);
+	__type(value, u32);
+	__uint(max_entries, LAVD_CGRP_LC_WEIGHT_MAX);
+} cgrp_lat_weights SEC(".maps");
+
 /*
  * Per-task scheduling context
  */
@@ -1047,6 +1061,33 @@ static int map_lat_cri_to_lat_prio(u64 lat_cri)
 	return lat_prio;
 }
 
+static u64 get_cgrp_lat_weight(struct task_struct *p)
+{
+	struct cgroup *cgrp = p->cgroups->dfl_cgrp;
+	int i, level, max_level;
+	u32 *weight;
+	u64 cgid;
+
+	/*
+	 * The weight of the closest ancestor applies so that a weight
+	 * configured for a slice covers all the services under it unless
+	 * a service has its own weight.
+	 */
+	max_level = BPF_CORE_READ(cgrp, level);
+	bpf_for(i, 0, LAVD_CGRP_MAX_LEVEL) {
+		level = max_level - i;
+		if (level < 0)
+			break;
+
+		cgid = BPF_CORE_READ(cgrp, ancestors[level], kn, id);
+		weight = bpf_map_lookup_elem(&cgrp_lat_weights, &cgid);
+		if (weight)
+			return *weight;
+	}
+
+	return LAVD_LC_WEIGHT_ONE;
+}
+
 static int boost_lat(struct task_struct *p, struct task_ctx *taskc,
 		     struct cpu_ctx *cpuc, bool is_wakeup)
 {
@@ -1119,6 +1160,24 @@ static int boost_lat(struct task_struct *p, struct task_ctx *taskc,
 	 */
 	taskc->lat_cri = bpf_log2l(lat_cri_raw + 1);
 
+	/*
+	 * Scale the latency criticality by the weight of @p's cgroup. This
+	 * allows, for example, audio services to be favored consistently even
+	 * when their measured behavior is not latency-critical after idling.
+	 */
+	if (has_cgrp_lat_weights)
+		taskc->lat_cri = (taskc->lat_cri * get_cgrp_lat_weight(p)) /
+				 LAVD_LC_WEIGHT_ONE;
+
+	/*
+	 * Threads of the focused application (e.g., a fullscreen game) are
+	 * considered more latency-critical than their measured behavior so
+	 * that they are not overshadowed by background work such as video
+	 * encoders.
+	 */
+	if (focus_tgid && p->tgid == focus_tgid)
+		taskc->lat_cri += LAVD_LC_FOCUS_BOOST;
+
 	/*
 	 * Convert @p's latency criticality to its boost priority linearly.
 	 * When a task is wakening up, boost its latency boost priority by 1.
@@ -1286,6 +1345,23 @@ static void update_stat_for_runnable(struct task_struct *p,
 	cpuc->load_run_time_ns += cap_time_slice_ns(taskc->run_time_ns);
 }
 
+static void update_lat_cri_stat(struct task_ctx *taskc, struct cpu_ctx *cpuc)
+{
+	struct sys_cpu_util *cutil_cur = get_sys_cpu_util_cur();
+	u64 bkt = taskc->lat_cri >> LAVD_STAT_LC_BKT_SHIFT;
+
+	if (bkt >= LAVD_STAT_NR_LC_BKTS)
+		bkt = LAVD_STAT_NR_LC_BKTS - 1;
+	cpuc->nr_lc_bkt[bkt]++;
+
+	/*
+	 * A task is considered latency-critical when its latency criticality
+	 * is high enough to kick other CPUs (see is_worth_kick_other_task()).
+	 */
+	if (taskc->lat_cri >= cutil_cur->thr_lat_cri)
+		cpuc->nr_lat_cri++;
+}
+
 static void update_stat_for_running(struct task_struct *p,
 				    struct task_ctx *taskc,
 				    struct cpu_ctx *cpuc)
@@ -1316,6 +1392,11 @@ static void update_stat_for_running(struct task_struct *p,
 	cpuc->sum_lat_cri += taskc->lat_cri;
 	cpuc->sched_nr++;
 
+	/*
+	 * Account the schedule to the latency criticality statistics.
+	 */
+	update_lat_cri_stat(taskc, cpuc);
+
 	/*
 	 * Update task's performance criticality
 	 *
@@ -1638,7 +1719,7 @@ static bool kick_cpu(struct cpu_ctx *victim_cpuc, u64 victim_last_kick_clk)
 static bool try_find_and_kick_victim_cpu(const struct cpumask *cpumask,
 					 struct task_ctx *taskc)
 {
-	struct cpu_ctx *victim_cpuc;
+	struct cpu_ctx *victim_cpuc, *cpuc;
 	u64 victim_last_kick_clk;
 	bool ret = false;
 
@@ -1655,6 +1736,8 @@ static bool try_find_and_kick_victim_cpu(const struct cpumask *cpumask,
 
 	if (!ret)
 		taskc->victim_cpu = (s32)LAVD_CPU_ID_NONE;
+	else if ((cpuc = get_cpu_ctx()))
+		cpuc->nr_preempt++;
 
 	return ret;
 }
@@ -1700,8 +1783,10 @@ static bool try_yield_current_cpu(struct task_struct *p_run,
 			ret = __sync_bool_compare_and_swap(
 					&taskc_wait->victim_cpu,
 					(s32)LAVD_CPU_ID_NONE, cpu_id);
-			if (ret)
+			if (ret) {
 				p_run->scx.slice = 0;
+				cpuc_run->nr_yield++;
+			}
 		}
 
 		/*
@@ -11,26 +11,40 @@ pub use bpf_skel::*;
 pub mod bpf_intf;
 pub use bpf_intf::*;
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 use libc::c_char;
 use std::ffi::CStr;
 use std::str;
 
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use clap::Parser;
 use libbpf_rs::skel::OpenSkel as _;
 use libbpf_rs::skel::SkelBuilder as _;
 use log::info;
+use log::warn;
+use scx_utils::bench::Bench;
+use scx_utils::bench::BenchOpts;
+use scx_utils::cgroup;
+use scx_utils::journal;
+use scx_utils::cpufreq::CpuFreqSnapshot;
 use scx_utils::scx_ops_attach;
 use scx_utils::scx_ops_load;
 use scx_utils::uei_exited;
 use scx_utils::uei_report;
 use scx_utils::Topology;
+use serde::Serialize;
 
 use nix::sys::signal;
 use plain::Plain;
@@ -38,6 +52,11 @@ use rlimit::{getrlimit, setrlimit, Resource};
 
 static RUNNING: AtomicBool = AtomicBool::new(true);
 
+/// Resolving the cgroups of --cgroup-lat-weights stats each of them. New
+/// cgroups needn't be picked up immediately, so don't do it every iteration
+/// of the main loop.
+const CGRP_LAT_WEIGHTS_REFRESH_INTV: Duration = Duration::from_secs(1);
+
 /// scx_lavd: Latency-criticality Aware Virtual Deadline (LAVD) scheduler
 ///
 /// The rust part is minimal. It processes command line options and logs out
@@ -57,10 +76,40 @@ struct Opts {
     #[clap(short = 'p', long, default_value = "0")]
     pid_traced: u64,
 
+    /// File containing the PID of the focused application, e.g. a
+    /// fullscreen game. The file is re-read whenever it's modified and
+    /// threads of the process get their latency criticality boosted. A
+    /// compositor or launcher hook (e.g. a gamescope wrapper script) is
+    /// expected to write the PID. An empty or missing file, or 0, clears
+    /// the focus.
+    #[clap(long)]
+    focus_hint_file: Option<PathBuf>,
+
+    /// JSON file mapping cgroup paths to latency criticality weights, e.g.
+    /// {"system.slice/pipewire.service": 1.5, "system.slice/backup.service": 0.5}.
+    /// The latency criticality of a task is multiplied by the weight of the
+    /// closest configured ancestor cgroup. Paths are relative to the cgroup2
+    /// root and cgroups that don't exist yet are picked up when created.
+    #[clap(long)]
+    cgroup_lat_weights: Option<PathBuf>,
+
+    /// Interval in seconds to report latency-criticality, preemption and
+    /// CPU frequency statistics. 0 disables the report.
+    #[clap(long, default_value = "0")]
+    stats: u64,
+
+    #[clap(flatten)]
+    bench: BenchOpts,
+
     /// Exit debug dump buffer length. 0 indicates default.
     #[clap(long, default_value = "0")]
     exit_dump_len: u32,
 
+    /// Send log output and exit reports to journald instead of stderr.
+    /// Falls back to stderr if journald isn't running.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    journald: bool,
+
     /// Enable verbose output including libbpf details. Specify multiple
     /// times to increase verbosity.
     #[clap(short = 'v', long, action = clap::ArgAction::Count)]
@@ -97,12 +146,232 @@ impl introspec {
     }
 }
 
+/// Cumulative statistics summed over all CPUs. See the statistics fields in
+/// struct cpu_ctx.
+#[derive(Clone, Debug, Default, Serialize)]
+struct Stats {
+    nr_lc_bkt: [u64; LAVD_STAT_NR_LC_BKTS as usize],
+    nr_lat_cri: u64,
+    nr_preempt: u64,
+    nr_yield: u64,
+}
+
+impl Stats {
+    fn read(skel: &BpfSkel) -> Result<Self> {
+        let cpu_ctxs = skel
+            .maps()
+            .cpu_ctx_stor()
+            .lookup_percpu(&0u32.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
+            .context("Failed to lookup cpu_ctx")?
+            .context("No cpu_ctx in cpu_ctx_stor")?;
+
+        let mut stats = Stats::default();
+        for buf in cpu_ctxs.iter() {
+            let cpuc = unsafe { &*(buf.as_slice().as_ptr() as *const cpu_ctx) };
+            for (sum, nr) in stats.nr_lc_bkt.iter_mut().zip(cpuc.nr_lc_bkt.iter()) {
+                *sum += nr;
+            }
+            stats.nr_lat_cri += cpuc.nr_lat_cri;
+            stats.nr_preempt += cpuc.nr_preempt;
+            stats.nr_yield += cpuc.nr_yield;
+        }
+        Ok(stats)
+    }
+
+    fn delta(&self, rhs: &Self) -> Self {
+        let mut nr_lc_bkt = self.nr_lc_bkt;
+        for (nr, prev) in nr_lc_bkt.iter_mut().zip(rhs.nr_lc_bkt.iter()) {
+            *nr -= prev;
+        }
+        Self {
+            nr_lc_bkt,
+            nr_lat_cri: self.nr_lat_cri - rhs.nr_lat_cri,
+            nr_preempt: self.nr_preempt - rhs.nr_preempt,
+            nr_yield: self.nr_yield - rhs.nr_yield,
+        }
+    }
+
+    fn report(&self, elapsed: Duration) {
+        let nr_sched: u64 = self.nr_lc_bkt.iter().sum();
+        let secs = elapsed.as_secs_f64();
+        let pct = |v: u64| {
+            if nr_sched > 0 {
+                v as f64 * 100.0 / nr_sched as f64
+            } else {
+                0.0
+            }
+        };
+
+        info!(
+            "sched={:.1}/s lat_cri={:5.1}% preempt={:.1}/s yield={:.1}/s",
+            nr_sched as f64 / secs,
+            pct(self.nr_lat_cri),
+            self.nr_preempt as f64 / secs,
+            self.nr_yield as f64 / secs,
+        );
+
+        let bkts: Vec<String> = self
+            .nr_lc_bkt
+            .iter()
+            .enumerate()
+            .map(|(i, nr)| {
+                let lo = i << LAVD_STAT_LC_BKT_SHIFT;
+                format!("{:>2}+:{:5.1}%", lo, pct(*nr))
+            })
+            .collect();
+        info!("lat_cri dist: {}", bkts.join(" "));
+    }
+}
+
+/// The focus hint file is polled every iteration of the main loop. It's
+/// only re-read when its modification time changes and only parsed when
+/// its content changes.
+struct FocusHint {
+    path: PathBuf,
+    mtime: Option<SystemTime>,
+    content: String,
+}
+
+impl FocusHint {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            mtime: None,
+            content: String::new(),
+        }
+    }
+
+    /// The PID of the focused application if the file changed since the
+    /// last call, 0 for no focus.
+    fn poll(&mut self) -> Option<u32> {
+        let mtime = std::fs::metadata(&self.path)
+            .and_then(|md| md.modified())
+            .ok();
+        if mtime == self.mtime {
+            return None;
+        }
+        self.mtime = mtime;
+
+        let content = std::fs::read_to_string(&self.path).unwrap_or_default();
+        if content == self.content {
+            return None;
+        }
+        self.content = content;
+
+        match self.content.trim() {
+            "" => Some(0),
+            pid => Some(pid.parse::<u32>().unwrap_or_else(|_| {
+                warn!("Invalid PID {:?} in {:?}", pid, &self.path);
+                0
+            })),
+        }
+    }
+}
+
+/// Latency criticality weights of cgroups. The cgroup paths are resolved to
+/// cgroup IDs every CGRP_LAT_WEIGHTS_REFRESH_INTV as cgroups may come and go.
+struct CgroupLatWeights {
+    root: PathBuf,
+    weights: Vec<(String, u32)>,
+    cgids: HashMap<String, u64>,
+    refresh_at: Instant,
+}
+
+impl CgroupLatWeights {
+    fn load(path: &PathBuf) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {:?}", path))?;
+        let cfg: BTreeMap<String, f64> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {:?}", path))?;
+
+        if cfg.len() > consts_LAVD_CGRP_LC_WEIGHT_MAX as usize {
+            bail!(
+                "Too many cgroups in {:?} ({} > {})",
+                path,
+                cfg.len(),
+                consts_LAVD_CGRP_LC_WEIGHT_MAX
+            );
+        }
+
+        let mut weights = vec![];
+        for (cgrp, weight) in cfg.into_iter() {
+            if !weight.is_finite() || weight < 0.0 || weight > 100.0 {
+                bail!("Invalid weight {} for cgroup {:?}", weight, cgrp);
+            }
+            let weight = (weight * consts_LAVD_LC_WEIGHT_ONE as f64).round() as u32;
+            weights.push((cgrp.trim_matches('/').to_string(), weight));
+        }
+
+        Ok(Self {
+            root: cgroup::cgroup2_root()?,
+            weights,
+            cgids: HashMap::new(),
+            refresh_at: Instant::now(),
+        })
+    }
+
+    /// Update the cgrp_lat_weights map if CGRP_LAT_WEIGHTS_REFRESH_INTV has
+    /// passed since the last refresh. Cgroups whose weights can't be set
+    /// are skipped and retried on the next refresh.
+    fn refresh(&mut self, skel: &mut BpfSkel) {
+        let now = Instant::now();
+        if now < self.refresh_at {
+            return;
+        }
+        self.refresh_at = now + CGRP_LAT_WEIGHTS_REFRESH_INTV;
+
+        let mut maps = skel.maps_mut();
+        let map = maps.cgrp_lat_weights();
+
+        for (cgrp, weight) in self.weights.iter() {
+            let cgid = std::fs::metadata(self.root.join(cgrp))
+                .ok()
+                .map(|md| md.ino());
+            let prev = self.cgids.get(cgrp).copied();
+            if cgid == prev {
+                continue;
+            }
+
+            if let Some(prev) = prev {
+                // Might have been removed already when the cgroup went away.
+                let _ = map.delete(&prev.to_ne_bytes());
+                self.cgids.remove(cgrp);
+            }
+            if let Some(cgid) = cgid {
+                if let Err(e) = map.update(
+                    &cgid.to_ne_bytes(),
+                    &weight.to_ne_bytes(),
+                    libbpf_rs::MapFlags::ANY,
+                ) {
+                    warn!("Failed to set the weight of {:?} ({})", cgrp, &e);
+                    continue;
+                }
+                self.cgids.insert(cgrp.clone(), cgid);
+                info!(
+                    "Latency criticality weight {:.3} for {:?} (cgid {})",
+                    *weight as f64 / consts_LAVD_LC_WEIGHT_ONE as f64,
+                    cgrp,
+                    cgid
+                );
+            }
+        }
+    }
+}
+
 struct Scheduler<'a> {
     skel: BpfSkel<'a>,
     struct_ops: Option<libbpf_rs::Link>,
     nr_cpus_onln: u64,
     rb_mgr: libbpf_rs::RingBuffer<'static>,
     intrspc: introspec,
+    stats_intv: Duration,
+    prev_stats: Stats,
+    prev_stats_at: Instant,
+    prev_cpufreq: Option<CpuFreqSnapshot>,
+    bench: Option<Bench>,
+    bench_stats: Stats,
+    focus_hint: Option<FocusHint>,
+    cgrp_lat_weights: Option<CgroupLatWeights>,
 }
 
 impl<'a> Scheduler<'a> {
@@ -125,9 +394,17 @@ impl<'a> Scheduler<'a> {
         skel.rodata_mut().no_freq_scaling = opts.no_freq_scaling;
         skel.rodata_mut().verbose = opts.verbose;
         let intrspc = introspec::init(opts);
+        let mut cgrp_lat_weights = match &opts.cgroup_lat_weights {
+            Some(path) => Some(CgroupLatWeights::load(path)?),
+            None => None,
+        };
+        skel.rodata_mut().has_cgrp_lat_weights = cgrp_lat_weights.is_some();
 
         // Attach.
         let mut skel = scx_ops_load!(skel, lavd_ops, uei)?;
+        if let Some(weights) = cgrp_lat_weights.as_mut() {
+            weights.refresh(&mut skel);
+        }
         let struct_ops = Some(scx_ops_attach!(skel, lavd_ops)?);
 
         // Build a ring buffer for instrumentation
@@ -137,12 +414,22 @@ impl<'a> Scheduler<'a> {
         builder.add(rb_map, Scheduler::print_bpf_msg).unwrap();
         let rb_mgr = builder.build().unwrap();
 
+        let prev_stats = Stats::read(&skel)?;
+
         Ok(Self {
             skel,
             struct_ops,
             nr_cpus_onln,
             rb_mgr,
             intrspc,
+            stats_intv: Duration::from_secs(opts.stats),
+            bench: opts.bench.start("scx_lavd")?,
+            bench_stats: prev_stats.clone(),
+            prev_stats,
+            prev_stats_at: Instant::now(),
+            prev_cpufreq: CpuFreqSnapshot::read().ok(),
+            focus_hint: opts.focus_hint_file.clone().map(FocusHint::new),
+            cgrp_lat_weights,
         })
     }
 
@@ -271,20 +558,83 @@ impl<'a> Scheduler<'a> {
         }
     }
 
+    fn report_stats(&mut self) -> Result<()> {
+        let elapsed = self.prev_stats_at.elapsed();
+        if self.stats_intv.is_zero() || elapsed < self.stats_intv {
+            return Ok(());
+        }
+
+        let stats = Stats::read(&self.skel)?;
+        stats.delta(&self.prev_stats).report(elapsed);
+        self.prev_stats = stats;
+        self.prev_stats_at = Instant::now();
+
+        // Show what frequency scaling achieved over the same interval.
+        let cpufreq = CpuFreqSnapshot::read().ok();
+        if let (Some(cur), Some(prev)) = (&cpufreq, &self.prev_cpufreq) {
+            if !cur.cpus.is_empty() {
+                info!("{}", cur.delta(prev).to_string().trim_end());
+            }
+        }
+        self.prev_cpufreq = cpufreq;
+        Ok(())
+    }
+
+    /// Feed the statistics since the last call to the benchmark. Returns
+    /// true once the benchmark is done.
+    fn record_bench(&mut self) -> Result<bool> {
+        let bench = match self.bench.as_mut() {
+            Some(bench) => bench,
+            None => return Ok(false),
+        };
+
+        let stats = Stats::read(&self.skel)?;
+        bench.record(&stats.delta(&self.bench_stats))?;
+        self.bench_stats = stats;
+        Ok(bench.done())
+    }
+
+    fn update_focus(&mut self) {
+        let tgid = match self.focus_hint.as_mut().and_then(|hint| hint.poll()) {
+            Some(tgid) => tgid,
+            None => return,
+        };
+
+        let bss = self.skel.bss_mut();
+        if bss.focus_tgid != tgid {
+            match tgid {
+                0 => info!("Focus cleared"),
+                tgid => info!("Focus on PID {}", tgid),
+            }
+            bss.focus_tgid = tgid;
+        }
+    }
+
     fn running(&mut self) -> bool {
         RUNNING.load(Ordering::Relaxed) && !uei_exited!(&self.skel, uei)
     }
 
     fn run(&mut self) -> Result<()> {
         while self.running() {
+            self.update_focus();
+            if let Some(weights) = self.cgrp_lat_weights.as_mut() {
+                weights.refresh(&mut self.skel);
+            }
             let interval_ms = self.prep_introspec();
             std::thread::sleep(Duration::from_millis(interval_ms));
             self.rb_mgr.poll(Duration::from_millis(100)).unwrap();
             self.cleanup_introspec();
+            self.report_stats()?;
+            if self.record_bench()? {
+                break;
+            }
         }
         self.rb_mgr.consume().unwrap();
 
         self.struct_ops.take();
+        if let Some(bench) = self.bench.take() {
+            bench.finish()?;
+        }
         uei_report!(&self.skel, uei)
     }
 }
@@ -303,6 +653,11 @@ fn init_log(opts: &Opts) {
         1 => simplelog::LevelFilter::Debug,
         _ => simplelog::LevelFilter::Trace,
     };
+    if opts.journald && journal::journal_available() {
+        journal::init_journal_logging("scx_lavd", llv).unwrap();
+        return;
+    }
+
     let mut lcfg = simplelog::ConfigBuilder::new();
     lcfg.set_time_level(simplelog::LevelFilter::Error)
         .set_location_level(simplelog::LevelFilter::Off)
@@ -23,6 +23,7 @@ const volatile unsigned char all_cpus[MAX_CPUS_U8];
 private(all_cpumask) struct bpf_cpumask __kptr *all_cpumask;
 struct layer layers[MAX_LAYERS];
 u32 fallback_cpu;
+u64 layer_refresh_seq;
 static u32 preempt_cursor;
 
 #define dbg(fmt, args...)	do { if (debug) bpf_printk(fmt, ##args); } while (0)
@@ -230,6 +231,7 @@ struct task_ctx {
 
 	int			layer;
 	bool			refresh_layer;
+	u64			layer_refresh_seq;
 	u64			layer_cpus_seq;
 	struct bpf_cpumask __kptr *layered_cpumask;
 
@@ -667,9 +669,14 @@ static void maybe_refresh_layer(struct task_struct *p, struct task_ctx *tctx)
 	bool matched = false;
 	u64 idx;	// XXX - int makes verifier unhappy
 
-	if (!tctx->refresh_layer)
+	/*
+	 * Userspace bumps @layer_refresh_seq after rewriting the layer specs on
+	 * reload. Re-match every task which hasn't seen the latest specs yet.
+	 */
+	if (!tctx->refresh_layer && tctx->layer_refresh_seq == layer_refresh_seq)
 		return;
 	tctx->refresh_layer = false;
+	tctx->layer_refresh_seq = layer_refresh_seq;
 
 	if (!(cgrp_path = format_cgrp_path(p->cgroups->dfl_cgrp)))
 		return;
@@ -0,0 +1,261 @@
+// Copyright (c) Meta Platforms, Inc. and affiliates.
+//
+// This software may be used and distributed according to the terms of the
+// GNU General Public License version 2.
+
+//! Layer growth algorithms
+//!
+//! When a Confined or Grouped layer needs more CPUs, it's given a whole
+//! core from CpuPool. When it has more than it needs, it gives one back.
+//! Which core is picked in either direction is decided by the layer's
+//! growth algorithm. All algorithms operate on CpuPool cores and only
+//! differ in the order they pick them.
+
+use bitvec::prelude::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::CpuPool;
+
+/// Growth algorithm selection in the layer spec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LayerGrowthAlgo {
+    /// Allocate the lowest numbered free core and free the highest numbered
+    /// owned one.
+    #[default]
+    Linear,
+    /// Prefer free cores in the LLCs the layer already occupies so that the
+    /// layer stays cache-local, and free cores from the LLC where the layer
+    /// has the fewest.
+    Sticky,
+    /// Spread the layer across LLCs, allocating from the LLC where the layer
+    /// has the fewest cores and freeing from where it has the most.
+    Topo,
+    /// Allocate the fastest free core first and free the slowest owned core
+    /// first, by maximum CPU frequency. Useful on hybrid machines.
+    BigLittle,
+}
+
+impl LayerGrowthAlgo {
+    pub fn new_algo(&self) -> Box<dyn GrowthAlgo> {
+        match self {
+            LayerGrowthAlgo::Linear => Box::new(Linear),
+            LayerGrowthAlgo::Sticky => Box::new(Sticky),
+            LayerGrowthAlgo::Topo => Box::new(Topo),
+            LayerGrowthAlgo::BigLittle => Box::new(BigLittle),
+        }
+    }
+}
+
+pub trait GrowthAlgo: std::fmt::Debug {
+    /// Pick the next free core to allocate to the layer which currently
+    /// owns @layer_cpus. None if no core is available.
+    fn core_to_alloc(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize>;
+
+    /// Pick the core that the layer which currently owns @layer_cpus should
+    /// give up first. None if the layer doesn't own any.
+    fn core_to_free(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize>;
+}
+
+fn owned_cores(pool: &CpuPool, layer_cpus: &BitVec) -> Vec<usize> {
+    let mut cores: Vec<usize> = layer_cpus
+        .iter_ones()
+        .map(|cpu| pool.cpu_core[cpu])
+        .collect();
+    cores.sort();
+    cores.dedup();
+    cores
+}
+
+fn nr_owned_cores_per_llc(pool: &CpuPool, layer_cpus: &BitVec) -> Vec<usize> {
+    let mut nr_cores = vec![0; pool.nr_llcs];
+    for core in owned_cores(pool, layer_cpus) {
+        nr_cores[pool.core_llc[core]] += 1;
+    }
+    nr_cores
+}
+
+#[derive(Debug)]
+struct Linear;
+
+impl GrowthAlgo for Linear {
+    fn core_to_alloc(&self, pool: &CpuPool, _layer_cpus: &BitVec) -> Option<usize> {
+        pool.available_cores.first_one()
+    }
+
+    fn core_to_free(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        layer_cpus.last_one().map(|cpu| pool.cpu_core[cpu])
+    }
+}
+
+#[derive(Debug)]
+struct Sticky;
+
+impl GrowthAlgo for Sticky {
+    fn core_to_alloc(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        let nr_owned = nr_owned_cores_per_llc(pool, layer_cpus);
+
+        // The free core in the LLC where the layer already has the most.
+        pool.available_cores
+            .iter_ones()
+            .filter(|core| nr_owned[pool.core_llc[*core]] > 0)
+            .max_by_key(|core| (nr_owned[pool.core_llc[*core]], std::cmp::Reverse(*core)))
+            .or_else(|| pool.available_cores.first_one())
+    }
+
+    fn core_to_free(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        let nr_owned = nr_owned_cores_per_llc(pool, layer_cpus);
+
+        owned_cores(pool, layer_cpus)
+            .into_iter()
+            .min_by_key(|core| (nr_owned[pool.core_llc[*core]], std::cmp::Reverse(*core)))
+    }
+}
+
+#[derive(Debug)]
+struct Topo;
+
+impl GrowthAlgo for Topo {
+    fn core_to_alloc(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        let nr_owned = nr_owned_cores_per_llc(pool, layer_cpus);
+
+        pool.available_cores
+            .iter_ones()
+            .min_by_key(|core| (nr_owned[pool.core_llc[*core]], *core))
+    }
+
+    fn core_to_free(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        let nr_owned = nr_owned_cores_per_llc(pool, layer_cpus);
+
+        owned_cores(pool, layer_cpus)
+            .into_iter()
+            .max_by_key(|core| (nr_owned[pool.core_llc[*core]], *core))
+    }
+}
+
+#[derive(Debug)]
+struct BigLittle;
+
+impl GrowthAlgo for BigLittle {
+    fn core_to_alloc(&self, pool: &CpuPool, _layer_cpus: &BitVec) -> Option<usize> {
+        pool.available_cores
+            .iter_ones()
+            .max_by_key(|core| (pool.core_max_freq[*core], std::cmp::Reverse(*core)))
+    }
+
+    fn core_to_free(&self, pool: &CpuPool, layer_cpus: &BitVec) -> Option<usize> {
+        owned_cores(pool, layer_cpus)
+            .into_iter()
+            .min_by_key(|core| (pool.core_max_freq[*core], std::cmp::Reverse(*core)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Six cores with two CPUs each. Cores 0-2 share LLC 10 and cores 3-5
+    // LLC 20. Core 4 is the fastest and cores 2 and 5 the slowest.
+    fn test_pool() -> CpuPool {
+        let core_cpus = (0..6)
+            .map(|core| {
+                let mut cpus = bitvec![0; 12];
+                cpus.set(core * 2, true);
+                cpus.set(core * 2 + 1, true);
+                cpus
+            })
+            .collect();
+        CpuPool::from_cores(
+            core_cpus,
+            &[
+                (10, 2000),
+                (10, 2000),
+                (10, 1000),
+                (20, 2000),
+                (20, 3000),
+                (20, 1000),
+            ],
+        )
+    }
+
+    // Hand @cores to a layer and return its CPUs.
+    fn take(pool: &mut CpuPool, cores: &[usize]) -> BitVec {
+        let mut cpus = bitvec![0; 12];
+        for core in cores.iter() {
+            pool.available_cores.set(*core, false);
+            cpus |= &pool.core_cpus[*core];
+        }
+        cpus
+    }
+
+    #[test]
+    fn test_pool_from_cores() {
+        let pool = test_pool();
+        assert_eq!(pool.nr_cores, 6);
+        assert_eq!(pool.nr_cpus, 12);
+        assert_eq!(pool.nr_llcs, 2);
+        assert_eq!(pool.core_llc, vec![0, 0, 0, 1, 1, 1]);
+        assert_eq!(pool.cpu_core[7], 3);
+        assert_eq!((pool.sibling_cpu[6], pool.sibling_cpu[7]), (7, 6));
+    }
+
+    #[test]
+    fn test_linear() {
+        let algo = LayerGrowthAlgo::Linear.new_algo();
+        let mut pool = test_pool();
+        assert_eq!(algo.core_to_alloc(&pool, &bitvec![0; 12]), Some(0));
+        assert_eq!(algo.core_to_free(&pool, &bitvec![0; 12]), None);
+
+        let cpus = take(&mut pool, &[0, 1, 4]);
+        assert_eq!(algo.core_to_alloc(&pool, &cpus), Some(2));
+        assert_eq!(algo.core_to_free(&pool, &cpus), Some(4));
+    }
+
+    #[test]
+    fn test_sticky() {
+        let algo = LayerGrowthAlgo::Sticky.new_algo();
+        let mut pool = test_pool();
+        assert_eq!(algo.core_to_alloc(&pool, &bitvec![0; 12]), Some(0));
+
+        // Grow within the occupied LLC.
+        let cpus = take(&mut pool, &[4]);
+        assert_eq!(algo.core_to_alloc(&pool, &cpus), Some(3));
+
+        // Shrink from the LLC with the fewest cores.
+        let cpus = take(&mut pool, &[0, 3, 4]);
+        assert_eq!(algo.core_to_free(&pool, &cpus), Some(0));
+
+        // Fall back to any free core once the LLC is full.
+        let cpus = take(&mut pool, &[3, 4, 5]);
+        assert_eq!(algo.core_to_alloc(&pool, &cpus), Some(1));
+    }
+
+    #[test]
+    fn test_topo() {
+        let algo = LayerGrowthAlgo::Topo.new_algo();
+        let mut pool = test_pool();
+        assert_eq!(algo.core_to_alloc(&pool, &bitvec![0; 12]), Some(0));
+
+        // Spread into the LLC with the fewest cores.
+        let cpus = take(&mut pool, &[0, 1]);
+        assert_eq!(algo.core_to_alloc(&pool, &cpus), Some(3));
+
+        // Shrink from the LLC with the most cores.
+        let cpus = take(&mut pool, &[0, 1, 3]);
+        assert_eq!(algo.core_to_free(&pool, &cpus), Some(1));
+    }
+
+    #[test]
+    fn test_big_little() {
+        let algo = LayerGrowthAlgo::BigLittle.new_algo();
+        let mut pool = test_pool();
+        assert_eq!(algo.core_to_alloc(&pool, &bitvec![0; 12]), Some(4));
+
+        // Ties are broken by the lowest core.
+        let cpus = take(&mut pool, &[4]);
+        assert_eq!(algo.core_to_alloc(&pool, &cpus), Some(0));
+
+        let cpus = take(&mut pool, &[0, 4, 5]);
+        assert_eq!(algo.core_to_free(&pool, &cpus), Some(5));
+    }
+}
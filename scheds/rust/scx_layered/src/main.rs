@@ -5,9 +5,11 @@
 mod bpf_skel;
 pub use bpf_skel::*;
 pub mod bpf_intf;
+mod growth;
 
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::ffi::CStr;
 use std::ffi::CString;
 use std::fs;
 use std::io::Read;
@@ -38,16 +40,26 @@ use prometheus_client::encoding::text::encode;
 use prometheus_client::metrics::family::Family;
 use prometheus_client::metrics::gauge::Gauge;
 use prometheus_client::registry::Registry;
+use scx_utils::cgroup;
 use scx_utils::compat;
 use scx_utils::init_libbpf_logging;
 use scx_utils::ravg::ravg_read;
 use scx_utils::scx_ops_attach;
 use scx_utils::scx_ops_load;
+use scx_utils::systemd;
+use scx_utils::time;
+use scx_utils::time::now_monotonic;
 use scx_utils::uei_exited;
-use scx_utils::uei_report;
+use scx_utils::uei_read;
+use scx_utils::StallHistory;
+use scx_utils::Topology;
+use scx_utils::UserExitInfo;
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::growth::GrowthAlgo;
+use crate::growth::LayerGrowthAlgo;
+
 const RAVG_FRAC_BITS: u32 = bpf_intf::ravg_consts_RAVG_FRAC_BITS;
 const MAX_CPUS: usize = bpf_intf::consts_MAX_CPUS as usize;
 const MAX_PATH: usize = bpf_intf::consts_MAX_PATH as usize;
@@ -66,6 +78,8 @@ lazy_static::lazy_static! {
     static ref USAGE_DECAY: f64 = 0.5f64.powf(1.0 / USAGE_HALF_LIFE_F64);
 }
 
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
 /// scx_layered: A highly configurable multi-layer sched_ext scheduler
 ///
 /// scx_layered allows classifying tasks into multiple layers and applying
@@ -123,6 +137,12 @@ lazy_static::lazy_static! {
 /// - NiceEquals: Matches if the task's nice value is exactly equal to
 ///   the pattern.
 ///
+/// - SystemdUnit: Matches tasks in the cgroup of the named systemd service,
+///   scope or slice of the system manager, e.g. "sshd.service". The unit is
+///   resolved to its cgroup path every 5 seconds, so a newly started unit
+///   may take that long to be picked up. While the unit isn't running, the
+///   match doesn't match any task.
+///
 /// While there are complexity limitations as the matches are performed in
 /// BPF, it is straightforward to add more types of matches.
 ///
@@ -165,6 +185,16 @@ lazy_static::lazy_static! {
 ///   is scheduled in, this is the minimum CPU time that it's charged no
 ///   matter how short the actual execution time may be.
 ///
+/// Both Confined and Grouped layers also accept the following option:
+///
+/// - growth_algo: Determines which cores the layer gets when it grows and
+///   which it gives up when it shrinks.
+///
+///   - Linear (default): Lowest numbered cores first.
+///   - Sticky: Stay within the LLCs the layer already occupies.
+///   - Topo: Spread evenly across LLCs.
+///   - BigLittle: Fastest cores first, by maximum frequency.
+///
 /// Both Grouped and Open layers also acception the following options:
 ///
 /// - preempt: If true, tasks in the layer will preempt tasks which belong
@@ -177,6 +207,30 @@ lazy_static::lazy_static! {
 /// Similar to matches, adding new policies and extending existing ones
 /// should be relatively straightforward.
 ///
+/// Template layers
+/// ===============
+///
+/// A layer with the "template" property is instantiated once per child
+/// cgroup of "cgroup_parent", e.g. once per Kubernetes pod:
+///
+///   "template": {
+///     "cgroup_parent": "kubepods.slice/kubepods-burstable.slice/",
+///     "max_instances": 4
+///   }
+///
+/// Each instance is named "NAME:CGROUP" and inherits the policy of the
+/// template. Its matches are the template's matches with a CgroupPrefix
+/// match on the child cgroup added to every OR block. If the template has
+/// no matches, the instance matches all tasks in the child cgroup.
+///
+/// Cgroups are scanned every scheduling interval. New child cgroups are
+/// assigned to free instances and instances whose cgroups went away are
+/// released. As the number of layers is fixed once the BPF scheduler is
+/// loaded, "max_instances" layers are reserved for each template and count
+/// towards the limit of 16 layers (MAX_LAYERS) along with the other specs.
+/// Child cgroups beyond "max_instances" fall through to the following
+/// layers. So do child cgroups whose paths are too long to match on.
+///
 /// Configuration example and running scx_layered
 /// =============================================
 ///
@@ -202,6 +256,18 @@ lazy_static::lazy_static! {
 ///   ...
 ///   $ scx_layered f:example.json
 ///
+/// Reloading the configuration
+/// ===========================
+///
+/// Sending SIGHUP to scx_layered makes it re-read the layer specifications
+/// from the same arguments it was started with and apply them without
+/// detaching the scheduler. Matches and policies can be changed freely but
+/// the number of layers must stay the same. If the new specifications fail
+/// to parse or verify, the error is logged and the current configuration is
+/// kept.
+///
+///   $ kill -HUP $(pidof scx_layered)
+///
 /// Statistics
 /// ==========
 ///
@@ -284,6 +350,12 @@ struct Opts {
     #[clap(long, default_value = "0")]
     exit_dump_len: u32,
 
+    /// Keep the fingerprints of recent stalls across restarts in
+    /// $SCX_STATE_DIR, $RUNTIME_DIRECTORY or /run/scx/scx_layered. On a
+    /// stall exit, report whether the stall matches earlier ones.
+    #[clap(long)]
+    stall_history: bool,
+
     /// Enable verbose output including libbpf details. Specify multiple
     /// times to increase verbosity.
     #[clap(short = 'v', long, action = clap::ArgAction::Count)]
@@ -311,6 +383,7 @@ enum LayerMatch {
     NiceAbove(i32),
     NiceBelow(i32),
     NiceEquals(i32),
+    SystemdUnit(String),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -323,6 +396,8 @@ enum LayerKind {
         min_exec_us: u64,
         #[serde(default)]
         perf: u64,
+        #[serde(default)]
+        growth_algo: LayerGrowthAlgo,
     },
     Grouped {
         util_range: (f64, f64),
@@ -336,6 +411,8 @@ enum LayerKind {
         exclusive: bool,
         #[serde(default)]
         perf: u64,
+        #[serde(default)]
+        growth_algo: LayerGrowthAlgo,
     },
     Open {
         #[serde(default)]
@@ -355,6 +432,13 @@ struct LayerSpec {
     comment: Option<String>,
     matches: Vec<Vec<LayerMatch>>,
     kind: LayerKind,
+    template: Option<LayerTemplate>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct LayerTemplate {
+    cgroup_parent: String,
+    max_instances: usize,
 }
 
 impl LayerSpec {
@@ -379,14 +463,86 @@ struct LayerConfig {
     specs: Vec<LayerSpec>,
 }
 
-fn now_monotonic() -> u64 {
-    let mut time = libc::timespec {
-        tv_sec: 0,
-        tv_nsec: 0,
-    };
-    let ret = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
-    assert!(ret == 0);
-    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+/// Layer slots reserved for a template layer spec and the cgroups they're
+/// currently instantiated for.
+#[derive(Debug)]
+struct TemplateSlots {
+    spec: LayerSpec,
+    first_layer: usize,
+    cgroups: Vec<Option<String>>,
+    overflowed: bool,
+    /// Child cgroups skipped because their paths don't fit in MAX_PATH.
+    too_long: BTreeSet<String>,
+}
+
+impl TemplateSlots {
+    fn instance_spec(&self, cgroup: &str) -> LayerSpec {
+        let prefix = LayerMatch::CgroupPrefix(format!("{}/", cgroup));
+        let matches = if self.spec.matches.is_empty() {
+            vec![vec![prefix]]
+        } else {
+            self.spec
+                .matches
+                .iter()
+                .map(|ands| {
+                    let mut ands = ands.clone();
+                    ands.insert(0, prefix.clone());
+                    ands
+                })
+                .collect()
+        };
+
+        LayerSpec {
+            name: format!(
+                "{}:{}",
+                self.spec.name,
+                cgroup.rsplit('/').next().unwrap_or(cgroup)
+            ),
+            comment: self.spec.comment.clone(),
+            matches,
+            kind: self.spec.kind.clone(),
+            template: None,
+        }
+    }
+
+    fn idle_spec(&self, slot: usize) -> LayerSpec {
+        // No match blocks, BPF never assigns tasks to idle instances.
+        LayerSpec {
+            name: format!("{}:-{}", self.spec.name, slot),
+            comment: self.spec.comment.clone(),
+            matches: vec![],
+            kind: self.spec.kind.clone(),
+            template: None,
+        }
+    }
+}
+
+/// Expand template layer specs into the reserved idle instances. Returns
+/// the per-layer specs and the template slot descriptions.
+fn expand_layer_specs(specs: &[LayerSpec]) -> (Vec<LayerSpec>, Vec<TemplateSlots>) {
+    let mut layer_specs = vec![];
+    let mut templates = vec![];
+
+    for spec in specs.iter() {
+        match &spec.template {
+            Some(template) => {
+                let slots = TemplateSlots {
+                    spec: spec.clone(),
+                    first_layer: layer_specs.len(),
+                    cgroups: vec![None; template.max_instances],
+                    overflowed: false,
+                    too_long: BTreeSet::new(),
+                };
+                for slot in 0..template.max_instances {
+                    layer_specs.push(slots.idle_spec(slot));
+                }
+                templates.push(slots);
+            }
+            None => layer_specs.push(spec.clone()),
+        }
+    }
+
+    (layer_specs, templates)
 }
 
 fn read_total_cpu(reader: &procfs::ProcReader) -> Result<procfs::CpuStat> {
@@ -445,10 +601,39 @@ fn calc_util(curr: &procfs::CpuStat, prev: &procfs::CpuStat) -> Result<f64> {
     }
 }
 
-fn copy_into_cstr(dst: &mut [i8], src: &str) {
-    let cstr = CString::new(src).unwrap();
+fn copy_into_cstr(dst: &mut [i8], src: &str) -> Result<()> {
+    let cstr = CString::new(src)?;
     let bytes = unsafe { std::mem::transmute::<&[u8], &[i8]>(cstr.as_bytes_with_nul()) };
+    if bytes.len() > dst.len() {
+        bail!("{:?} doesn't fit in {} bytes", src, dst.len());
+    }
     dst[0..bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Cgroup paths formatted by BPF never start with '/', so this prefix never
+/// matches. Used for systemd units which aren't running.
+const NO_CGROUP_PREFIX: &str = "/";
+
+/// Resolving systemd units walks the cgroup hierarchy. Units don't start and
+/// stop often, so do it on a much slower timer than the scheduling interval.
+const SYSTEMD_REFRESH_INTV: Duration = Duration::from_secs(5);
+
+fn systemd_unit_cgroup_prefix(unit: &str) -> Result<Option<String>> {
+    let cgrp = match cgroup::systemd_unit_cgroups(unit)?.into_iter().next() {
+        Some(cgrp) => cgrp,
+        None => return Ok(None),
+    };
+
+    let prefix = format!("{}/", &cgrp);
+    if prefix.len() >= MAX_PATH {
+        bail!(
+            "cgroup path {:?} of systemd unit {:?} is too long",
+            &prefix,
+            unit
+        );
+    }
+    Ok(Some(prefix))
 }
 
 fn format_bitvec(bitvec: &BitVec) -> String {
@@ -687,12 +872,15 @@ impl Stats {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct CpuPool {
     nr_cores: usize,
     nr_cpus: usize,
+    nr_llcs: usize,
     all_cpus: BitVec,
     core_cpus: Vec<BitVec>,
+    core_llc: Vec<usize>,
+    core_max_freq: Vec<usize>,
     sibling_cpu: Vec<i32>,
     cpu_core: Vec<usize>,
     available_cores: BitVec,
@@ -712,7 +900,6 @@ impl CpuPool {
 
         let mut cpu_to_cache = vec![]; // (cpu_id, Option<cache_id>)
         let mut cache_ids = BTreeSet::<usize>::new();
-        let mut nr_offline = 0;
 
         // Build cpu -> cache ID mapping.
         for cpu in 0..*NR_POSSIBLE_CPUS {
@@ -724,10 +911,7 @@ impl CpuPool {
                 Ok(val) => Some(val.trim().parse::<usize>().with_context(|| {
                     format!("Failed to parse {:?}'s content {:?}", &path, &val)
                 })?),
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    nr_offline += 1;
-                    None
-                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
                 Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", &path)),
             };
 
@@ -737,8 +921,6 @@ impl CpuPool {
             }
         }
 
-        let nr_cpus = *NR_POSSIBLE_CPUS - nr_offline;
-
         // Cache IDs may have holes. Assign consecutive core IDs to existing
         // cache IDs.
         let mut cache_to_core = BTreeMap::<usize, usize>::new();
@@ -748,23 +930,73 @@ impl CpuPool {
             nr_cores += 1;
         }
 
-        // Build core -> cpumask and cpu -> core mappings.
-        let mut all_cpus = bitvec![0; *NR_POSSIBLE_CPUS];
+        // Build core -> cpumask mapping.
         let mut core_cpus = vec![bitvec![0; *NR_POSSIBLE_CPUS]; nr_cores];
-        let mut cpu_core = vec![];
-
         for (cpu, cache) in cpu_to_cache.iter().enumerate().take(*NR_POSSIBLE_CPUS) {
             if let Some(cache_id) = cache {
-                let core_id = cache_to_core[cache_id];
-                all_cpus.set(cpu, true);
-                core_cpus[core_id].set(cpu, true);
-                cpu_core.push(core_id);
+                core_cpus[cache_to_core[cache_id]].set(cpu, true);
+            }
+        }
+
+        // Build core -> LLC and core -> max frequency mappings for the
+        // growth algorithms. If the information isn't available, assume a
+        // single LLC and identical cores.
+        let mut cpu_llc_freq = BTreeMap::<usize, (usize, usize)>::new();
+        match Topology::new() {
+            Ok(topo) => {
+                for node in topo.nodes() {
+                    for (llc_id, llc) in node.llcs() {
+                        for core in llc.cores().values() {
+                            for (cpu_id, cpu) in core.cpus() {
+                                cpu_llc_freq.insert(*cpu_id, (*llc_id, cpu.max_freq()));
+                            }
+                        }
+                    }
+                }
             }
+            Err(e) => warn!(
+                "Failed to read CPU topology, assuming a single LLC and identical cores ({:?})",
+                &e
+            ),
         }
+        let core_llc_freq: Vec<(usize, usize)> = core_cpus
+            .iter()
+            .map(|cpus| {
+                let cpu = cpus.first_one().unwrap();
+                cpu_llc_freq.get(&cpu).copied().unwrap_or((0, 0))
+            })
+            .collect();
+
+        let cpu_pool = Self::from_cores(core_cpus, &core_llc_freq);
+
+        info!(
+            "CPUs: online/possible={}/{} nr_cores={} nr_llcs={}",
+            cpu_pool.nr_cpus, *NR_POSSIBLE_CPUS, cpu_pool.nr_cores, cpu_pool.nr_llcs,
+        );
+        debug!(
+            "CPUs: siblings={:?}",
+            &cpu_pool.sibling_cpu[..cpu_pool.nr_cpus]
+        );
+
+        Ok(cpu_pool)
+    }
+
+    /// Build the pool from the CPUs of each core and the (LLC ID, maximum
+    /// frequency) of each core. LLC IDs may have holes.
+    fn from_cores(core_cpus: Vec<BitVec>, core_llc_freq: &[(usize, usize)]) -> Self {
+        let nr_cores = core_cpus.len();
+        let nr_possible_cpus = core_cpus[0].len();
+
+        // Build cpu -> core mapping and sibling_cpu[].
+        let mut all_cpus = bitvec![0; nr_possible_cpus];
+        let mut cpu_core = vec![0; nr_possible_cpus];
+        let mut sibling_cpu = vec![-1i32; nr_possible_cpus];
+        for (core_id, cpus) in core_cpus.iter().enumerate() {
+            all_cpus |= cpus;
+            for cpu in cpus.iter_ones() {
+                cpu_core[cpu] = core_id;
+            }
 
-        // Build sibling_cpu[]
-        let mut sibling_cpu = vec![-1i32; *NR_POSSIBLE_CPUS];
-        for cpus in &core_cpus {
             let mut first = -1i32;
             for cpu in cpus.iter_ones() {
                 if first < 0 {
@@ -777,19 +1009,26 @@ impl CpuPool {
             }
         }
 
-        info!(
-            "CPUs: online/possible={}/{} nr_cores={}",
-            nr_cpus, *NR_POSSIBLE_CPUS, nr_cores,
-        );
-        debug!("CPUs: siblings={:?}", &sibling_cpu[..nr_cpus]);
+        // Assign consecutive IDs to the LLCs.
+        let mut llc_ids = BTreeMap::<usize, usize>::new();
+        let mut core_llc = vec![];
+        let mut core_max_freq = vec![];
+        for (llc_id, max_freq) in core_llc_freq.iter() {
+            let nr_llcs = llc_ids.len();
+            core_llc.push(*llc_ids.entry(*llc_id).or_insert(nr_llcs));
+            core_max_freq.push(*max_freq);
+        }
 
         let first_cpu = core_cpus[0].first_one().unwrap();
 
         let mut cpu_pool = Self {
             nr_cores,
-            nr_cpus,
+            nr_cpus: all_cpus.count_ones(),
+            nr_llcs: llc_ids.len(),
             all_cpus,
             core_cpus,
+            core_llc,
+            core_max_freq,
             sibling_cpu,
             cpu_core,
             available_cores: bitvec![1; nr_cores],
@@ -797,7 +1036,7 @@ impl CpuPool {
             fallback_cpu: first_cpu,
         };
         cpu_pool.update_fallback_cpu();
-        Ok(cpu_pool)
+        cpu_pool
     }
 
     fn update_fallback_cpu(&mut self) {
@@ -807,8 +1046,8 @@ impl CpuPool {
         }
     }
 
-    fn alloc<'a>(&'a mut self) -> Option<&'a BitVec> {
-        let core = self.available_cores.first_one()?;
+    fn alloc<'a>(&'a mut self, algo: &dyn GrowthAlgo, layer_cpus: &BitVec) -> Option<&'a BitVec> {
+        let core = algo.core_to_alloc(self, layer_cpus)?;
         self.available_cores.set(core, false);
         self.update_fallback_cpu();
         Some(&self.core_cpus[core])
@@ -847,12 +1086,15 @@ impl CpuPool {
         Ok(())
     }
 
-    fn next_to_free<'a>(&'a self, cands: &BitVec) -> Result<Option<&'a BitVec>> {
-        let last = match cands.last_one() {
+    fn next_to_free<'a>(
+        &'a self,
+        algo: &dyn GrowthAlgo,
+        cands: &BitVec,
+    ) -> Result<Option<&'a BitVec>> {
+        let core = match algo.core_to_free(self, cands) {
             Some(ret) => ret,
             None => return Ok(None),
         };
-        let core = self.cpu_core[last];
         if (self.core_cpus[core].clone() & !cands.clone()).count_ones() != 0 {
             bail!(
                 "CPUs{} partially intersect with core {} ({})",
@@ -878,6 +1120,8 @@ impl CpuPool {
 struct Layer {
     name: String,
     kind: LayerKind,
+    growth_algo: Box<dyn GrowthAlgo>,
+    active: bool,
 
     nr_cpus: usize,
     cpus: BitVec,
@@ -907,11 +1151,20 @@ impl Layer {
             _ => {}
         }
 
+        let growth_algo = match &kind {
+            LayerKind::Confined { growth_algo, .. } | LayerKind::Grouped { growth_algo, .. } => {
+                growth_algo.new_algo()
+            }
+            LayerKind::Open { .. } => LayerGrowthAlgo::default().new_algo(),
+        };
+
         let nr_cpus = cpu_pool.nr_cpus;
 
         Ok(Self {
             name: name.into(),
             kind,
+            growth_algo,
+            active: true,
 
             nr_cpus: 0,
             cpus: bitvec![0; nr_cpus],
@@ -953,7 +1206,7 @@ impl Layer {
             return Ok(false);
         }
 
-        let new_cpus = match cpu_pool.alloc().clone() {
+        let new_cpus = match cpu_pool.alloc(&*self.growth_algo, &self.cpus).clone() {
             Some(ret) => ret.clone(),
             None => {
                 trace!("layer-{} can't grow, no CPUs", &self.name);
@@ -986,7 +1239,7 @@ impl Layer {
             return Ok(None);
         }
 
-        let cpus_to_free = match cpu_pool.next_to_free(&self.cpus)? {
+        let cpus_to_free = match cpu_pool.next_to_free(&*self.growth_algo, &self.cpus)? {
             Some(ret) => ret.clone(),
             None => return Ok(None),
         };
@@ -1229,7 +1482,9 @@ impl OpenMetricsStats {
 struct Scheduler<'a> {
     skel: BpfSkel<'a>,
     struct_ops: Option<libbpf_rs::Link>,
+    spec_inputs: Vec<String>,
     layer_specs: Vec<LayerSpec>,
+    templates: Vec<TemplateSlots>,
 
     sched_intv: Duration,
     monitor_intv: Duration,
@@ -1243,83 +1498,106 @@ struct Scheduler<'a> {
     report_stats: Stats,
 
     nr_layer_cpus_min_max: Vec<(usize, usize)>,
+    systemd_refresh_at: Instant,
     processing_dur: Duration,
     prev_processing_dur: Duration,
 
     om_stats: OpenMetricsStats,
     om_format: bool,
+
+    stall_history: Option<StallHistory>,
 }
 
 impl<'a> Scheduler<'a> {
-    fn init_layers(skel: &mut OpenBpfSkel, specs: &Vec<LayerSpec>) -> Result<()> {
-        skel.rodata_mut().nr_layers = specs.len() as u32;
-        let mut perf_set = false;
-
-        for (spec_i, spec) in specs.iter().enumerate() {
-            let layer = &mut skel.bss_mut().layers[spec_i];
-
-            for (or_i, or) in spec.matches.iter().enumerate() {
-                for (and_i, and) in or.iter().enumerate() {
-                    let mt = &mut layer.matches[or_i].matches[and_i];
-                    match and {
-                        LayerMatch::CgroupPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
-                            copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str());
-                        }
-                        LayerMatch::CommPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_COMM_PREFIX as i32;
-                            copy_into_cstr(&mut mt.comm_prefix, prefix.as_str());
-                        }
-                        LayerMatch::PcommPrefix(prefix) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_PCOMM_PREFIX as i32;
-                            copy_into_cstr(&mut mt.pcomm_prefix, prefix.as_str());
-                        }
-                        LayerMatch::NiceAbove(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_ABOVE as i32;
-                            mt.nice = *nice;
-                        }
-                        LayerMatch::NiceBelow(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_BELOW as i32;
-                            mt.nice = *nice;
-                        }
-                        LayerMatch::NiceEquals(nice) => {
-                            mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_EQUALS as i32;
-                            mt.nice = *nice;
-                        }
+    fn init_bpf_layer(layer: &mut bpf_types::layer, spec: &LayerSpec) -> Result<()> {
+        for (or_i, or) in spec.matches.iter().enumerate() {
+            for (and_i, and) in or.iter().enumerate() {
+                let mt = &mut layer.matches[or_i].matches[and_i];
+                match and {
+                    LayerMatch::CgroupPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
+                        copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str())?;
+                    }
+                    LayerMatch::CommPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_COMM_PREFIX as i32;
+                        copy_into_cstr(&mut mt.comm_prefix, prefix.as_str())?;
+                    }
+                    LayerMatch::PcommPrefix(prefix) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_PCOMM_PREFIX as i32;
+                        copy_into_cstr(&mut mt.pcomm_prefix, prefix.as_str())?;
+                    }
+                    LayerMatch::NiceAbove(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_ABOVE as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::NiceBelow(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_BELOW as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::NiceEquals(nice) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_NICE_EQUALS as i32;
+                        mt.nice = *nice;
+                    }
+                    LayerMatch::SystemdUnit(unit) => {
+                        mt.kind = bpf_intf::layer_match_kind_MATCH_CGROUP_PREFIX as i32;
+                        let prefix = match systemd_unit_cgroup_prefix(unit)? {
+                            Some(prefix) => prefix,
+                            None => {
+                                info!("systemd unit {:?} is not running", unit);
+                                NO_CGROUP_PREFIX.into()
+                            }
+                        };
+                        copy_into_cstr(&mut mt.cgroup_prefix, prefix.as_str())?;
                     }
                 }
-                layer.matches[or_i].nr_match_ands = or.len() as i32;
             }
+            layer.matches[or_i].nr_match_ands = or.len() as i32;
+        }
 
-            layer.nr_match_ors = spec.matches.len() as u32;
-
-            match &spec.kind {
-                LayerKind::Confined { min_exec_us, perf, .. } => {
-                    layer.min_exec_ns = min_exec_us * 1000;
-                    layer.perf = u32::try_from(*perf)?;
-                }
-                LayerKind::Open {
-                    min_exec_us,
-                    preempt,
-                    exclusive,
-                    perf,
-                    ..
-                }
-                | LayerKind::Grouped {
-                    min_exec_us,
-                    preempt,
-                    exclusive,
-                    perf,
-                    ..
-                } => {
-                    layer.open.write(true);
-                    layer.min_exec_ns = min_exec_us * 1000;
-                    layer.preempt.write(*preempt);
-                    layer.exclusive.write(*exclusive);
-                    layer.perf = u32::try_from(*perf)?;
-                }
+        layer.nr_match_ors = spec.matches.len() as u32;
+
+        match &spec.kind {
+            LayerKind::Confined { min_exec_us, perf, .. } => {
+                // Clear the open layer flags in case the layer was of a
+                // different kind before a reload.
+                layer.open.write(false);
+                layer.preempt.write(false);
+                layer.exclusive.write(false);
+                layer.min_exec_ns = time::us_to_ns(*min_exec_us);
+                layer.perf = u32::try_from(*perf)?;
+            }
+            LayerKind::Open {
+                min_exec_us,
+                preempt,
+                exclusive,
+                perf,
+                ..
+            }
+            | LayerKind::Grouped {
+                min_exec_us,
+                preempt,
+                exclusive,
+                perf,
+                ..
+            } => {
+                layer.open.write(true);
+                layer.min_exec_ns = time::us_to_ns(*min_exec_us);
+                layer.preempt.write(*preempt);
+                layer.exclusive.write(*exclusive);
+                layer.perf = u32::try_from(*perf)?;
             }
+        }
 
+        Ok(())
+    }
+
+    fn init_layers(skel: &mut OpenBpfSkel, specs: &Vec<LayerSpec>) -> Result<()> {
+        skel.rodata_mut().nr_layers = specs.len() as u32;
+        let mut perf_set = false;
+
+        for (spec_i, spec) in specs.iter().enumerate() {
+            let layer = &mut skel.bss_mut().layers[spec_i];
+            Self::init_bpf_layer(layer, spec)?;
             perf_set |= layer.perf > 0;
         }
 
@@ -1331,6 +1609,7 @@ impl<'a> Scheduler<'a> {
     }
 
     fn init(opts: &Opts, layer_specs: Vec<LayerSpec>) -> Result<Self> {
+        let (layer_specs, templates) = expand_layer_specs(&layer_specs);
         let nr_layers = layer_specs.len();
         let mut cpu_pool = CpuPool::new()?;
 
@@ -1344,7 +1623,7 @@ impl<'a> Scheduler<'a> {
         skel.struct_ops.layered_mut().exit_dump_len = opts.exit_dump_len;
 
         skel.rodata_mut().debug = opts.verbose as u32;
-        skel.rodata_mut().slice_ns = opts.slice_us * 1000;
+        skel.rodata_mut().slice_ns = time::us_to_ns(opts.slice_us);
         skel.rodata_mut().nr_possible_cpus = *NR_POSSIBLE_CPUS as u32;
         skel.rodata_mut().smt_enabled = cpu_pool.nr_cpus > cpu_pool.nr_cores;
         for (cpu, sib) in cpu_pool.sibling_cpu.iter().enumerate() {
@@ -1361,13 +1640,20 @@ impl<'a> Scheduler<'a> {
         for spec in layer_specs.iter() {
             layers.push(Layer::new(&mut cpu_pool, &spec.name, spec.kind.clone())?);
         }
+        for tmpl in templates.iter() {
+            for slot in 0..tmpl.cgroups.len() {
+                layers[tmpl.first_layer + slot].active = false;
+            }
+        }
 
         // Other stuff.
         let proc_reader = procfs::ProcReader::new();
 
         let mut sched = Self {
             struct_ops: None,
+            spec_inputs: opts.specs.clone(),
             layer_specs,
+            templates,
 
             sched_intv: Duration::from_secs_f64(opts.interval),
             monitor_intv: Duration::from_secs_f64(opts.monitor),
@@ -1380,6 +1666,7 @@ impl<'a> Scheduler<'a> {
             report_stats: Stats::new(&mut skel, &proc_reader)?,
 
             nr_layer_cpus_min_max: vec![(0, 0); nr_layers],
+            systemd_refresh_at: Instant::now() + SYSTEMD_REFRESH_INTV,
             processing_dur: Duration::from_millis(0),
             prev_processing_dur: Duration::from_millis(0),
 
@@ -1388,6 +1675,10 @@ impl<'a> Scheduler<'a> {
 
             om_stats: OpenMetricsStats::new(),
             om_format: opts.open_metrics_format,
+
+            stall_history: opts
+                .stall_history
+                .then(|| StallHistory::new("scx_layered")),
         };
 
         // XXX If we try to refresh the cpumasks here before attaching, we
@@ -1396,6 +1687,8 @@ impl<'a> Scheduler<'a> {
         // attach, but the value will quickly converge anyways so it's not a
         // huge problem in the interim until we figure it out.
 
+        sched.refresh_template_layers()?;
+
         // Attach.
         sched.struct_ops = Some(scx_ops_attach!(sched.skel, layered)?);
         info!("Layered Scheduler Attached");
@@ -1418,6 +1711,9 @@ impl<'a> Scheduler<'a> {
         let mut updated = false;
 
         for idx in 0..self.layers.len() {
+            if !self.layers[idx].active {
+                continue;
+            }
             match self.layers[idx].kind {
                 LayerKind::Confined {
                     cpus_range,
@@ -1462,6 +1758,9 @@ impl<'a> Scheduler<'a> {
             let nr_available_cpus = available_cpus.count_ones();
             for idx in 0..self.layers.len() {
                 let layer = &mut self.layers[idx];
+                if !layer.active {
+                    continue;
+                }
                 let bpf_layer = &mut self.skel.bss_mut().layers[idx];
                 match &layer.kind {
                     LayerKind::Open { .. } => {
@@ -1486,11 +1785,286 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
+    fn reload_layer_specs(&mut self) -> Result<()> {
+        let (specs, templates) = expand_layer_specs(&parse_layer_specs(&self.spec_inputs)?);
+        if specs.len() != self.layers.len() {
+            bail!(
+                "The number of layers can't change on reload ({} -> {})",
+                self.layers.len(),
+                specs.len()
+            );
+        }
+
+        // Build the new layers and their BPF counterparts on scratch copies
+        // first so that an invalid spec leaves the current configuration
+        // untouched. All the CPUs owned by the current layers are returned to
+        // the scratch pool and allocation starts over with the new specs.
+        // Open layers don't own CPUs. Template instances are re-created by
+        // refresh_template_layers() below.
+        let mut cpu_pool = self.cpu_pool.clone();
+        for layer in self.layers.iter() {
+            match &layer.kind {
+                LayerKind::Confined { .. } | LayerKind::Grouped { .. } => {
+                    if layer.nr_cpus > 0 {
+                        cpu_pool.free(&layer.cpus)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut layers = vec![];
+        for spec in specs.iter() {
+            layers.push(Layer::new(&mut cpu_pool, &spec.name, spec.kind.clone())?);
+        }
+        for tmpl in templates.iter() {
+            for slot in 0..tmpl.cgroups.len() {
+                layers[tmpl.first_layer + slot].active = false;
+            }
+        }
+
+        let available_cpus = cpu_pool.available_cpus();
+        let nr_available_cpus = available_cpus.count_ones();
+        let mut bpf_layers = vec![];
+        for (idx, spec) in specs.iter().enumerate() {
+            let layer = &mut layers[idx];
+            if let (true, LayerKind::Open { .. }) = (layer.active, &layer.kind) {
+                layer.cpus.copy_from_bitslice(&available_cpus);
+                layer.nr_cpus = nr_available_cpus;
+            }
+
+            let mut bpf_layer = self.skel.bss().layers[idx];
+            Self::init_bpf_layer(&mut bpf_layer, spec)?;
+            Self::update_bpf_layer_cpumask(layer, &mut bpf_layer);
+            bpf_layers.push(bpf_layer);
+        }
+
+        // Nothing can fail from here on. Only copy the spec derived fields,
+        // the rest of struct layer is managed by BPF.
+        self.cpu_pool = cpu_pool;
+        self.layers = layers;
+        for (idx, src) in bpf_layers.iter().enumerate() {
+            let dst = &mut self.skel.bss_mut().layers[idx];
+            dst.matches = src.matches;
+            dst.nr_match_ors = src.nr_match_ors;
+            dst.min_exec_ns = src.min_exec_ns;
+            dst.open = src.open;
+            dst.preempt = src.preempt;
+            dst.exclusive = src.exclusive;
+            dst.perf = src.perf;
+            dst.cpus = src.cpus;
+            dst.refresh_cpus = 1;
+        }
+        self.skel.bss_mut().fallback_cpu = self.cpu_pool.fallback_cpu as u32;
+
+        // Make BPF re-match all tasks against the new specs.
+        self.skel.bss_mut().layer_refresh_seq += 1;
+
+        self.layer_specs = specs;
+        self.templates = templates;
+        self.nr_layer_cpus_min_max = vec![(0, 0); self.layers.len()];
+
+        self.refresh_template_layers()?;
+        self.refresh_cpumasks()?;
+        info!("Reloaded {} layer specs", self.layers.len());
+        Ok(())
+    }
+
+    fn activate_layer(&mut self, idx: usize, spec: LayerSpec) -> Result<()> {
+        let bpf_layer = &mut self.skel.bss_mut().layers[idx];
+        Self::init_bpf_layer(bpf_layer, &spec)?;
+
+        // Confined and Grouped layers grow from zero through
+        // refresh_cpumasks(). Open layers aren't resized and need the
+        // currently available CPUs right away.
+        let layer = &mut self.layers[idx];
+        if let LayerKind::Open { .. } = &layer.kind {
+            let available_cpus = self.cpu_pool.available_cpus();
+            layer.nr_cpus = available_cpus.count_ones();
+            layer.cpus.copy_from_bitslice(&available_cpus);
+            Self::update_bpf_layer_cpumask(layer, bpf_layer);
+        }
+        layer.name = spec.name.clone();
+        layer.active = true;
+        self.layer_specs[idx] = spec;
+        Ok(())
+    }
+
+    fn deactivate_layer(&mut self, idx: usize, spec: LayerSpec) -> Result<()> {
+        let layer = &mut self.layers[idx];
+        match &layer.kind {
+            LayerKind::Confined { .. } | LayerKind::Grouped { .. } => {
+                if layer.nr_cpus > 0 {
+                    self.cpu_pool.free(&layer.cpus)?;
+                }
+            }
+            _ => {}
+        }
+        layer.cpus.fill(false);
+        layer.nr_cpus = 0;
+        layer.name = spec.name.clone();
+        layer.active = false;
+
+        let bpf_layer = &mut self.skel.bss_mut().layers[idx];
+        Self::init_bpf_layer(bpf_layer, &spec)?;
+        Self::update_bpf_layer_cpumask(layer, bpf_layer);
+        self.skel.bss_mut().fallback_cpu = self.cpu_pool.fallback_cpu as u32;
+
+        self.layer_specs[idx] = spec;
+        Ok(())
+    }
+
+    fn refresh_template_layers(&mut self) -> Result<()> {
+        let mut templates = std::mem::take(&mut self.templates);
+        let mut changed = false;
+
+        for tmpl in templates.iter_mut() {
+            let cgroup_parent = &tmpl.spec.template.as_ref().unwrap().cgroup_parent;
+            let mut cgroups = cgroup::child_cgroups(cgroup_parent)?;
+
+            // The instance matches on "CGROUP/" which must fit in
+            // MAX_PATH. Skip the children which don't, warning once.
+            tmpl.too_long
+                .retain(|cgrp| cgroups.binary_search(cgrp).is_ok());
+            cgroups.retain(|cgrp| {
+                if cgrp.len() + 1 < MAX_PATH {
+                    return true;
+                }
+                if tmpl.too_long.insert(cgrp.clone()) {
+                    warn!(
+                        "Template layer {:?} skipping cgroup with too long a path {:?}",
+                        &tmpl.spec.name, cgrp
+                    );
+                }
+                false
+            });
+
+            // Release the instances whose cgroups are gone.
+            for slot in 0..tmpl.cgroups.len() {
+                let gone = match &tmpl.cgroups[slot] {
+                    Some(cgrp) => cgroups.binary_search(cgrp).is_err(),
+                    None => false,
+                };
+                if gone {
+                    debug!(
+                        "Releasing layer {:?}",
+                        &self.layer_specs[tmpl.first_layer + slot].name
+                    );
+                    tmpl.cgroups[slot] = None;
+                    self.deactivate_layer(tmpl.first_layer + slot, tmpl.idle_spec(slot))?;
+                    changed = true;
+                }
+            }
+
+            // Instantiate new ones.
+            let mut overflowed = false;
+            for cgrp in cgroups.iter() {
+                if tmpl.cgroups.contains(&Some(cgrp.clone())) {
+                    continue;
+                }
+                let slot = match tmpl.cgroups.iter().position(|c| c.is_none()) {
+                    Some(slot) => slot,
+                    None => {
+                        overflowed = true;
+                        break;
+                    }
+                };
+                let spec = tmpl.instance_spec(cgrp);
+                debug!("Instantiating layer {:?}", &spec.name);
+                tmpl.cgroups[slot] = Some(cgrp.clone());
+                self.activate_layer(tmpl.first_layer + slot, spec)?;
+                changed = true;
+            }
+
+            if overflowed && !tmpl.overflowed {
+                warn!(
+                    "Template layer {:?} ran out of instances (max_instances={})",
+                    &tmpl.spec.name,
+                    tmpl.cgroups.len()
+                );
+            }
+            tmpl.overflowed = overflowed;
+        }
+
+        self.templates = templates;
+
+        // Make BPF re-match the tasks against the updated layers.
+        if changed {
+            self.skel.bss_mut().layer_refresh_seq += 1;
+        }
+        Ok(())
+    }
+
+    fn refresh_systemd_units(&mut self, now: Instant) {
+        if now < self.systemd_refresh_at {
+            return;
+        }
+        self.systemd_refresh_at = now + SYSTEMD_REFRESH_INTV;
+
+        // The same unit may be matched by multiple layers, resolve it once.
+        let mut resolved = BTreeMap::<&str, Option<String>>::new();
+        let mut changed = false;
+
+        for (idx, spec) in self.layer_specs.iter().enumerate() {
+            for (or_i, or) in spec.matches.iter().enumerate() {
+                for (and_i, and) in or.iter().enumerate() {
+                    let unit = match and {
+                        LayerMatch::SystemdUnit(unit) => unit.as_str(),
+                        _ => continue,
+                    };
+                    let prefix = match resolved.get(unit) {
+                        Some(prefix) => prefix.clone(),
+                        None => match systemd_unit_cgroup_prefix(unit) {
+                            Ok(prefix) => {
+                                resolved.insert(unit, prefix.clone());
+                                prefix
+                            }
+                            // Keep the current prefix and retry on the next
+                            // refresh.
+                            Err(e) => {
+                                warn!("Failed to resolve systemd unit {:?} ({:?})", unit, &e);
+                                continue;
+                            }
+                        },
+                    };
+                    let prefix = prefix.as_deref().unwrap_or(NO_CGROUP_PREFIX);
+
+                    let mt = &mut self.skel.bss_mut().layers[idx].matches[or_i].matches[and_i];
+                    let cur = unsafe { CStr::from_ptr(mt.cgroup_prefix.as_ptr()) };
+                    if cur.to_bytes() == prefix.as_bytes() {
+                        continue;
+                    }
+
+                    if let Err(e) = copy_into_cstr(&mut mt.cgroup_prefix, prefix) {
+                        warn!("Failed to update systemd unit {:?} ({:?})", unit, &e);
+                        continue;
+                    }
+                    match prefix {
+                        NO_CGROUP_PREFIX => info!("systemd unit {:?} stopped", unit),
+                        _ => info!("systemd unit {:?} started in {:?}", unit, prefix),
+                    }
+                    changed = true;
+                }
+            }
+        }
+
+        // Make BPF re-match the tasks against the updated cgroups.
+        if changed {
+            self.skel.bss_mut().layer_refresh_seq += 1;
+        }
+    }
+
     fn step(&mut self) -> Result<()> {
         let started_at = Instant::now();
         self.sched_stats
             .refresh(&mut self.skel, &self.proc_reader, started_at)?;
 
+        if !self.templates.is_empty() {
+            self.refresh_template_layers()?;
+        }
+
+        self.refresh_systemd_units(started_at);
+
         self.refresh_cpumasks()?;
 
         self.processing_dur += Instant::now().duration_since(started_at);
@@ -1592,6 +2166,9 @@ impl<'a> Scheduler<'a> {
         };
 
         for (lidx, (spec, layer)) in self.layer_specs.iter().zip(self.layers.iter()).enumerate() {
+            if !layer.active {
+                continue;
+            }
             let lstat = |sidx| stats.bpf_stats.lstats[lidx][sidx as usize];
             let ltotal = lstat(bpf_intf::layer_stat_idx_LSTAT_LOCAL)
                 + lstat(bpf_intf::layer_stat_idx_LSTAT_GLOBAL);
@@ -1733,12 +2310,21 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
-    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<()> {
+    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
         let now = Instant::now();
         let mut next_sched_at = now + self.sched_intv;
         let mut next_monitor_at = now + self.monitor_intv;
 
         while !shutdown.load(Ordering::Relaxed) && !uei_exited!(&self.skel, uei) {
+            if RELOAD_REQUESTED.swap(false, Ordering::Relaxed) {
+                if let Err(e) = self.reload_layer_specs() {
+                    warn!(
+                        "Failed to reload layer specs, keeping the current ones: {:?}",
+                        &e
+                    );
+                }
+            }
+
             let now = Instant::now();
 
             if now >= next_sched_at {
@@ -1763,7 +2349,11 @@ impl<'a> Scheduler<'a> {
         }
 
         self.struct_ops.take();
-        uei_report!(&self.skel, uei)
+        let uei = uei_read!(&self.skel, uei);
+        if let Some(history) = &self.stall_history {
+            uei.record_stall(history);
+        }
+        Ok(uei)
     }
 }
 
@@ -1790,7 +2380,9 @@ fn write_example_file(path: &str) -> Result<()> {
                     util_range: (0.8, 0.9),
                     min_exec_us: 1000,
                     perf: 1024,
+                    growth_algo: LayerGrowthAlgo::Sticky,
                 },
+                template: None,
             },
             LayerSpec {
                 name: "immediate".into(),
@@ -1805,6 +2397,7 @@ fn write_example_file(path: &str) -> Result<()> {
                     exclusive: true,
                     perf: 1024,
                 },
+                template: None,
             },
             LayerSpec {
                 name: "normal".into(),
@@ -1817,7 +2410,9 @@ fn write_example_file(path: &str) -> Result<()> {
                     preempt: false,
                     exclusive: false,
                     perf: 1024,
+                    growth_algo: LayerGrowthAlgo::Linear,
                 },
+                template: None,
             },
         ],
     };
@@ -1834,12 +2429,29 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
     if nr_specs == 0 {
         bail!("No layer spec");
     }
-    if nr_specs > MAX_LAYERS {
+    let nr_layers: usize = specs
+        .iter()
+        .map(|spec| match &spec.template {
+            Some(template) => template.max_instances,
+            None => 1,
+        })
+        .sum();
+    if nr_layers > MAX_LAYERS {
         bail!("Too many layer specs");
     }
 
     for (idx, spec) in specs.iter().enumerate() {
-        if idx < nr_specs - 1 {
+        if let Some(template) = &spec.template {
+            if idx == nr_specs - 1 {
+                bail!("Terminal spec {:?} can't be a template", spec.name);
+            }
+            if template.max_instances == 0 {
+                bail!("Template spec {:?} has zero max_instances", spec.name);
+            }
+            if template.cgroup_parent.len() >= MAX_PATH {
+                bail!("Template spec {:?} has too long a cgroup_parent", spec.name);
+            }
+        } else if idx < nr_specs - 1 {
             if spec.matches.len() == 0 {
                 bail!("Non-terminal spec {:?} has NULL matches", spec.name);
             }
@@ -1857,8 +2469,13 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
             );
         }
 
+        // Template instances add a CgroupPrefix match to each OR block.
+        let max_ands = match &spec.template {
+            Some(_) => NR_LAYER_MATCH_KINDS - 1,
+            None => NR_LAYER_MATCH_KINDS,
+        };
         for (ands_idx, ands) in spec.matches.iter().enumerate() {
-            if ands.len() > NR_LAYER_MATCH_KINDS {
+            if ands.len() > max_ands {
                 bail!(
                     "Spec {:?}'s {}th OR block has too many ({}) match conditions",
                     spec.name,
@@ -1883,6 +2500,18 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
                             bail!("Spec {:?} has too long a process name prefix", spec.name);
                         }
                     }
+                    LayerMatch::SystemdUnit(unit) => {
+                        if !cgroup::is_systemd_cgroup_unit(unit) {
+                            bail!(
+                                "Spec {:?} has invalid systemd unit {:?}, must be a service, scope or slice",
+                                spec.name,
+                                unit
+                            );
+                        }
+                        if unit.len() > MAX_PATH {
+                            bail!("Spec {:?} has too long a systemd unit name", spec.name);
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -1925,6 +2554,24 @@ fn verify_layer_specs(specs: &[LayerSpec]) -> Result<()> {
     Ok(())
 }
 
+fn parse_layer_specs(inputs: &[String]) -> Result<Vec<LayerSpec>> {
+    let mut layer_config = LayerConfig { specs: vec![] };
+    for (idx, input) in inputs.iter().enumerate() {
+        layer_config.specs.append(
+            &mut LayerSpec::parse(input)
+                .context(format!("Failed to parse specs[{}] ({:?})", idx, input))?,
+        );
+    }
+
+    debug!("specs={}", serde_json::to_string_pretty(&layer_config)?);
+    verify_layer_specs(&layer_config.specs)?;
+    Ok(layer_config.specs)
+}
+
+extern "C" fn handle_sighup(_: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+}
+
 fn main() -> Result<()> {
     let opts = Opts::parse();
 
@@ -1952,18 +2599,9 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let mut layer_config = LayerConfig { specs: vec![] };
-    for (idx, input) in opts.specs.iter().enumerate() {
-        layer_config.specs.append(
-            &mut LayerSpec::parse(input)
-                .context(format!("Failed to parse specs[{}] ({:?})", idx, input))?,
-        );
-    }
-
-    debug!("specs={}", serde_json::to_string_pretty(&layer_config)?);
-    verify_layer_specs(&layer_config.specs)?;
+    let layer_specs = parse_layer_specs(&opts.specs)?;
 
-    let mut sched = Scheduler::init(&opts, layer_config.specs)?;
+    let mut sched = Scheduler::init(&opts, layer_specs)?;
 
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown_clone = shutdown.clone();
@@ -1972,5 +2610,16 @@ fn main() -> Result<()> {
     })
     .context("Error setting Ctrl-C handler")?;
 
-    sched.run(shutdown)
+    if unsafe { libc::signal(libc::SIGHUP, handle_sighup as libc::sighandler_t) } == libc::SIG_ERR {
+        bail!("Error setting SIGHUP handler");
+    }
+
+    if let Err(e) = systemd::notify_ready() {
+        warn!("Failed to notify systemd: {:#}", e);
+    }
+
+    // Exit with a status which tells systemd why the scheduler went away.
+    let uei = sched.run(shutdown)?;
+    drop(sched);
+    systemd::report_and_exit(&uei)
 }
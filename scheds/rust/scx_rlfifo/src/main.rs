@@ -9,69 +9,135 @@ pub mod bpf_intf;
 mod bpf;
 use bpf::*;
 
+use scx_utils::time;
+use scx_utils::weight;
 use scx_utils::Topology;
 
+use std::collections::VecDeque;
+
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use std::time::SystemTime;
-
 use anyhow::Result;
+use clap::Parser;
+use clap::ValueEnum;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Policy {
+    /// Dispatch tasks in the same order they are queued.
+    Fifo,
+    /// Like fifo, but each task runs for at most --quantum-us before going back to the end of the
+    /// queue.
+    Rr,
+    /// Map nice values to --prio-levels priority levels and dispatch tasks from the highest
+    /// priority level first, in FIFO order within the same level.
+    Prio,
+}
 
-struct Scheduler<'a> {
-    bpf: BpfScheduler<'a>,
+/// scx_rlfifo: simple reference scheduler running in user-space
+///
+/// scx_rlfifo implements a few basic scheduling policies on top of scx_rustland_core, keeping the
+/// code as small as possible, so that it can be used as a starting point to implement more
+/// complex policies.
+#[derive(Debug, Parser)]
+struct Opts {
+    /// Scheduling policy.
+    #[clap(short = 'p', long, value_enum, default_value = "fifo")]
+    policy: Policy,
+
+    /// Default time slice in microseconds.
+    #[clap(short = 's', long, default_value = "5000")]
+    slice_us: u64,
+
+    /// Time slice in microseconds assigned to each task with the rr policy.
+    #[clap(short = 'q', long, default_value = "1000")]
+    quantum_us: u64,
+
+    /// Number of priority levels used by the prio policy (1-40).
+    #[clap(short = 'l', long, default_value = "3",
+           value_parser = clap::value_parser!(u64).range(1..=40))]
+    prio_levels: u64,
 }
 
-impl<'a> Scheduler<'a> {
-    fn init() -> Result<Self> {
-        let topo = Topology::new().expect("Failed to build host topology");
-        let bpf = BpfScheduler::init(5000, topo.nr_cpus_possible() as i32, false, 0, false, false)?;
-        Ok(Self { bpf })
+// Basic scheduling policies: tasks are stored in one FIFO queue per priority level (the fifo and
+// rr policies only use a single level).
+struct RlFifo {
+    policy: Policy,
+    quantum_ns: u64,
+    queues: Vec<VecDeque<QueuedTask>>,
+}
+
+impl RlFifo {
+    fn new(opts: &Opts) -> Self {
+        let nr_levels = match opts.policy {
+            Policy::Prio => opts.prio_levels as usize,
+            _ => 1,
+        };
+        Self {
+            policy: opts.policy,
+            quantum_ns: time::us_to_ns(opts.quantum_us),
+            queues: vec![VecDeque::new(); nr_levels],
+        }
+    }
+
+    // Convert the task's weight back to its nice value, i.e. the nice value whose load weight is
+    // the closest to the task's weight scaled to the kernel's load weights.
+    fn nice(task: &QueuedTask) -> i64 {
+        let load = task.weight * weight::NICE_0_LOAD / weight::WEIGHT_DFL as u64;
+        let idx = weight::PRIO_TO_WEIGHT
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, prio_load)| (**prio_load as u64).abs_diff(load))
+            .map_or(20, |(idx, _)| idx);
+        idx as i64 - 20
+    }
+
+    // Map the task's nice value to a priority level (0 is the highest priority).
+    fn level(&self, task: &QueuedTask) -> usize {
+        let nr_levels = self.queues.len() as i64;
+        ((Self::nice(task) + 20) * nr_levels / 40) as usize
     }
+}
 
-    fn now() -> u64 {
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+impl Scheduler for RlFifo {
+    fn enqueue(&mut self, _bpf: &mut BpfScheduler, task: QueuedTask) {
+        let level = self.level(&task);
+        self.queues[level].push_back(task);
     }
 
-    fn dispatch_tasks(&mut self) {
-        loop {
-            // Get queued taks and dispatch them in order (FIFO).
-            match self.bpf.dequeue_task() {
-                Ok(Some(task)) => {
-                    // task.cpu < 0 is used to to notify an exiting task, in this
-                    // case we can simply ignore the task.
-                    if task.cpu >= 0 {
-                        let _ = self.bpf.dispatch_task(&DispatchedTask::new(&task));
-
-                        // Give the task a chance to run and prevent overflowing the dispatch queue.
-                        std::thread::yield_now();
-                    }
-                }
-                Ok(None) => {
-                    // Notify the BPF component that all tasks have been scheduled and dispatched.
-                    self.bpf.update_tasks(Some(0), Some(0));
-                    break;
+    fn dispatch(&mut self, bpf: &mut BpfScheduler) {
+        // Dispatch queued tasks from the highest priority level, in FIFO order.
+        for level in 0..self.queues.len() {
+            while let Some(task) = self.queues[level].pop_front() {
+                let mut dispatched_task = DispatchedTask::new(&task);
+                if self.policy == Policy::Rr {
+                    dispatched_task.set_slice_ns(self.quantum_ns);
                 }
-                Err(_) => {
-                    break;
+
+                if bpf.dispatch_task(&dispatched_task).is_err() {
+                    // Retry on the next round.
+                    self.queues[level].push_front(task);
+                    return;
                 }
+
+                // Give the task a chance to run and prevent overflowing the dispatch queue.
+                std::thread::yield_now();
             }
         }
-        // All queued tasks have been dipatched, yield to reduce scheduler's CPU consumption.
-        std::thread::yield_now();
     }
 
-    fn print_stats(&mut self) {
-        let nr_user_dispatches = *self.bpf.nr_user_dispatches_mut();
-        let nr_kernel_dispatches = *self.bpf.nr_kernel_dispatches_mut();
-        let nr_cancel_dispatches = *self.bpf.nr_cancel_dispatches_mut();
-        let nr_bounce_dispatches = *self.bpf.nr_bounce_dispatches_mut();
-        let nr_failed_dispatches = *self.bpf.nr_failed_dispatches_mut();
-        let nr_sched_congested = *self.bpf.nr_sched_congested_mut();
+    fn nr_scheduled(&self) -> u64 {
+        self.queues.iter().map(|q| q.len() as u64).sum()
+    }
+
+    fn tick(&mut self, bpf: &mut BpfScheduler) {
+        let nr_user_dispatches = *bpf.nr_user_dispatches_mut();
+        let nr_kernel_dispatches = *bpf.nr_kernel_dispatches_mut();
+        let nr_cancel_dispatches = *bpf.nr_cancel_dispatches_mut();
+        let nr_bounce_dispatches = *bpf.nr_bounce_dispatches_mut();
+        let nr_failed_dispatches = *bpf.nr_failed_dispatches_mut();
+        let nr_sched_congested = *bpf.nr_sched_congested_mut();
 
         println!(
             "user={} kernel={} cancel={} bounce={} fail={} cong={}",
@@ -83,22 +149,6 @@ impl<'a> Scheduler<'a> {
             nr_sched_congested,
         );
     }
-
-    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<()> {
-        let mut prev_ts = Self::now();
-
-        while !shutdown.load(Ordering::Relaxed) && !self.bpf.exited() {
-            self.dispatch_tasks();
-
-            let curr_ts = Self::now();
-            if curr_ts > prev_ts {
-                self.print_stats();
-                prev_ts = curr_ts;
-            }
-        }
-
-        self.bpf.shutdown_and_report()
-    }
 }
 
 fn print_warning() {
@@ -121,7 +171,12 @@ please open a GitHub issue.
 }
 
 fn main() -> Result<()> {
-    let mut sched = Scheduler::init()?;
+    let opts = Opts::parse();
+
+    let topo = Topology::new().expect("Failed to build host topology");
+    let nr_cpus = topo.nr_cpus_possible() as i32;
+    let mut bpf = BpfScheduler::init(opts.slice_us, nr_cpus, false, 0, false, false)?;
+    let mut sched = RlFifo::new(&opts);
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown_clone = shutdown.clone();
 
@@ -131,5 +186,5 @@ fn main() -> Result<()> {
         shutdown_clone.store(true, Ordering::Relaxed);
     })?;
 
-    sched.run(shutdown)
+    bpf.run(&mut sched, &shutdown)
 }
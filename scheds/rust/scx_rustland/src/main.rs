@@ -9,11 +9,13 @@ pub mod bpf_intf;
 mod bpf;
 use bpf::*;
 
+use scx_utils::preflight;
+use scx_utils::sched_info::SchedulerInfo;
+use scx_utils::vm::StealSnapshot;
+use scx_utils::vm::VmInfo;
 use scx_utils::Topology;
 use scx_utils::TopologyMap;
 
-use std::thread;
-
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 
@@ -28,9 +30,9 @@ use std::path::Path;
 
 use anyhow::Context;
 use anyhow::Result;
+use clap::CommandFactory;
 use clap::Parser;
 use log::info;
-use log::warn;
 
 const SCHEDULER_NAME: &'static str = "RustLand";
 
@@ -135,6 +137,19 @@ struct Opts {
     #[clap(short = 'p', long, action = clap::ArgAction::SetTrue)]
     partial: bool,
 
+    /// If specified, busy-poll for new tasks, instead of yielding the CPU, as long as at least
+    /// this amount of tasks is received in a single scheduling round.
+    ///
+    /// Busy-polling reduces the wakeup latency of the scheduler when the system is busy, at the
+    /// cost of a higher CPU usage of the scheduler itself. 0 disables busy-polling.
+    #[clap(long, default_value = "0")]
+    busy_poll_tasks: u64,
+
+    /// How long to keep busy-polling (in microseconds) after the last busy scheduling round
+    /// before falling back to yielding the CPU (see --busy-poll-tasks).
+    #[clap(long, default_value = "1000")]
+    busy_poll_idle_us: u64,
+
     /// Exit debug dump buffer length. 0 indicates default.
     #[clap(long, default_value = "0")]
     exit_dump_len: u32,
@@ -147,6 +162,11 @@ struct Opts {
     /// Print scheduler version and exit.
     #[clap(short = 'v', long, action = clap::ArgAction::SetTrue)]
     version: bool,
+
+    /// Print a JSON description of the scheduler's options, modes and kernel requirements and
+    /// exit.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    describe: bool,
 }
 
 // Time constants.
@@ -155,6 +175,9 @@ const NSEC_PER_USEC: u64 = 1_000;
 const MSEC_PER_SEC: u64 = 1_000;
 const NSEC_PER_SEC: u64 = 1_000_000_000;
 
+// Fraction of stolen time above which a vCPU is reported in the statistics.
+const STEAL_RATIO_THRESH: f64 = 0.2;
+
 // Basic item stored in the task information map.
 #[derive(Debug)]
 struct TaskInfo {
@@ -244,9 +267,8 @@ impl TaskTree {
     }
 }
 
-// Main scheduler object
-struct Scheduler<'a> {
-    bpf: BpfScheduler<'a>, // BPF connector
+// Main scheduler object (scheduling policy driven by BpfScheduler::run())
+struct RustLand {
     topo_map: TopologyMap, // Host topology
     task_pool: TaskTree,   // tasks ordered by vruntime
     task_map: TaskInfoMap, // map pids to the corresponding task information
@@ -257,10 +279,11 @@ struct Scheduler<'a> {
     init_page_faults: u64, // Initial page faults counter
     builtin_idle: bool,    // Use sched-ext built-in idle selection logic
     no_preemption: bool,   // Disable task preemption
+    prev_steal: Option<StealSnapshot>, // vCPU steal time (only in shared VMs)
 }
 
-impl<'a> Scheduler<'a> {
-    fn init(opts: &Opts) -> Result<Self> {
+impl RustLand {
+    fn init(opts: &Opts) -> Result<(Self, BpfScheduler<'static>)> {
         // Initialize core mapping topology.
         let topo = Topology::new().expect("Failed to build host topology");
         let topo_map = TopologyMap::new(topo).expect("Failed to generate topology map");
@@ -290,9 +313,20 @@ impl<'a> Scheduler<'a> {
         // Initialize initial page fault counter.
         let init_page_faults: u64 = 0;
 
+        // Inside a VM, keep track of the time the host steals from the vCPUs, unless they are
+        // backed by dedicated physical CPUs.
+        let vm = VmInfo::detect();
+        let prev_steal = match &vm.hypervisor {
+            Some(hypervisor) if !vm.dedicated => {
+                info!("running on {} with shared vCPUs", hypervisor);
+                StealSnapshot::read().ok()
+            }
+            _ => None,
+        };
+
         // Low-level BPF connector.
         let nr_online_cpus = topo_map.nr_cpus_possible();
-        let bpf = BpfScheduler::init(
+        let mut bpf = BpfScheduler::init(
             opts.slice_us,
             nr_online_cpus as i32,
             opts.partial,
@@ -300,11 +334,11 @@ impl<'a> Scheduler<'a> {
             opts.full_user,
             opts.debug,
         )?;
+        bpf.set_busy_poll(opts.busy_poll_tasks, opts.busy_poll_idle_us);
         info!("{} scheduler attached - {} online CPUs", SCHEDULER_NAME, nr_online_cpus);
 
         // Return scheduler object.
-        Ok(Self {
-            bpf,
+        let sched = Self {
             topo_map,
             task_pool,
             task_map,
@@ -315,21 +349,23 @@ impl<'a> Scheduler<'a> {
             init_page_faults,
             builtin_idle,
             no_preemption,
-        })
+            prev_steal,
+        };
+        Ok((sched, bpf))
     }
 
     // Return the amount of idle cores.
     //
     // On SMT systems consider only one CPU for each fully idle core, to avoid disrupting
     // performnance too much by running multiple tasks in the same core.
-    fn nr_idle_cpus(&mut self) -> usize {
+    fn nr_idle_cpus(&self, bpf: &BpfScheduler) -> usize {
         let mut idle_cpu_count = 0;
 
         // Count the number of cores where all the CPUs are idle.
         for core in self.topo_map.iter() {
             let mut all_idle = true;
             for cpu_id in core {
-                if self.bpf.get_cpu_pid(*cpu_id as i32) != 0 {
+                if bpf.get_cpu_pid(*cpu_id as i32) != 0 {
                     all_idle = false;
                     break;
                 }
@@ -356,7 +392,7 @@ impl<'a> Scheduler<'a> {
     // interactive or not (interactive tasks are allowed to preempt other tasks).
     //
     // This method implements the main task ordering logic of the scheduler.
-    fn update_enqueued(&mut self, task: &QueuedTask) -> (u64, bool) {
+    fn update_enqueued(&mut self, bpf: &mut BpfScheduler, task: &QueuedTask) -> (u64, bool) {
         // Determine if a task is new or old, based on their current runtime and previous runtime
         // counters.
         //
@@ -376,7 +412,7 @@ impl<'a> Scheduler<'a> {
         let now = Self::now();
 
         // Get the current effective time slice.
-        let slice_ns = self.bpf.get_effective_slice_us() * MSEC_PER_SEC;
+        let slice_ns = bpf.get_effective_slice_us() * MSEC_PER_SEC;
 
         // Update dynamic slice boost.
         //
@@ -465,46 +501,8 @@ impl<'a> Scheduler<'a> {
         (task_info.vruntime, is_interactive)
     }
 
-    // Drain all the tasks from the queued list, update their vruntime (Self::update_enqueued()),
-    // then push them all to the task pool (doing so will sort them by their vruntime).
-    fn drain_queued_tasks(&mut self) {
-        loop {
-            match self.bpf.dequeue_task() {
-                Ok(Some(task)) => {
-                    // Check for exiting tasks (cpu < 0) and remove their corresponding entries in
-                    // the task map (if present).
-                    if task.cpu < 0 {
-                        self.task_map.tasks.remove(&task.pid);
-                        continue;
-                    }
-
-                    // Update task information and determine vruntime and interactiveness.
-                    let (vruntime, is_interactive) = self.update_enqueued(&task);
-
-                    // Insert task in the task pool (ordered by vruntime).
-                    self.task_pool.push(Task {
-                        qtask: task,
-                        vruntime,
-                        is_interactive,
-                    });
-                }
-                Ok(None) => {
-                    // Reset nr_queued and update nr_scheduled, to notify the dispatcher that
-                    // queued tasks are drained, but there is still some work left to do in the
-                    // scheduler.
-                    self.bpf.update_tasks(Some(0), Some(self.task_pool.tasks.len() as u64));
-                    break;
-                }
-                Err(err) => {
-                    warn!("Error: {}", err);
-                    break;
-                }
-            }
-        }
-    }
-
     // Dynamically adjust the time slice based on the amount of waiting tasks.
-    fn scale_slice_ns(&mut self) {
+    fn scale_slice_ns(&mut self, bpf: &mut BpfScheduler) {
         let nr_scheduled = self.task_pool.tasks.len() as u64;
         let slice_us_max = self.slice_ns / NSEC_PER_USEC;
 
@@ -513,17 +511,17 @@ impl<'a> Scheduler<'a> {
         let slice_us = (slice_us_max / scaling).max(USEC_PER_NSEC / 4);
 
         // Apply new scaling.
-        self.bpf.set_effective_slice_us(slice_us);
+        bpf.set_effective_slice_us(slice_us);
     }
 
     // Dispatch tasks from the task pool in order (sending them to the BPF dispatcher).
-    fn dispatch_tasks(&mut self) {
+    fn dispatch_tasks(&mut self, bpf: &mut BpfScheduler) {
         // Dispatch only a batch of tasks equal to the amount of idle CPUs in the system.
         //
         // This allows to have more tasks sitting in the task pool, reducing the pressure on the
         // dispatcher queues and giving a chance to higher priority tasks to come in and get
         // dispatched earlier, mitigating potential priority inversion issues.
-        for _ in 0..self.nr_idle_cpus() {
+        for _ in 0..self.nr_idle_cpus(bpf) {
             match self.task_pool.pop() {
                 Some(task) => {
                     // Update global minimum vruntime.
@@ -549,7 +547,7 @@ impl<'a> Scheduler<'a> {
                     }
 
                     // Send task to the BPF dispatcher.
-                    match self.bpf.dispatch_task(&dispatched_task) {
+                    match bpf.dispatch_task(&dispatched_task) {
                         Ok(_) => {}
                         Err(_) => {
                             /*
@@ -564,23 +562,6 @@ impl<'a> Scheduler<'a> {
                 None => break,
             }
         }
-        // Update nr_scheduled to notify the dispatcher that all the tasks received by the
-        // scheduler has been dispatched, so there is no reason to re-activate the scheduler,
-        // unless more tasks are queued.
-        self.bpf.update_tasks(None, Some(self.task_pool.tasks.len() as u64));
-    }
-
-    // Main scheduling function (called in a loop to periodically drain tasks from the queued list
-    // and dispatch them to the BPF part via the dispatched list).
-    fn schedule(&mut self) {
-        self.drain_queued_tasks();
-        self.dispatch_tasks();
-
-        // Adjust the dynamic time slice immediately after dispatching the tasks.
-        self.scale_slice_ns();
-
-        // Yield to avoid using too much CPU from the scheduler itself.
-        thread::yield_now();
     }
 
     // Get total page faults from /proc/self/stat.
@@ -638,10 +619,10 @@ impl<'a> Scheduler<'a> {
     }
 
     // Print critical user-space scheduler statistics.
-    fn print_faults(&mut self) {
+    fn print_faults(&mut self, bpf: &mut BpfScheduler) {
         // Get counters of scheduling failures.
-        let nr_failed_dispatches = *self.bpf.nr_failed_dispatches_mut();
-        let nr_sched_congested = *self.bpf.nr_sched_congested_mut();
+        let nr_failed_dispatches = *bpf.nr_failed_dispatches_mut();
+        let nr_sched_congested = *bpf.nr_sched_congested_mut();
 
         // Get the total amount of page faults of the user-space scheduler.
         //
@@ -669,7 +650,7 @@ impl<'a> Scheduler<'a> {
     }
 
     // Print internal scheduler statistics (fetched from the BPF part).
-    fn print_stats(&mut self) {
+    fn print_stats(&mut self, bpf: &mut BpfScheduler) {
         // Show minimum vruntime (this should be constantly incrementing).
         info!("vruntime={}", self.min_vruntime);
 
@@ -677,22 +658,22 @@ impl<'a> Scheduler<'a> {
         info!("  tasks={}", self.task_map.tasks.len());
 
         // Show general statistics.
-        let nr_user_dispatches = *self.bpf.nr_user_dispatches_mut();
-        let nr_kernel_dispatches = *self.bpf.nr_kernel_dispatches_mut();
+        let nr_user_dispatches = *bpf.nr_user_dispatches_mut();
+        let nr_kernel_dispatches = *bpf.nr_kernel_dispatches_mut();
         info!(
             "  nr_user_dispatches={} nr_kernel_dispatches={}",
             nr_user_dispatches, nr_kernel_dispatches,
         );
-        let nr_cancel_dispatches = *self.bpf.nr_cancel_dispatches_mut();
-        let nr_bounce_dispatches = *self.bpf.nr_bounce_dispatches_mut();
+        let nr_cancel_dispatches = *bpf.nr_cancel_dispatches_mut();
+        let nr_bounce_dispatches = *bpf.nr_bounce_dispatches_mut();
         info!(
             "  nr_cancel_dispatches={} nr_bounce_dispatches={}",
             nr_cancel_dispatches, nr_bounce_dispatches,
         );
 
         // Show tasks that are waiting to be dispatched.
-        let nr_queued = *self.bpf.nr_queued_mut();
-        let nr_scheduled = *self.bpf.nr_scheduled_mut();
+        let nr_queued = *bpf.nr_queued_mut();
+        let nr_scheduled = *bpf.nr_scheduled_mut();
         let nr_waiting = nr_queued + nr_scheduled;
         info!(
             "  nr_waiting={} [nr_queued={} + nr_scheduled={}]",
@@ -700,14 +681,26 @@ impl<'a> Scheduler<'a> {
         );
 
         // Show total page faults of the user-space scheduler.
-        self.print_faults();
+        self.print_faults(bpf);
 
         // Show current used time slice.
-        info!("time slice = {} us", self.bpf.get_effective_slice_us());
+        info!("time slice = {} us", bpf.get_effective_slice_us());
 
         // Show current slice boost.
         info!("slice boost = {}", self.eff_slice_boost);
 
+        // Show the vCPUs that the host kept from running for a significant amount of time.
+        if let Some(prev) = self.prev_steal.take() {
+            if let Ok(steal) = StealSnapshot::read() {
+                info!(
+                    "stolen vCPUs (>{}%) = {:?}",
+                    STEAL_RATIO_THRESH * 100.0,
+                    steal.delta(&prev).stolen_cpus(STEAL_RATIO_THRESH)
+                );
+                self.prev_steal = Some(steal);
+            }
+        }
+
         // Show tasks that are currently running on each core and CPU.
         let sched_cpu = match Self::get_current_cpu() {
             Ok(cpu_info) => cpu_info,
@@ -719,7 +712,7 @@ impl<'a> Scheduler<'a> {
                 let pid = if *cpu_id as i32 == sched_cpu {
                     "[self]".to_string()
                 } else {
-                    self.bpf.get_cpu_pid(*cpu_id as i32).to_string()
+                    bpf.get_cpu_pid(*cpu_id as i32).to_string()
                 };
                 info!("  core {:2} cpu {:2} pid={}", core_id, cpu_id, pid);
             }
@@ -727,36 +720,79 @@ impl<'a> Scheduler<'a> {
 
         log::logger().flush();
     }
+}
 
-    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<()> {
-        let mut prev_ts = Self::now();
+impl Scheduler for RustLand {
+    // Update task information, determine vruntime and interactiveness, then push the task to the
+    // task pool (doing so will sort them by their vruntime).
+    fn enqueue(&mut self, bpf: &mut BpfScheduler, task: QueuedTask) {
+        let (vruntime, is_interactive) = self.update_enqueued(bpf, &task);
+
+        // Insert task in the task pool (ordered by vruntime).
+        self.task_pool.push(Task {
+            qtask: task,
+            vruntime,
+            is_interactive,
+        });
+    }
 
-        while !shutdown.load(Ordering::Relaxed) && !self.bpf.exited() {
-            // Call the main scheduler body.
-            self.schedule();
+    fn dispatch(&mut self, bpf: &mut BpfScheduler) {
+        self.dispatch_tasks(bpf);
 
-            // Print scheduler statistics every second.
-            let curr_ts = Self::now();
-            if curr_ts - prev_ts > NSEC_PER_SEC {
-                self.print_stats();
+        // Adjust the dynamic time slice immediately after dispatching the tasks.
+        self.scale_slice_ns(bpf);
+    }
 
-                prev_ts = curr_ts;
-            }
-        }
-        // Dump scheduler statistics before exiting
-        self.print_stats();
+    fn nr_scheduled(&self) -> u64 {
+        self.task_pool.tasks.len() as u64
+    }
 
-        self.bpf.shutdown_and_report()
+    // Remove the corresponding entry of an exiting task in the task map (if present).
+    fn task_exit(&mut self, _bpf: &mut BpfScheduler, pid: i32) {
+        self.task_map.tasks.remove(&pid);
+    }
+
+    // Print scheduler statistics every second and before exiting.
+    fn tick(&mut self, bpf: &mut BpfScheduler) {
+        self.print_stats(bpf);
     }
 }
 
 // Unregister the scheduler.
-impl<'a> Drop for Scheduler<'a> {
+impl Drop for RustLand {
     fn drop(&mut self) {
         info!("Unregister {} scheduler", SCHEDULER_NAME);
     }
 }
 
+// Describe the scheduler for --describe. The kernel requirements are the kfuncs used
+// unconditionally by the scx_rustland_core BPF component.
+fn describe() -> SchedulerInfo {
+    SchedulerInfo::from_clap(&Opts::command(), VERSION)
+        .mode(
+            "partial",
+            "Only switch tasks which are set to SCHED_EXT",
+            &["--partial"],
+        )
+        .mode(
+            "full_user",
+            "Make all scheduling decisions in user-space",
+            &["--full-user"],
+        )
+        .mode(
+            "builtin_idle",
+            "Use the sched-ext built-in idle selection logic",
+            &["--builtin-idle"],
+        )
+        .kfunc("scx_bpf_create_dsq")
+        .kfunc("scx_bpf_dispatch_cancel")
+        .kfunc("scx_bpf_dispatch_nr_slots")
+        .kfunc("scx_bpf_select_cpu_dfl")
+        .kfunc("scx_bpf_kick_cpu")
+        .kfunc("bpf_task_from_pid")
+        .struct_field("task_struct", "scx")
+}
+
 fn main() -> Result<()> {
     let opts = Opts::parse();
 
@@ -770,6 +806,11 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if opts.describe {
+        println!("{}", describe().to_json()?);
+        return Ok(());
+    }
+
     let loglevel = simplelog::LevelFilter::Info;
 
     let mut lcfg = simplelog::ConfigBuilder::new();
@@ -784,7 +825,9 @@ fn main() -> Result<()> {
         simplelog::ColorChoice::Auto,
     )?;
 
-    let mut sched = Scheduler::init(&opts)?;
+    preflight::check(&describe().requires).into_result()?;
+
+    let (mut sched, mut bpf) = RustLand::init(&opts)?;
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown_clone = shutdown.clone();
     ctrlc::set_handler(move || {
@@ -793,5 +836,5 @@ fn main() -> Result<()> {
     .context("Error setting Ctrl-C handler")?;
 
     // Start the scheduler.
-    sched.run(shutdown)
+    bpf.run(&mut sched, &shutdown)
 }
@@ -37,6 +37,7 @@
  */
 #include <scx/common.bpf.h>
 #include <scx/ravg_impl.bpf.h>
+#include <scx/weight.bpf.h>
 #include "intf.h"
 
 #include <errno.h>
@@ -250,16 +251,6 @@ static inline bool vtime_before(u64 a, u64 b)
 	return (s64)(a - b) < 0;
 }
 
-static u64 scale_up_fair(u64 value, u64 weight)
-{
-	return value * weight / 100;
-}
-
-static u64 scale_inverse_fair(u64 value, u64 weight)
-{
-	return value * 100 / weight;
-}
-
 static void dom_dcycle_adj(u32 dom_id, u32 weight, u64 now, bool runnable)
 {
 	struct dom_ctx *domc;
@@ -535,17 +526,6 @@ static u64 min(u64 a, u64 b)
  * If a task goes up by ~10% and another task goes down by ~10% then
  * the relative distance between them is ~25%.)"
  */
-const int sched_prio_to_weight[DL_MAX_LAT_PRIO] = {
- /* -20 */     88761,     71755,     56483,     46273,     36291,
- /* -15 */     29154,     23254,     18705,     14949,     11916,
- /* -10 */      9548,      7620,      6100,      4904,      3906,
- /*  -5 */      3121,      2501,      1991,      1586,      1277,
- /*   0 */      1024,       820,       655,       526,       423,
- /*   5 */       335,       272,       215,       172,       137,
- /*  10 */       110,        87,        70,        56,        45,
- /*  15 */        36,        29,        23,        18,        15,
-};
-
 static u64 sched_prio_to_latency_weight(u64 prio)
 {
 	if (prio >= DL_MAX_LAT_PRIO) {
@@ -553,7 +533,7 @@ static u64 sched_prio_to_latency_weight(u64 prio)
 		return 0;
 	}
 
-	return sched_prio_to_weight[DL_MAX_LAT_PRIO - prio - 1];
+	return scx_prio_to_weight[DL_MAX_LAT_PRIO - prio - 1];
 }
 
 static u64 task_compute_dl(struct task_struct *p, struct task_ctx *taskc,
@@ -638,7 +618,7 @@ static u64 task_compute_dl(struct task_struct *p, struct task_ctx *taskc,
 	 * with higher weight is given a higher frequency factor than a task
 	 * with a lower weight.
 	 */
-	freq_factor = scale_up_fair(freq_factor, p->scx.weight);
+	freq_factor = scx_scale_by_weight(freq_factor, p->scx.weight);
 
 	/*
 	 * The above frequencies roughly follow an exponential distribution, so
@@ -664,7 +644,7 @@ static u64 task_compute_dl(struct task_struct *p, struct task_ctx *taskc,
 	 */
 	avg_run_raw = taskc->avg_runtime / DL_RUNTIME_SCALE;
 	avg_run_raw = (avg_run_raw, DL_MAX_LATENCY_NS);
-	avg_run_raw = scale_inverse_fair(avg_run_raw, p->scx.weight);
+	avg_run_raw = scx_scale_by_weight_inverse(avg_run_raw, p->scx.weight);
 	avg_run = bpf_log2l(avg_run_raw + 1);
 
 	if (avg_run < lat_prio) {
@@ -700,7 +680,7 @@ static u64 task_compute_dl(struct task_struct *p, struct task_ctx *taskc,
 	 * In other words, the "CPU request length" which is used to determine
 	 * the actual absolute vtime that the task is dispatched with.
 	 */
-	return scale_inverse_fair(taskc->avg_runtime, lat_scale);
+	return scx_scale_by_weight_inverse(taskc->avg_runtime, lat_scale);
 }
 
 static void clamp_task_vtime(struct task_struct *p, struct task_ctx *taskc, u64 enq_flags)
@@ -783,7 +763,7 @@ static bool task_set_domain(struct task_ctx *taskc, struct task_struct *p,
 		taskc->dom_id = new_dom_id;
 		p->scx.dsq_vtime = dom_min_vruntime(new_domc);
 		taskc->deadline = p->scx.dsq_vtime +
-				  scale_inverse_fair(taskc->avg_runtime, taskc->weight);
+				  scx_scale_by_weight_inverse(taskc->avg_runtime, taskc->weight);
 		bpf_cpumask_and(t_cpumask, (const struct cpumask *)d_cpumask,
 				p->cpus_ptr);
 	}
@@ -1346,7 +1326,7 @@ static void stopping_update_vtime(struct task_struct *p,
 	taskc->sum_runtime += delta;
 	taskc->avg_runtime = calc_avg(taskc->avg_runtime, taskc->sum_runtime);
 
-	p->scx.dsq_vtime += scale_inverse_fair(delta, p->scx.weight);
+	p->scx.dsq_vtime += scx_vtime_delta(delta, p->scx.weight);
 	taskc->deadline = p->scx.dsq_vtime + task_compute_dl(p, taskc, 0);
 }
 
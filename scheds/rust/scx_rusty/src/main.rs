@@ -30,19 +30,28 @@ use anyhow::anyhow;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
 use libbpf_rs::skel::OpenSkel as _;
 use libbpf_rs::skel::SkelBuilder as _;
 use log::info;
+use log::warn;
 use scx_utils::compat;
+use scx_utils::config::ConfigFile;
 use scx_utils::init_libbpf_logging;
+use scx_utils::sandbox::Sandbox;
 use scx_utils::scx_ops_attach;
 use scx_utils::scx_ops_load;
+use scx_utils::time;
 use scx_utils::uei_exited;
 use scx_utils::uei_read;
 use scx_utils::Cpumask;
+use scx_utils::PerCpuArraySnapshot;
 use scx_utils::Topology;
 use scx_utils::UserExitInfo;
+use serde::Deserialize;
+use serde::Serialize;
 
 const MAX_DOMS: usize = bpf_intf::consts_MAX_DOMS as usize;
 const MAX_CPUS: usize = bpf_intf::consts_MAX_CPUS as usize;
@@ -77,7 +86,7 @@ const MAX_CPUS: usize = bpf_intf::consts_MAX_CPUS as usize;
 /// WARNING: scx_rusty currently assumes that all domains have equal
 /// processing power and at similar distances from each other. This
 /// limitation will be removed in the future.
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser, Serialize, Deserialize)]
 struct Opts {
     /// Scheduling slice duration for under-utilized hosts, in microseconds.
     #[clap(short = 'u', long, default_value = "20000")]
@@ -183,8 +192,22 @@ struct Opts {
     #[clap(long, default_value = "0")]
     exit_dump_len: u32,
 
+    /// Load options from a JSON or TOML config file, e.g. {"fifo_sched":
+    /// true}. Options specified on the command line take precedence. The
+    /// scheduler restarts with the new options when the file is modified.
+    #[clap(long)]
+    #[serde(skip)]
+    config: Option<String>,
+
+    /// Confine the scheduler with landlock and a dangerous syscall denylist
+    /// once it's attached. Only /proc, /sys and the config file stay
+    /// readable. Once applied, the sandbox can't be lifted by reloading the
+    /// config file.
+    #[clap(long, action = clap::ArgAction::SetTrue)]
+    sandbox: bool,
+
     /// Enable verbose output including libbpf details. Specify multiple
-    /// times to increase verbosity.
+    /// times to increase verbosity. The log level is only set at startup.
     #[clap(short = 'v', long, action = clap::ArgAction::Count)]
     verbose: u8,
 }
@@ -222,6 +245,7 @@ struct Scheduler<'a> {
 
     prev_at: Instant,
     prev_total_cpu: procfs::CpuStat,
+    prev_bpf_stats: PerCpuArraySnapshot<u64>,
 
     nr_lb_data_errors: u64,
 
@@ -326,6 +350,7 @@ impl<'a> Scheduler<'a> {
         // Other stuff.
         let proc_reader = procfs::ProcReader::new();
         let prev_total_cpu = read_total_cpu(&proc_reader)?;
+        let prev_bpf_stats = PerCpuArraySnapshot::read(skel.maps().stats())?;
 
         Ok(Self {
             skel,
@@ -342,14 +367,15 @@ impl<'a> Scheduler<'a> {
 
             prev_at: Instant::now(),
             prev_total_cpu,
+            prev_bpf_stats,
 
             nr_lb_data_errors: 0,
 
             tuner: Tuner::new(domains,
                               opts.direct_greedy_under,
                               opts.kick_greedy_under,
-                              opts.slice_us_underutil * 1000,
-                              opts.slice_us_overutil * 1000,)?,
+                              time::us_to_ns(opts.slice_us_underutil),
+                              time::us_to_ns(opts.slice_us_overutil),)?,
         })
     }
 
@@ -406,33 +432,14 @@ impl<'a> Scheduler<'a> {
     }
 
     fn read_bpf_stats(&mut self) -> Result<Vec<u64>> {
-        let mut maps = self.skel.maps_mut();
-        let stats_map = maps.stats();
-        let mut stats: Vec<u64> = Vec::new();
-        let zero_vec =
-            vec![vec![0u8; stats_map.value_size() as usize]; self.top.nr_cpus_possible()];
-
-        for stat in 0..bpf_intf::stat_idx_RUSTY_NR_STATS {
-            let cpu_stat_vec = stats_map
-                .lookup_percpu(&stat.to_ne_bytes(), libbpf_rs::MapFlags::ANY)
-                .with_context(|| format!("Failed to lookup stat {}", stat))?
-                .expect("per-cpu stat should exist");
-            let sum = cpu_stat_vec
-                .iter()
-                .map(|val| {
-                    u64::from_ne_bytes(
-                        val.as_slice()
-                            .try_into()
-                            .expect("Invalid value length in stat map"),
-                    )
-                })
-                .sum();
-            stats_map
-                .update_percpu(&stat.to_ne_bytes(), &zero_vec, libbpf_rs::MapFlags::ANY)
-                .context("Failed to zero stat")?;
-            stats.push(sum);
-        }
-        Ok(stats)
+        let cur = PerCpuArraySnapshot::<u64>::read(self.skel.maps().stats())
+            .context("Failed to read stats")?;
+        let delta = cur.delta(&self.prev_bpf_stats);
+        self.prev_bpf_stats = cur;
+
+        Ok((0..bpf_intf::stat_idx_RUSTY_NR_STATS as usize)
+            .map(|stat| delta.sum(stat))
+            .collect())
     }
 
     fn report(
@@ -542,7 +549,11 @@ impl<'a> Scheduler<'a> {
         Ok(())
     }
 
-    fn run(&mut self, shutdown: Arc<AtomicBool>) -> Result<UserExitInfo> {
+    fn run(
+        &mut self,
+        shutdown: Arc<AtomicBool>,
+        mut config: Option<&mut ConfigFile<Opts>>,
+    ) -> Result<UserExitInfo> {
         let now = Instant::now();
         let mut next_tune_at = now + self.tune_interval;
         let mut next_sched_at = now + self.sched_interval;
@@ -566,6 +577,17 @@ impl<'a> Scheduler<'a> {
                 }
             }
 
+            if let Some(config) = config.as_deref_mut() {
+                match config.reload() {
+                    Ok(true) => {
+                        info!("Config file {:?} updated, restarting", config.path());
+                        break;
+                    }
+                    Ok(false) => (),
+                    Err(e) => warn!("{:#}", e),
+                }
+            }
+
             std::thread::sleep(
                 next_sched_at
                     .min(next_tune_at)
@@ -588,21 +610,33 @@ impl<'a> Drop for Scheduler<'a> {
     }
 }
 
-fn main() -> Result<()> {
-    let opts = Opts::parse();
-
-    let llv = match opts.verbose {
+fn log_level(verbose: u8) -> simplelog::LevelFilter {
+    match verbose {
         0 => simplelog::LevelFilter::Info,
         1 => simplelog::LevelFilter::Debug,
         _ => simplelog::LevelFilter::Trace,
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = Opts::command().get_matches();
+    let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+
+    let mut config = match opts.config.as_ref() {
+        Some(path) => Some(ConfigFile::new(path, opts.clone(), &matches)?),
+        None => None,
     };
+    if let Some(config) = config.as_ref() {
+        opts = config.get().clone();
+    }
+
     let mut lcfg = simplelog::ConfigBuilder::new();
     lcfg.set_time_level(simplelog::LevelFilter::Error)
         .set_location_level(simplelog::LevelFilter::Off)
         .set_target_level(simplelog::LevelFilter::Off)
         .set_thread_level(simplelog::LevelFilter::Off);
     simplelog::TermLogger::init(
-        llv,
+        log_level(opts.verbose),
         lcfg.build(),
         simplelog::TerminalMode::Stderr,
         simplelog::ColorChoice::Auto,
@@ -615,10 +649,32 @@ fn main() -> Result<()> {
     })
     .context("Error setting Ctrl-C handler")?;
 
+    let mut sandboxed = false;
     while !shutdown.load(Ordering::Relaxed) {
+        if let Some(config) = config.as_ref() {
+            opts = config.get().clone();
+        }
+        let config_gen = config.as_ref().map(|config| config.generation());
+
+        // On config reloads and CPU hotplug, the scheduler is re-initialized
+        // inside the sandbox. This works as init only reads /proc and /sys
+        // and the BPF object is embedded in the binary. Anything new init
+        // needs to access must be allowed below.
         let mut sched = Scheduler::init(&opts)?;
 
-        let uei = sched.run(shutdown.clone())?;
+        if opts.sandbox && !sandboxed {
+            let mut sandbox = Sandbox::new();
+            if let Some(config) = config.as_ref() {
+                sandbox = sandbox.allow_read(config.path());
+            }
+            sandbox.apply()?;
+            sandboxed = true;
+        }
+
+        let uei = sched.run(shutdown.clone(), config.as_mut())?;
+        if config.as_ref().map(|config| config.generation()) != config_gen {
+            continue;
+        }
         if let Some(exit_code) = uei.exit_code() {
             if exit_code == bpf_intf::rusty_exit_codes_RUSTY_EXIT_HOTPLUG as i64 {
                 continue;
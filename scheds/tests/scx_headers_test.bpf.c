@@ -0,0 +1,87 @@
+/* SPDX-License-Identifier: GPL-2.0 */
+/*
+ * Build test for the shared BPF headers in include/scx which none of the
+ * schedulers in this tree include yet. Each header is included and its
+ * helpers are called from a minimal vtime scheduler so that the build
+ * breaks when one of them stops compiling. The object is never loaded.
+ *
+ * Copyright (c) 2024 Meta Platforms, Inc. and affiliates.
+ */
+#include <scx/common.bpf.h>
+#include <bpf/bpf_core_read.h>
+#include <scx/dsq_dump.bpf.h>
+#include <scx/events.bpf.h>
+#include <scx/idle.bpf.h>
+#include <scx/nice.bpf.h>
+
+char _license[] SEC("license") = "GPL";
+
+static u64 vtime_now;
+UEI_DEFINE(uei);
+
+/* a single LLC whose ID doubles as the ID of its DSQ */
+#define LLC_ID 0
+
+s32 BPF_STRUCT_OPS(headers_select_cpu, struct task_struct *p, s32 prev_cpu, u64 wake_flags)
+{
+	s32 cpu;
+
+	cpu = scx_idle_pick_cpu(p, prev_cpu, NULL,
+				SCX_IDLE_PREV_STICKY | SCX_IDLE_SMT_FIRST);
+	if (cpu < 0)
+		return prev_cpu;
+
+	scx_bpf_dispatch(p, SCX_DSQ_LOCAL, SCX_SLICE_DFL, 0);
+	return cpu;
+}
+
+void BPF_STRUCT_OPS(headers_enqueue, struct task_struct *p, u64 enq_flags)
+{
+	u64 vtime = scx_vtime_clamp(p->scx.dsq_vtime, vtime_now, SCX_SLICE_DFL);
+
+	scx_bpf_dispatch_vtime(p, LLC_ID, SCX_SLICE_DFL, vtime, enq_flags);
+}
+
+void BPF_STRUCT_OPS(headers_dispatch, s32 cpu, struct task_struct *prev)
+{
+	scx_bpf_consume(LLC_ID);
+}
+
+void BPF_STRUCT_OPS(headers_running, struct task_struct *p)
+{
+	if ((s64)(vtime_now - p->scx.dsq_vtime) < 0)
+		vtime_now = p->scx.dsq_vtime;
+}
+
+void BPF_STRUCT_OPS(headers_stopping, struct task_struct *p, bool runnable)
+{
+	p->scx.dsq_vtime += scx_vtime_delta(SCX_SLICE_DFL - p->scx.slice,
+					    p->scx.weight);
+}
+
+void BPF_STRUCT_OPS(headers_enable, struct task_struct *p)
+{
+	/* tasks with a lower latency nice start closer to the front */
+	p->scx.dsq_vtime = scx_task_deadline(p, vtime_now, SCX_SLICE_DFL);
+}
+
+s32 BPF_STRUCT_OPS_SLEEPABLE(headers_init)
+{
+	return scx_bpf_create_dsq(LLC_ID, -1);
+}
+
+void BPF_STRUCT_OPS(headers_exit, struct scx_exit_info *ei)
+{
+	UEI_RECORD(uei, ei);
+}
+
+SCX_OPS_DEFINE(headers_ops,
+	       .select_cpu		= (void *)headers_select_cpu,
+	       .enqueue			= (void *)headers_enqueue,
+	       .dispatch		= (void *)headers_dispatch,
+	       .running			= (void *)headers_running,
+	       .stopping		= (void *)headers_stopping,
+	       .enable			= (void *)headers_enable,
+	       .init			= (void *)headers_init,
+	       .exit			= (void *)headers_exit,
+	       .name			= "headers_test");